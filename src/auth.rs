@@ -0,0 +1,274 @@
+use axum::http::{HeaderMap, Method, header};
+use sha2::{Digest, Sha256};
+
+use crate::config::AuthConfig;
+use crate::database::{self, AclPermission, TokenScope};
+use crate::error::HandleRequestError;
+use crate::oidc;
+
+/// The identity of an authenticated request, as recorded in the audit log.
+#[derive(Clone)]
+pub struct Identity(pub String);
+
+/// The identity of the token configured with `ADMIN_TOKEN`.
+const ADMIN_IDENTITY: &str = "admin";
+
+/// Who a request is made by, as far as ACLs are concerned.
+#[derive(Clone)]
+pub enum Principal {
+    /// Authentication is disabled, or the route is public.
+    Unrestricted,
+    Anonymous,
+    Token {
+        id: String,
+        /// Tokens with admin scope aren't restricted by ACLs.
+        admin: bool,
+    },
+}
+
+impl Principal {
+    /// The identity recorded in the audit log.
+    pub fn identity(&self) -> Option<Identity> {
+        match self {
+            Principal::Token { id, .. } => Some(Identity(id.clone())),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, principal: &str) -> bool {
+        match self {
+            Principal::Unrestricted => true,
+            Principal::Anonymous => principal == "*",
+            Principal::Token { id, .. } => {
+                principal == "*" || principal == "authenticated" || principal == id
+            }
+        }
+    }
+
+    /// Whether ACLs allow this principal `permission` on a repository. Only the most specific ACL
+    /// attached to the repository, its owner or its server applies. Without any, access is
+    /// limited by token scopes alone.
+    pub fn permitted(
+        &self,
+        db: &database::Database,
+        server: &String,
+        owner: &String,
+        repo: &String,
+        permission: AclPermission,
+    ) -> Result<bool, database::Error> {
+        if matches!(
+            self,
+            Principal::Unrestricted | Principal::Token { admin: true, .. }
+        ) {
+            return Ok(true);
+        }
+        for (owner, repo) in [(Some(owner), Some(repo)), (Some(owner), None), (None, None)] {
+            let target = database::AclTarget {
+                server,
+                owner,
+                repo,
+            };
+            if let Some(acl) = db.get_acl(target)? {
+                return Ok(acl.entries.iter().any(|entry| {
+                    self.matches(&entry.principal) && entry.permissions.contains(&permission)
+                }));
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The repository a request is about, as far as it's known.
+#[derive(Default, Debug, PartialEq)]
+pub struct Target {
+    pub server: Option<String>,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    pub commit: Option<String>,
+}
+
+/// Generate a new token, returning its id and the token to hand out.
+/// Only the hash of the secret part is stored.
+pub fn generate_token() -> (String, String) {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let secret = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let token = format!("{id}.{secret}");
+    (id, token)
+}
+
+/// The hex encoded SHA-256 hash of the secret part of `token`.
+pub fn hash_token(token: &str) -> String {
+    let secret = token.split_once('.').map_or(token, |(_, secret)| secret);
+    sha256_hex(secret)
+}
+
+fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+/// The scope a route needs, or `None` if it's public.
+pub fn required_scope(method: &Method, route: &str) -> Option<TokenScope> {
+    // pre-signing authorizes the request being signed instead
+    if matches!(route, "/" | "/robots.txt" | "/ping" | "/@presign") {
+        return None;
+    }
+    let admin = route == "/audit"
+        || route.starts_with("/@acls")
+        || route.starts_with("/@tokens")
+        || route.starts_with("/@webhooks")
+        || route.ends_with("/@webhooks");
+    if admin {
+        Some(TokenScope::Admin)
+    } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        Some(TokenScope::Read)
+    } else {
+        Some(TokenScope::Write)
+    }
+}
+
+/// The ACL permission a route needs on the repository it's about. Repository listings are
+/// filtered instead, and repository webhooks are for admins only.
+pub fn required_permission(method: &Method, route: &str) -> Option<AclPermission> {
+    match (method.as_str(), route) {
+        (_, "/{server}/{owner}/{repo}/@webhooks") => None,
+        ("GET" | "HEAD", "/{server}/{owner}/{repo}/{commit}/{*path}") => {
+            Some(AclPermission::Download)
+        }
+        ("POST", "/{server}/{owner}/{repo}/@channels/{channel}") => Some(AclPermission::Promote),
+        ("DELETE", "/@sessions/{session}") => Some(AclPermission::Delete),
+        ("GET" | "HEAD" | "OPTIONS", _) => Some(AclPermission::List),
+        _ => Some(AclPermission::Upload),
+    }
+}
+
+/// Check the ACL permission `route` needs on the repository of `target`, if it's about one.
+pub fn check_permission(
+    db: &database::Database,
+    principal: &Principal,
+    method: &Method,
+    route: &str,
+    target: &Target,
+) -> Result<(), HandleRequestError> {
+    let permission = required_permission(method, route);
+    if let (Some(permission), Some(server), Some(owner), Some(repo)) =
+        (permission, &target.server, &target.owner, &target.repo)
+        && !principal.permitted(db, server, owner, repo, permission)?
+    {
+        return Err(HandleRequestError::Forbidden(format!(
+            "{permission} is not permitted on {server}/{owner}/{repo}"
+        )));
+    }
+    Ok(())
+}
+
+/// Check the bearer token of a request against the scope it needs and the repository it's about.
+/// Returns the principal of the token, which is anonymous for an allowed anonymous read.
+pub async fn authorize(
+    db: &database::Database,
+    config: &AuthConfig,
+    oidc: Option<&oidc::Verifier>,
+    headers: &HeaderMap,
+    required: TokenScope,
+    target: &Target,
+) -> Result<Principal, HandleRequestError> {
+    let token = match headers.get(header::AUTHORIZATION) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| {
+                HandleRequestError::Unauthorized("invalid authorization header".to_string())
+            })?,
+        None if required == TokenScope::Read && config.anonymous_read => {
+            return Ok(Principal::Anonymous);
+        }
+        None => {
+            return Err(HandleRequestError::Unauthorized(
+                "missing bearer token".to_string(),
+            ));
+        }
+    };
+
+    if let Some(admin_token) = &config.admin_token
+        && sha256_hex(admin_token) == sha256_hex(token)
+    {
+        return Ok(Principal::Token {
+            id: ADMIN_IDENTITY.to_string(),
+            admin: true,
+        });
+    }
+
+    // our own tokens have a single dot, JWTs have two
+    if let Some(verifier) = oidc
+        && token.matches('.').count() == 2
+    {
+        let claims = verifier.verify(token).await?;
+        return authorize_oidc(claims, required, target);
+    }
+
+    let invalid = || HandleRequestError::Unauthorized("invalid token".to_string());
+    let (id, _) = token.split_once('.').ok_or_else(invalid)?;
+    let data = db.get_token(&id.to_string())?.ok_or_else(invalid)?;
+    if data.hash != hash_token(token) {
+        return Err(invalid());
+    }
+
+    if !data.scopes.iter().any(|scope| *scope >= required) {
+        return Err(HandleRequestError::Forbidden(format!(
+            "token {} lacks {required} scope",
+            data.id
+        )));
+    }
+    let allowed = [
+        (&data.server, &target.server),
+        (&data.owner, &target.owner),
+        (&data.repo, &target.repo),
+    ]
+    .iter()
+    .all(|(restriction, target)| restriction.is_none() || restriction == target);
+    if !allowed {
+        return Err(HandleRequestError::Forbidden(format!(
+            "token {} can't access this repository",
+            data.id
+        )));
+    }
+
+    Ok(Principal::Token {
+        admin: data.scopes.contains(&TokenScope::Admin),
+        id: data.id,
+    })
+}
+
+/// A CI job may read its own repository, and write to its own commit only.
+fn authorize_oidc(
+    claims: oidc::Claims,
+    required: TokenScope,
+    target: &Target,
+) -> Result<Principal, HandleRequestError> {
+    let repository = format!("{}/{}/{}", claims.server, claims.owner, claims.repo);
+    if required == TokenScope::Admin {
+        return Err(HandleRequestError::Forbidden(format!(
+            "oidc token of {repository} lacks admin scope"
+        )));
+    }
+    let allowed = target.server.as_ref() == Some(&claims.server)
+        && target.owner.as_ref() == Some(&claims.owner)
+        && target.repo.as_ref() == Some(&claims.repo)
+        && (required == TokenScope::Read || target.commit.as_ref() == Some(&claims.commit));
+    if !allowed {
+        return Err(HandleRequestError::Forbidden(format!(
+            "oidc token of {repository} at {} can't access this",
+            claims.commit
+        )));
+    }
+
+    Ok(Principal::Token {
+        id: format!("oidc:{}", claims.subject),
+        admin: false,
+    })
+}
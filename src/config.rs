@@ -1,29 +1,812 @@
-use std::env::var;
+use std::{collections::HashMap, env::var, fmt, net::SocketAddr, time::Duration};
 
+/// Settings are read from defaults, then the config file, then environment variables,
+/// then command-line flags, each overriding the ones before.
 pub struct Config {
+    /// Where connections are accepted, from `LISTEN`, default to `0.0.0.0:3001`.
+    pub listeners: Vec<ListenerConfig>,
+    pub log: LogConfig,
     /// The path to the rocksdb database, default to $DATA_PATH/rocksdb.
     pub rocksdb_path: String,
     /// The path to the artifacts directory, default to $DATA_PATH/artifacts.
     pub artifact_path: String,
+    pub timeouts: TimeoutsConfig,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    /// Serve HTTPS instead of HTTP, if `TLS_CERT_FILE` and `TLS_KEY_FILE` are set.
+    pub tls: Option<TlsConfig>,
 }
 
-pub fn load() -> Config {
-    let data_path = match var("DATA_PATH") {
-        Ok(dir) => dir,
-        Err(_) => "/data".to_string(),
-    };
-    let rocksdb_path = match var("ROCKSDB_PATH") {
-        Ok(dir) => dir,
-        Err(_) => format!("{data_path}/rocksdb").to_string(),
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeoutsConfig {
+    /// How long a request other than an upload may take, from `TIMEOUT_SECONDS`,
+    /// default to 10 seconds.
+    pub request: Duration,
+    /// How long an upload may go without receiving any data, from `UPLOAD_IDLE_TIMEOUT_SECONDS`,
+    /// default to 30 seconds. Uploads have no overall deadline, so large files can take their time.
+    pub upload_idle: Duration,
+    /// How long a client may take to send the headers of a request, from
+    /// `HEADER_READ_TIMEOUT_SECONDS`, default to 10 seconds.
+    pub header_read: Duration,
+    /// How long an upload session may stay open before it's discarded with everything uploaded
+    /// into it, from `SESSION_TTL_SECONDS`, default to a day.
+    pub session_ttl: Duration,
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        TimeoutsConfig {
+            request: Duration::from_secs(10),
+            upload_idle: Duration::from_secs(30),
+            header_read: Duration::from_secs(10),
+            session_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenerConfig {
+    /// A TCP address, such as `0.0.0.0:3001`. IPv6 addresses accept IPv4 connections too,
+    /// so `[::]:3001` listens on both.
+    Tcp(SocketAddr),
+    /// A Unix domain socket, from `unix:{path}`, with permissions from `UNIX_SOCKET_MODE`.
+    Unix { path: String, mode: Option<u32> },
+    /// The sockets passed by systemd socket activation in `LISTEN_FDS`, from `systemd`.
+    Systemd,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+    /// From `LOG_FORMAT`, default to `json`.
+    pub format: LogFormat,
+    /// The most verbose level logged, from `LOG_LEVEL`, default to `info`.
+    pub level: tracing::Level,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Json,
+    Text,
+}
+
+#[derive(Clone)]
+pub struct TlsConfig {
+    /// The PEM encoded certificate chain, from `TLS_CERT_FILE`.
+    pub cert_path: String,
+    /// The PEM encoded private key, from `TLS_KEY_FILE`.
+    pub key_path: String,
+    /// The PEM encoded CAs client certificates are verified against, from `TLS_CLIENT_CA_FILE`.
+    /// Client certificates aren't requested if `None`.
+    pub client_ca_path: Option<String>,
+    /// Whether clients may connect without a certificate, from `TLS_CLIENT_AUTH_OPTIONAL`,
+    /// default to false.
+    pub client_auth_optional: bool,
+}
+
+/// Limits applied to each client IP and each identity separately. All are disabled if unset.
+#[derive(Clone, Default)]
+pub struct LimitsConfig {
+    /// Requests per second, from `RATE_LIMIT_REQUESTS` and `RATE_LIMIT_REQUESTS_BURST`.
+    pub requests: Option<Rate>,
+    /// Bytes uploaded and downloaded per second, from `RATE_LIMIT_BYTES` and `RATE_LIMIT_BYTES_BURST`.
+    pub bytes: Option<Rate>,
+    /// Uploads in progress at once across all clients, from `MAX_CONCURRENT_UPLOADS`.
+    pub max_concurrent_uploads: Option<usize>,
+    /// The largest artifact in bytes, from `MAX_UPLOAD_SIZE`.
+    pub max_upload_size: Option<u64>,
+    /// Overrides of `max_upload_size` keyed by `{server}/{owner}/{repo}`,
+    /// from `REPO_MAX_UPLOAD_SIZES`.
+    pub repo_max_upload_sizes: HashMap<String, u64>,
+}
+
+/// A token bucket refilled at `per_second`, holding at most `burst`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    /// Default to a second's worth, and at least 1.
+    pub burst: f64,
+}
+
+#[derive(Clone, Default)]
+pub struct AuthConfig {
+    /// Whether requests need a bearer token, default to false.
+    pub enabled: bool,
+    /// Whether read requests are allowed without a token, default to true.
+    pub anonymous_read: bool,
+    /// A token with admin scope that always exists, used to create the first tokens.
+    pub admin_token: Option<String>,
+    /// Accept OIDC ID tokens issued to CI jobs, if `OIDC_ISSUER` is set.
+    pub oidc: Option<OidcConfig>,
+    /// The key signing pre-signed URLs, which are disabled if `None`.
+    pub presign_secret: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct OidcConfig {
+    /// The expected `iss` claim.
+    pub issuer: String,
+    /// The expected `aud` claim.
+    pub audience: String,
+    /// Where the keys signing the tokens are read from.
+    pub jwks: JwksSource,
+    /// The `{server}` the repositories of the issuer are on, such as `github.com`.
+    pub server: String,
+    /// The claim holding `{owner}/{repo}`, default to `repository`.
+    pub repository_claim: String,
+    /// The claim holding the commit, default to `sha`.
+    pub commit_claim: String,
+}
+
+#[derive(Clone)]
+pub enum JwksSource {
+    /// A local JWKS file, from `OIDC_JWKS_FILE`.
+    File(String),
+    /// A JWKS URL, from `OIDC_JWKS_URL`, fetched and cached.
+    Url(String),
+}
+
+/// What the command line asks for.
+pub enum Command {
+    Serve(Box<Config>),
+    /// The effective settings and their sources, from `--print-config`.
+    PrintConfig(String),
+    /// The usage, from `--help`.
+    Help(String),
+}
+
+/// Every problem found in the settings, reported together.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// How a setting is written in the config file.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Bool,
+    Number,
+    /// A string of comma separated values, or an array of strings.
+    List,
+}
+
+/// A setting, by its key in the config file, its environment variable,
+/// and its flag, derived from the environment variable.
+struct Setting {
+    key: &'static str,
+    env: &'static str,
+    kind: Kind,
+    /// Not shown by `--print-config`.
+    secret: bool,
+}
+
+impl Setting {
+    const fn new(key: &'static str, env: &'static str, kind: Kind) -> Self {
+        Setting {
+            key,
+            env,
+            kind,
+            secret: false,
+        }
+    }
+
+    const fn secret(self) -> Self {
+        Setting {
+            secret: true,
+            ..self
+        }
+    }
+
+    fn flag(&self) -> String {
+        format!("--{}", self.env.to_lowercase().replace('_', "-"))
+    }
+}
+
+/// The config file, if `--config` isn't given.
+const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
+
+const SETTINGS: &[Setting] = &[
+    Setting::new("listen", "LISTEN", Kind::List),
+    Setting::new("unix_socket_mode", "UNIX_SOCKET_MODE", Kind::String),
+    Setting::new("log.format", "LOG_FORMAT", Kind::String),
+    Setting::new("log.level", "LOG_LEVEL", Kind::String),
+    Setting::new("data_path", "DATA_PATH", Kind::String),
+    Setting::new("rocksdb_path", "ROCKSDB_PATH", Kind::String),
+    Setting::new("artifacts_path", "ARTIFACTS_PATH", Kind::String),
+    Setting::new("timeout_seconds", "TIMEOUT_SECONDS", Kind::Number),
+    Setting::new(
+        "upload_idle_timeout_seconds",
+        "UPLOAD_IDLE_TIMEOUT_SECONDS",
+        Kind::Number,
+    ),
+    Setting::new(
+        "header_read_timeout_seconds",
+        "HEADER_READ_TIMEOUT_SECONDS",
+        Kind::Number,
+    ),
+    Setting::new("session_ttl_seconds", "SESSION_TTL_SECONDS", Kind::Number),
+    Setting::new("auth.enabled", "AUTH_ENABLED", Kind::Bool),
+    Setting::new("auth.anonymous_read", "ANONYMOUS_READ", Kind::Bool),
+    Setting::new("auth.admin_token", "ADMIN_TOKEN", Kind::String).secret(),
+    Setting::new("auth.presign_secret", "PRESIGN_SECRET", Kind::String).secret(),
+    Setting::new("oidc.issuer", "OIDC_ISSUER", Kind::String),
+    Setting::new("oidc.audience", "OIDC_AUDIENCE", Kind::String),
+    Setting::new("oidc.jwks_file", "OIDC_JWKS_FILE", Kind::String),
+    Setting::new("oidc.jwks_url", "OIDC_JWKS_URL", Kind::String),
+    Setting::new("oidc.server", "OIDC_SERVER", Kind::String),
+    Setting::new(
+        "oidc.repository_claim",
+        "OIDC_REPOSITORY_CLAIM",
+        Kind::String,
+    ),
+    Setting::new("oidc.commit_claim", "OIDC_COMMIT_CLAIM", Kind::String),
+    Setting::new("limits.requests", "RATE_LIMIT_REQUESTS", Kind::Number),
+    Setting::new(
+        "limits.requests_burst",
+        "RATE_LIMIT_REQUESTS_BURST",
+        Kind::Number,
+    ),
+    Setting::new("limits.bytes", "RATE_LIMIT_BYTES", Kind::Number),
+    Setting::new("limits.bytes_burst", "RATE_LIMIT_BYTES_BURST", Kind::Number),
+    Setting::new(
+        "limits.max_concurrent_uploads",
+        "MAX_CONCURRENT_UPLOADS",
+        Kind::Number,
+    ),
+    Setting::new("limits.max_upload_size", "MAX_UPLOAD_SIZE", Kind::Number),
+    Setting::new(
+        "limits.repo_max_upload_sizes",
+        "REPO_MAX_UPLOAD_SIZES",
+        Kind::List,
+    ),
+    Setting::new("tls.cert_file", "TLS_CERT_FILE", Kind::String),
+    Setting::new("tls.key_file", "TLS_KEY_FILE", Kind::String),
+    Setting::new("tls.client_ca_file", "TLS_CLIENT_CA_FILE", Kind::String),
+    Setting::new(
+        "tls.client_auth_optional",
+        "TLS_CLIENT_AUTH_OPTIONAL",
+        Kind::Bool,
+    ),
+];
+
+/// Where the value of a setting comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(String),
+    Env(&'static str),
+    Flag(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {path}"),
+            Source::Env(name) => write!(f, "env {name}"),
+            Source::Flag(flag) => write!(f, "flag {flag}"),
+        }
+    }
+}
+
+/// Read the settings, with `args` being the command-line arguments without the program name.
+pub fn load(args: impl IntoIterator<Item = String>) -> Result<Command, ConfigError> {
+    let mut errors = vec![];
+    let mut values = HashMap::new();
+
+    let args = parse_args(args, &mut errors);
+    if args.help {
+        return Ok(Command::Help(usage()));
+    }
+    if let Some(path) = args.config_file.or_else(|| env_var(CONFIG_FILE_ENV)) {
+        read_file(&path, &mut values, &mut errors);
+    }
+    for setting in SETTINGS {
+        if let Some(value) = env_var(setting.env) {
+            values.insert(setting.key, (value, Source::Env(setting.env)));
+        }
+    }
+    values.extend(args.values);
+
+    let mut parser = Parser {
+        values,
+        effective: HashMap::new(),
+        errors,
     };
-    let artifact_path = match var("ARTIFACTS_PATH") {
-        Ok(dir) => dir,
-        Err(_) => format!("{data_path}/artifacts").to_string(),
+    let config = parser.config();
+    if !parser.errors.is_empty() {
+        return Err(ConfigError(parser.errors));
+    }
+    match args.print_config {
+        true => Ok(Command::PrintConfig(parser.describe())),
+        false => Ok(Command::Serve(Box::new(config))),
+    }
+}
+
+/// An environment variable, where empty is the same as unset.
+fn env_var(name: &str) -> Option<String> {
+    var(name).ok().filter(|value| !value.is_empty())
+}
+
+#[derive(Default)]
+struct Args {
+    config_file: Option<String>,
+    print_config: bool,
+    help: bool,
+    values: HashMap<&'static str, (String, Source)>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>, errors: &mut Vec<String>) -> Args {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let setting = SETTINGS.iter().find(|setting| setting.flag() == flag);
+        // boolean flags may be given without a value
+        let takes_value = flag == "--config" || setting.is_some_and(|s| s.kind != Kind::Bool);
+        let value = match inline {
+            Some(value) => Some(value),
+            None if takes_value => args.next_if(|next| !next.starts_with("--")),
+            None => None,
+        };
+        if takes_value && value.is_none() {
+            errors.push(format!("{flag} needs a value"));
+            continue;
+        }
+
+        match (flag.as_str(), setting) {
+            ("-h" | "--help", _) => parsed.help = true,
+            ("--print-config", _) => parsed.print_config = true,
+            ("--config", _) => parsed.config_file = value,
+            (_, Some(setting)) => {
+                let value = value.unwrap_or("true".to_string());
+                parsed
+                    .values
+                    .insert(setting.key, (value, Source::Flag(flag)));
+            }
+            (_, None) => errors.push(format!("unknown argument {flag}")),
+        }
+    }
+    parsed
+}
+
+fn usage() -> String {
+    let mut usage = "Usage: artifact-store [OPTIONS]\n\nOptions:\n".to_string();
+    let options = [
+        (
+            "--config <FILE>".to_string(),
+            format!("read settings from a TOML file, or from {CONFIG_FILE_ENV}"),
+        ),
+        (
+            "--print-config".to_string(),
+            "print the effective settings and their sources, then exit".to_string(),
+        ),
+        ("-h, --help".to_string(), "print this help".to_string()),
+    ];
+    let settings: Vec<(String, String)> = SETTINGS
+        .iter()
+        .map(|setting| {
+            let flag = match setting.kind {
+                Kind::Bool => format!("{}[=<BOOL>]", setting.flag()),
+                _ => format!("{} <VALUE>", setting.flag()),
+            };
+            (
+                flag,
+                format!("{}, or {} in the file", setting.env, setting.key),
+            )
+        })
+        .collect();
+    let width = options
+        .iter()
+        .chain(&settings)
+        .map(|(flag, _)| flag.len())
+        .max();
+    let width = width.unwrap_or_default() + 2;
+
+    for (flag, help) in &options {
+        usage.push_str(&format!("  {flag:width$}{help}\n"));
+    }
+    usage.push_str("\nSettings, overriding the environment variables, which override the file:\n");
+    for (flag, help) in &settings {
+        usage.push_str(&format!("  {flag:width$}{help}\n"));
+    }
+    usage
+}
+
+fn read_file(
+    path: &String,
+    values: &mut HashMap<&'static str, (String, Source)>,
+    errors: &mut Vec<String>,
+) {
+    let table = match std::fs::read_to_string(path) {
+        Ok(content) => content.parse::<toml::Table>(),
+        Err(e) => {
+            errors.push(format!("failed to read {path}: {e}"));
+            return;
+        }
     };
+    match table {
+        Ok(table) => read_table(path, "", table, values, errors),
+        Err(e) => errors.push(format!("failed to parse {path}: {e}")),
+    }
+}
 
-    Config {
-        rocksdb_path,
-        artifact_path,
+fn read_table(
+    path: &String,
+    prefix: &str,
+    table: toml::Table,
+    values: &mut HashMap<&'static str, (String, Source)>,
+    errors: &mut Vec<String>,
+) {
+    for (name, value) in table {
+        let key = format!("{prefix}{name}");
+        if let toml::Value::Table(table) = value {
+            read_table(path, &format!("{key}."), table, values, errors);
+            continue;
+        }
+        let Some(setting) = SETTINGS.iter().find(|setting| setting.key == key) else {
+            errors.push(format!("unknown setting {key} in {path}"));
+            continue;
+        };
+        let value = match (setting.kind, value) {
+            (Kind::String | Kind::List, toml::Value::String(value)) => value,
+            (Kind::Bool, toml::Value::Boolean(value)) => value.to_string(),
+            (Kind::Number, toml::Value::Integer(value)) => value.to_string(),
+            (Kind::Number, toml::Value::Float(value)) => value.to_string(),
+            (Kind::List, toml::Value::Array(items)) => {
+                let items: Option<Vec<&str>> = items.iter().map(toml::Value::as_str).collect();
+                match items {
+                    Some(items) => items.join(","),
+                    None => {
+                        errors.push(format!("{key} in {path} must be strings"));
+                        continue;
+                    }
+                }
+            }
+            (kind, _) => {
+                let expected = match kind {
+                    Kind::String => "a string",
+                    Kind::Bool => "a boolean",
+                    Kind::Number => "a number",
+                    Kind::List => "a string or an array of strings",
+                };
+                errors.push(format!("{key} in {path} must be {expected}"));
+                continue;
+            }
+        };
+        values.insert(setting.key, (value, Source::File(path.clone())));
+    }
+}
+
+/// Turns the layered values into a `Config`, collecting errors instead of stopping at the first,
+/// and remembering the effective value of each setting.
+struct Parser {
+    values: HashMap<&'static str, (String, Source)>,
+    effective: HashMap<&'static str, (String, Source)>,
+    errors: Vec<String>,
+}
+
+impl Parser {
+    fn config(&mut self) -> Config {
+        let data_path = self.string_or("data_path", "/data".to_string());
+        let rocksdb_path = self.string_or("rocksdb_path", format!("{data_path}/rocksdb"));
+        let artifact_path = self.string_or("artifacts_path", format!("{data_path}/artifacts"));
+
+        let auth = AuthConfig {
+            enabled: self.bool("auth.enabled", false),
+            anonymous_read: self.bool("auth.anonymous_read", true),
+            admin_token: self.string("auth.admin_token"),
+            oidc: self.oidc(),
+            presign_secret: self.string("auth.presign_secret"),
+        };
+
+        let limits = LimitsConfig {
+            requests: self.rate("limits.requests"),
+            bytes: self.rate("limits.bytes"),
+            max_concurrent_uploads: self.number("limits.max_concurrent_uploads"),
+            max_upload_size: self.number("limits.max_upload_size"),
+            repo_max_upload_sizes: self.repo_max_upload_sizes(),
+        };
+
+        Config {
+            listeners: self.listeners(),
+            log: self.log(),
+            rocksdb_path,
+            artifact_path,
+            timeouts: self.timeouts(),
+            auth,
+            limits,
+            tls: self.tls(),
+        }
+    }
+
+    /// The effective settings as TOML, with their sources as comments.
+    fn describe(&self) -> String {
+        let lines: Vec<(String, &Source)> = SETTINGS
+            .iter()
+            .filter_map(|setting| {
+                let (value, source) = self.effective.get(setting.key)?;
+                let value = match (setting.secret, setting.kind) {
+                    (true, _) => toml::Value::from("<redacted>"),
+                    (false, Kind::Bool) => toml::Value::from(value == "true"),
+                    (false, Kind::Number) => match value.parse::<i64>() {
+                        Ok(number) => toml::Value::from(number),
+                        Err(_) => toml::Value::from(value.parse::<f64>().unwrap_or_default()),
+                    },
+                    (false, Kind::List) => toml::Value::from(value.split(',').collect::<Vec<_>>()),
+                    (false, Kind::String) => toml::Value::from(value.as_str()),
+                };
+                Some((format!("{} = {value}", setting.key), source))
+            })
+            .collect();
+        let width = lines.iter().map(|(line, _)| line.len()).max();
+        let width = width.unwrap_or_default() + 2;
+        lines
+            .iter()
+            .map(|(line, source)| format!("{line:width$}# {source}\n"))
+            .collect()
+    }
+
+    fn get(&mut self, key: &'static str) -> Option<(String, Source)> {
+        let value = self.values.get(key).cloned()?;
+        self.effective.insert(key, value.clone());
+        Some(value)
+    }
+
+    fn default<T: ToString>(&mut self, key: &'static str, value: T) -> T {
+        self.effective
+            .insert(key, (value.to_string(), Source::Default));
+        value
+    }
+
+    fn invalid(&mut self, key: &str, value: &str, source: &Source) {
+        self.errors
+            .push(format!("invalid value for {key} from {source}: {value}"));
+    }
+
+    fn string(&mut self, key: &'static str) -> Option<String> {
+        self.get(key).map(|(value, _)| value)
+    }
+
+    fn string_or(&mut self, key: &'static str, default: String) -> String {
+        match self.string(key) {
+            Some(value) => value,
+            None => self.default(key, default),
+        }
+    }
+
+    /// Reads `true`/`1` or `false`/`0`, or `default` if unset.
+    fn bool(&mut self, key: &'static str, default: bool) -> bool {
+        match self.get(key) {
+            Some((value, source)) => match value.as_str() {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => {
+                    self.invalid(key, &value, &source);
+                    default
+                }
+            },
+            None => self.default(key, default),
+        }
+    }
+
+    /// Reads a positive, finite number, or `None` if unset.
+    fn number<T: std::str::FromStr + PartialOrd + Default>(
+        &mut self,
+        key: &'static str,
+    ) -> Option<T> {
+        let (value, source) = self.get(key)?;
+        let finite = value.parse::<f64>().is_ok_and(f64::is_finite);
+        match value.parse::<T>() {
+            Ok(number) if finite && number > T::default() => Some(number),
+            _ => {
+                self.invalid(key, &value, &source);
+                None
+            }
+        }
+    }
+
+    /// Reads a rate from `key`, and its burst from `{key}_burst`.
+    fn rate(&mut self, key: &'static str) -> Option<Rate> {
+        let burst_key = SETTINGS
+            .iter()
+            .find(|setting| setting.key.strip_suffix("_burst") == Some(key))
+            .map(|setting| setting.key)?;
+        let per_second: f64 = self.number(key)?;
+        let burst = match self.number(burst_key) {
+            Some(burst) => burst,
+            None => self.default(burst_key, per_second.max(1.0)),
+        };
+        Some(Rate { per_second, burst })
+    }
+
+    /// Reads a value that must be set because `reason` is.
+    fn required(&mut self, key: &'static str, reason: &str) -> String {
+        self.string(key).unwrap_or_else(|| {
+            self.errors
+                .push(format!("{key} is required when {reason} is set"));
+            String::new()
+        })
+    }
+
+    fn timeouts(&mut self) -> TimeoutsConfig {
+        let defaults = TimeoutsConfig::default();
+        TimeoutsConfig {
+            request: self.seconds("timeout_seconds", defaults.request),
+            upload_idle: self.seconds("upload_idle_timeout_seconds", defaults.upload_idle),
+            header_read: self.seconds("header_read_timeout_seconds", defaults.header_read),
+            session_ttl: self.seconds("session_ttl_seconds", defaults.session_ttl),
+        }
+    }
+
+    fn seconds(&mut self, key: &'static str, default: Duration) -> Duration {
+        match self.number(key).map(Duration::try_from_secs_f64) {
+            Some(Ok(seconds)) => return seconds,
+            // too long to be a duration
+            Some(Err(_)) => {
+                let (value, source) = self.values[key].clone();
+                self.invalid(key, &value, &source);
+            }
+            None => {}
+        }
+        Duration::from_secs_f64(self.default(key, default.as_secs_f64()))
+    }
+
+    /// Reads `{server}/{owner}/{repo}={bytes}` pairs.
+    fn repo_max_upload_sizes(&mut self) -> HashMap<String, u64> {
+        let key = "limits.repo_max_upload_sizes";
+        let Some((value, source)) = self.get(key) else {
+            return HashMap::new();
+        };
+        let mut sizes = HashMap::new();
+        for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let parsed = pair.split_once('=').and_then(|(repo, size)| {
+                let size = size.trim().parse::<u64>().ok().filter(|size| *size > 0)?;
+                let parts: Vec<&str> = repo.trim().split('/').collect();
+                (parts.len() == 3 && parts.iter().all(|part| !part.is_empty()))
+                    .then(|| (parts.join("/"), size))
+            });
+            match parsed {
+                Some((repo, size)) => {
+                    sizes.insert(repo, size);
+                }
+                None => self.invalid(key, pair, &source),
+            }
+        }
+        sizes
+    }
+
+    fn log(&mut self) -> LogConfig {
+        let format = match self.get("log.format") {
+            Some((value, source)) => match value.as_str() {
+                "json" => LogFormat::Json,
+                "text" => LogFormat::Text,
+                _ => {
+                    self.invalid("log.format", &value, &source);
+                    LogFormat::Json
+                }
+            },
+            None => {
+                self.default("log.format", "json");
+                LogFormat::Json
+            }
+        };
+        let level = match self.get("log.level") {
+            Some((value, source)) => value.parse().unwrap_or_else(|_| {
+                self.invalid("log.level", &value, &source);
+                tracing::Level::INFO
+            }),
+            None => {
+                self.default("log.level", "info");
+                tracing::Level::INFO
+            }
+        };
+        LogConfig { format, level }
+    }
+
+    fn listeners(&mut self) -> Vec<ListenerConfig> {
+        let mode =
+            self.get("unix_socket_mode").and_then(|(mode, source)| {
+                match u32::from_str_radix(&mode, 8) {
+                    Ok(mode) if mode <= 0o777 => Some(mode),
+                    _ => {
+                        self.invalid("unix_socket_mode", &mode, &source);
+                        None
+                    }
+                }
+            });
+        let (listen, source) = match self.get("listen") {
+            Some(listen) => listen,
+            None => (
+                self.default("listen", "0.0.0.0:3001".to_string()),
+                Source::Default,
+            ),
+        };
+        let mut listeners = vec![];
+        for address in listen.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            let listener = if address == "systemd" {
+                ListenerConfig::Systemd
+            } else if let Some(path) = address.strip_prefix("unix:") {
+                ListenerConfig::Unix {
+                    path: path.to_string(),
+                    mode,
+                }
+            } else {
+                match address.parse() {
+                    Ok(addr) => ListenerConfig::Tcp(addr),
+                    Err(_) => {
+                        self.invalid("listen", address, &source);
+                        continue;
+                    }
+                }
+            };
+            if !listeners.contains(&listener) {
+                listeners.push(listener);
+            }
+        }
+        if listeners.is_empty() {
+            self.errors
+                .push("listen must have at least one address".to_string());
+        }
+        listeners
+    }
+
+    fn tls(&mut self) -> Option<TlsConfig> {
+        let (cert_path, key_path) =
+            match (self.string("tls.cert_file"), self.string("tls.key_file")) {
+                (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+                (None, None) => return None,
+                _ => {
+                    self.errors
+                        .push("tls.cert_file and tls.key_file must be set together".to_string());
+                    return None;
+                }
+            };
+        let client_ca_path = self.string("tls.client_ca_file");
+        let client_auth_optional = self.bool("tls.client_auth_optional", false);
+        if client_auth_optional && client_ca_path.is_none() {
+            self.errors
+                .push("tls.client_auth_optional needs tls.client_ca_file".to_string());
+        }
+        Some(TlsConfig {
+            cert_path,
+            key_path,
+            client_ca_path,
+            client_auth_optional,
+        })
+    }
+
+    fn oidc(&mut self) -> Option<OidcConfig> {
+        let issuer = self.string("oidc.issuer")?;
+        let jwks = match (self.string("oidc.jwks_file"), self.string("oidc.jwks_url")) {
+            (Some(path), None) => JwksSource::File(path),
+            (None, Some(url)) => JwksSource::Url(url),
+            _ => {
+                self.errors.push(
+                    "exactly one of oidc.jwks_file and oidc.jwks_url is required".to_string(),
+                );
+                JwksSource::File(String::new())
+            }
+        };
+        Some(OidcConfig {
+            issuer,
+            audience: self.required("oidc.audience", "oidc.issuer"),
+            jwks,
+            server: self.required("oidc.server", "oidc.issuer"),
+            repository_claim: self.string_or("oidc.repository_claim", "repository".to_string()),
+            commit_claim: self.string_or("oidc.commit_claim", "sha".to_string()),
+        })
     }
 }
 
@@ -31,16 +814,69 @@ pub fn load() -> Config {
 mod tests {
     use super::*;
     use std::env::{remove_var, set_var};
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    /// Held by every test reading the environment, as it's shared by the whole process.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Lock the environment and unset every setting in it.
+    fn clear_env() -> MutexGuard<'static, ()> {
+        let guard = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        unsafe {
+            remove_var(CONFIG_FILE_ENV);
+            for setting in SETTINGS {
+                remove_var(setting.env);
+            }
+        }
+        guard
+    }
+
+    /// Load with `args`, expecting to serve.
+    fn serve(args: &[&str]) -> Config {
+        match load(args.iter().map(|arg| arg.to_string())) {
+            Ok(Command::Serve(config)) => *config,
+            Ok(_) => panic!("not serving"),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Load with `args`, expecting to print the config, and return its lines.
+    fn print_config(args: &[&str]) -> Vec<String> {
+        let args = ["--print-config"].iter().chain(args);
+        match load(args.map(|arg| arg.to_string())) {
+            Ok(Command::PrintConfig(output)) => output.lines().map(str::to_string).collect(),
+            Ok(_) => panic!("not printing config"),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Load with `args`, expecting errors.
+    fn errors(args: &[&str]) -> Vec<String> {
+        match load(args.iter().map(|arg| arg.to_string())) {
+            Err(ConfigError(errors)) => errors,
+            Ok(_) => panic!("invalid config loaded"),
+        }
+    }
+
+    /// Write a config file under `data/{name}`, returning its path.
+    fn config_file(name: &str, content: &str) -> String {
+        let path = format!("data/{name}");
+        std::fs::create_dir_all(&path).unwrap();
+        let file = format!("{path}/config.toml");
+        std::fs::write(&file, content).unwrap();
+        file
+    }
 
     #[test]
     fn load_config() {
+        let _env = clear_env();
         {
             unsafe {
                 remove_var("DATA_PATH");
                 remove_var("ROCKSDB_PATH");
                 remove_var("ARTIFACTS_PATH");
             }
-            let config = load();
+            let config = serve(&[]);
             assert_eq!(config.rocksdb_path, "/data/rocksdb");
             assert_eq!(config.artifact_path, "/data/artifacts");
         }
@@ -51,7 +887,7 @@ mod tests {
                 remove_var("ROCKSDB_PATH");
                 remove_var("ARTIFACTS_PATH");
             }
-            let config = load();
+            let config = serve(&[]);
             assert_eq!(config.rocksdb_path, "/data/rocksdb");
             assert_eq!(config.artifact_path, "/data/artifacts");
         }
@@ -62,7 +898,7 @@ mod tests {
                 set_var("ROCKSDB_PATH", "/etc/rocksdb");
                 remove_var("ARTIFACTS_PATH");
             }
-            let config = load();
+            let config = serve(&[]);
             assert_eq!(config.rocksdb_path, "/etc/rocksdb");
             assert_eq!(config.artifact_path, "/data/artifacts");
         }
@@ -73,7 +909,7 @@ mod tests {
                 remove_var("ROCKSDB_PATH");
                 set_var("ARTIFACTS_PATH", "/etc/artifacts");
             }
-            let config = load();
+            let config = serve(&[]);
             assert_eq!(config.rocksdb_path, "/data/rocksdb");
             assert_eq!(config.artifact_path, "/etc/artifacts");
         }
@@ -84,9 +920,289 @@ mod tests {
                 set_var("ROCKSDB_PATH", "/etc/rocksdb");
                 set_var("ARTIFACTS_PATH", "/etc/artifacts");
             }
-            let config = load();
+            let config = serve(&[]);
             assert_eq!(config.rocksdb_path, "/etc/rocksdb");
             assert_eq!(config.artifact_path, "/etc/artifacts");
         }
     }
+
+    #[test]
+    fn load_auth_config() {
+        let _env = clear_env();
+        let config = serve(&[]);
+        assert!(!config.auth.enabled);
+        assert!(config.auth.anonymous_read);
+        assert_eq!(config.auth.admin_token, None);
+        assert_eq!(config.auth.presign_secret, None);
+        assert!(config.auth.oidc.is_none());
+
+        unsafe {
+            set_var("AUTH_ENABLED", "true");
+            set_var("ANONYMOUS_READ", "0");
+            set_var("ADMIN_TOKEN", "secret");
+            set_var("PRESIGN_SECRET", "presign");
+        }
+        let config = serve(&[]);
+        assert!(config.auth.enabled);
+        assert!(!config.auth.anonymous_read);
+        assert_eq!(config.auth.admin_token.as_deref(), Some("secret"));
+        assert_eq!(config.auth.presign_secret.as_deref(), Some("presign"));
+
+        unsafe {
+            set_var("OIDC_ISSUER", "https://token.actions.githubusercontent.com");
+            set_var("OIDC_AUDIENCE", "https://artifacts.example.com");
+            set_var(
+                "OIDC_JWKS_URL",
+                "https://token.actions.githubusercontent.com/.well-known/jwks",
+            );
+            set_var("OIDC_SERVER", "github.com");
+            set_var("OIDC_COMMIT_CLAIM", "commit");
+        }
+        let oidc = serve(&[]).auth.oidc.unwrap();
+        assert_eq!(oidc.issuer, "https://token.actions.githubusercontent.com");
+        assert!(matches!(oidc.jwks, JwksSource::Url(_)));
+        assert_eq!(oidc.server, "github.com");
+        assert_eq!(oidc.repository_claim, "repository");
+        assert_eq!(oidc.commit_claim, "commit");
+    }
+
+    #[test]
+    fn load_limits_config() {
+        let _env = clear_env();
+        unsafe {
+            set_var("RATE_LIMIT_BYTES", "1048576");
+            set_var("RATE_LIMIT_BYTES_BURST", "10485760");
+            set_var("MAX_CONCURRENT_UPLOADS", "8");
+        }
+        let limits = serve(&[]).limits;
+        assert_eq!(limits.requests, None);
+        assert_eq!(
+            limits.bytes,
+            Some(Rate {
+                per_second: 1048576.0,
+                burst: 10485760.0
+            })
+        );
+        assert_eq!(limits.max_concurrent_uploads, Some(8));
+
+        unsafe {
+            set_var("RATE_LIMIT_REQUESTS", "0.5");
+            remove_var("RATE_LIMIT_BYTES");
+            remove_var("RATE_LIMIT_BYTES_BURST");
+            remove_var("MAX_CONCURRENT_UPLOADS");
+        }
+        let limits = serve(&[]).limits;
+        assert_eq!(
+            limits.requests,
+            Some(Rate {
+                per_second: 0.5,
+                burst: 1.0
+            })
+        );
+        assert_eq!(limits.bytes, None);
+        assert_eq!(limits.max_concurrent_uploads, None);
+    }
+
+    #[test]
+    fn load_listeners_config() {
+        let _env = clear_env();
+        assert_eq!(
+            serve(&[]).listeners,
+            vec![ListenerConfig::Tcp("0.0.0.0:3001".parse().unwrap())]
+        );
+
+        unsafe {
+            set_var(
+                "LISTEN",
+                "[::]:8080, unix:/run/artifact-store.sock,systemd,[::]:8080",
+            );
+            set_var("UNIX_SOCKET_MODE", "660");
+        }
+        assert_eq!(
+            serve(&[]).listeners,
+            vec![
+                ListenerConfig::Tcp("[::]:8080".parse().unwrap()),
+                ListenerConfig::Unix {
+                    path: "/run/artifact-store.sock".to_string(),
+                    mode: Some(0o660),
+                },
+                ListenerConfig::Systemd,
+            ]
+        );
+    }
+
+    #[test]
+    fn load_tls_config() {
+        let _env = clear_env();
+        assert!(serve(&[]).tls.is_none());
+
+        unsafe {
+            set_var("TLS_CERT_FILE", "/etc/tls/cert.pem");
+            set_var("TLS_KEY_FILE", "/etc/tls/key.pem");
+            set_var("TLS_CLIENT_CA_FILE", "/etc/tls/ca.pem");
+        }
+        let tls = serve(&[]).tls.unwrap();
+        assert_eq!(tls.cert_path, "/etc/tls/cert.pem");
+        assert_eq!(tls.key_path, "/etc/tls/key.pem");
+        assert_eq!(tls.client_ca_path.as_deref(), Some("/etc/tls/ca.pem"));
+        assert!(!tls.client_auth_optional);
+    }
+
+    #[test]
+    fn config_precedence() {
+        let _env = clear_env();
+        let file = config_file(
+            "test_config_precedence",
+            r#"
+listen = ["[::]:3001", "unix:/run/artifact-store.sock"]
+unix_socket_mode = "660"
+data_path = "/srv/artifacts"
+timeout_seconds = 30
+
+[log]
+format = "text"
+level = "warn"
+
+[auth]
+anonymous_read = false
+
+[limits]
+requests = 5
+"#,
+        );
+
+        // the file overrides the defaults
+        let config = serve(&["--config", &file]);
+        assert_eq!(config.rocksdb_path, "/srv/artifacts/rocksdb");
+        assert_eq!(
+            config.listeners,
+            vec![
+                ListenerConfig::Tcp("[::]:3001".parse().unwrap()),
+                ListenerConfig::Unix {
+                    path: "/run/artifact-store.sock".to_string(),
+                    mode: Some(0o660),
+                },
+            ]
+        );
+        assert_eq!(
+            config.log,
+            LogConfig {
+                format: LogFormat::Text,
+                level: tracing::Level::WARN,
+            }
+        );
+        assert_eq!(config.timeouts.request, Duration::from_secs(30));
+        assert!(!config.auth.enabled);
+        assert!(!config.auth.anonymous_read);
+        assert_eq!(config.limits.requests.unwrap().burst, 5.0);
+
+        // the environment overrides the file, and names the file too
+        unsafe {
+            set_var(CONFIG_FILE_ENV, &file);
+            set_var("LOG_LEVEL", "debug");
+            set_var("TIMEOUT_SECONDS", "45");
+        }
+        let config = serve(&[]);
+        assert_eq!(config.log.format, LogFormat::Text);
+        assert_eq!(config.log.level, tracing::Level::DEBUG);
+        assert_eq!(config.timeouts.request, Duration::from_secs(45));
+
+        // flags override both
+        let config = serve(&["--timeout-seconds", "60", "--auth-enabled"]);
+        assert_eq!(config.log.level, tracing::Level::DEBUG);
+        assert_eq!(config.timeouts.request, Duration::from_secs(60));
+        assert!(config.auth.enabled);
+        assert!(!config.auth.anonymous_read);
+
+        std::fs::remove_dir_all("data/test_config_precedence").unwrap();
+    }
+
+    #[test]
+    fn print_config_sources() {
+        let _env = clear_env();
+        let file = config_file(
+            "test_print_config",
+            "listen = [\"[::]:3001\"]\n[auth]\nadmin_token = \"secret\"\n[limits]\nrequests = 5\n",
+        );
+        unsafe {
+            set_var("LOG_LEVEL", "debug");
+        }
+        let lines = print_config(&["--config", &file, "--timeout-seconds", "60"]);
+
+        for (expected, source) in [
+            ("listen = [\"[::]:3001\"]", format!("# file {file}")),
+            ("log.level = \"debug\"", "# env LOG_LEVEL".to_string()),
+            ("rocksdb_path = \"/data/rocksdb\"", "# default".to_string()),
+            (
+                "timeout_seconds = 60",
+                "# flag --timeout-seconds".to_string(),
+            ),
+            (
+                "auth.admin_token = \"<redacted>\"",
+                format!("# file {file}"),
+            ),
+            ("limits.requests_burst = 5", "# default".to_string()),
+        ] {
+            let line = lines
+                .iter()
+                .find(|line| line.starts_with(expected))
+                .unwrap_or_else(|| panic!("{expected} in {lines:?}"));
+            assert!(line.ends_with(&source), "{line}");
+        }
+        assert!(!lines.iter().any(|line| line.contains("\"secret\"")));
+
+        std::fs::remove_dir_all("data/test_print_config").unwrap();
+    }
+
+    #[test]
+    fn config_errors() {
+        let _env = clear_env();
+        let file = config_file(
+            "test_config_errors",
+            "timeout = 30\n[auth]\nenabled = \"yes\"\n[tls]\ncert_file = \"cert.pem\"\n",
+        );
+        assert_eq!(
+            errors(&[
+                "--config",
+                &file,
+                "--rate-limit-requests",
+                "-1",
+                "--listen=localhost",
+                "--unknown",
+                "--data-path",
+            ]),
+            vec![
+                "unknown argument --unknown",
+                "--data-path needs a value",
+                &format!("auth.enabled in {file} must be a boolean"),
+                &format!("unknown setting timeout in {file}"),
+                "invalid value for limits.requests from flag --rate-limit-requests: -1",
+                "invalid value for listen from flag --listen: localhost",
+                "listen must have at least one address",
+                "tls.cert_file and tls.key_file must be set together",
+            ]
+        );
+
+        // numbers that aren't finite, or durations too long to represent
+        assert_eq!(
+            errors(&[
+                "--rate-limit-requests",
+                "inf",
+                "--rate-limit-bytes",
+                "NaN",
+                "--timeout-seconds",
+                "1e300",
+                "--session-ttl-seconds",
+                "infinity",
+            ]),
+            vec![
+                "invalid value for limits.requests from flag --rate-limit-requests: inf",
+                "invalid value for limits.bytes from flag --rate-limit-bytes: NaN",
+                "invalid value for timeout_seconds from flag --timeout-seconds: 1e300",
+                "invalid value for session_ttl_seconds from flag --session-ttl-seconds: infinity",
+            ]
+        );
+
+        std::fs::remove_dir_all("data/test_config_errors").unwrap();
+    }
 }
@@ -1,16 +1,52 @@
+use std::collections::{BTreeMap, HashMap};
+
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
 type TransactionDB = rocksdb::OptimisticTransactionDB;
 
-const NANOSECONDS_PER_SECOND: i64 = 1_000_000_000;
+/// Limits a listing to at most `limit` items, starting after the key encoded
+/// in `cursor`.
+#[derive(Clone, Default)]
+pub struct Pagination<'a> {
+    pub limit: Option<usize>,
+    pub cursor: Option<&'a String>,
+}
+
+/// A page of listing results. `next_cursor` is set when more items follow.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+pub type RepoFilter<'a> = &'a dyn Fn(&RepoData) -> Result<bool, Error>;
+
+#[derive(Clone, Default)]
+pub struct ListReposParams<'a> {
+    /// Only include repositories on this server.
+    pub server: Option<&'a String>,
+    /// Only include repositories of this owner, requires `server`.
+    pub owner: Option<&'a String>,
+    /// Only include repositories whose `{server}/{owner}/{repo}` path, relative to
+    /// `server` and `owner` if set, starts with this prefix.
+    pub prefix: Option<&'a String>,
+    /// Only include repositories added at or after this time.
+    pub since: Option<OffsetDateTime>,
+    /// Only include repositories added at or before this time.
+    pub until: Option<OffsetDateTime>,
+    /// Only include repositories for which this returns `true`.
+    pub visible: Option<RepoFilter<'a>>,
+    pub pagination: Pagination<'a>,
+}
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct RepoData {
     pub server: String,
     pub owner: String,
     pub repo: String,
+    #[serde(with = "time::serde::rfc3339")]
     pub time_added: OffsetDateTime,
 }
 
@@ -22,19 +58,155 @@ pub struct ExistsCommitParams<'a> {
     pub commit: &'a String,
 }
 
+/// Key/value labels attached to a commit or an artifact, such as `arch=arm64`.
+pub type Labels = BTreeMap<String, String>;
+
 #[derive(Clone)]
 pub struct ListRepoCommitsParams<'a> {
     pub server: &'a String,
     pub owner: &'a String,
     pub repo: &'a String,
+    /// Only include commits added at or after this time.
+    pub since: Option<OffsetDateTime>,
+    /// Only include commits added at or before this time.
+    pub until: Option<OffsetDateTime>,
+    /// Only include commits whose metadata matches this author.
+    pub author: Option<&'a String>,
+    /// Only include commits whose metadata matches this branch.
+    pub branch: Option<&'a String>,
+    /// Only include commits whose metadata matches this pipeline.
+    pub pipeline: Option<&'a String>,
+    /// Only include commits carrying all of these labels.
+    pub labels: &'a Labels,
+    /// Only include commits with this status.
+    pub status: Option<CommitStatus>,
+    pub pagination: Pagination<'a>,
+}
+
+/// Metadata describing where a commit came from, attached when it is first uploaded.
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ci_run_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ci_run_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipeline: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: Labels,
+}
+
+impl CommitMetadata {
+    /// Overwrite the fields that are set in `other`, keeping the rest.
+    pub fn merge(&mut self, other: CommitMetadata) {
+        if other.author.is_some() {
+            self.author = other.author;
+        }
+        if other.message.is_some() {
+            self.message = other.message;
+        }
+        if other.branch.is_some() {
+            self.branch = other.branch;
+        }
+        if !other.parents.is_empty() {
+            self.parents = other.parents;
+        }
+        if other.ci_run_url.is_some() {
+            self.ci_run_url = other.ci_run_url;
+        }
+        if other.ci_run_id.is_some() {
+            self.ci_run_id = other.ci_run_id;
+        }
+        if other.pipeline.is_some() {
+            self.pipeline = other.pipeline;
+        }
+        self.labels.extend(other.labels);
+    }
+}
+
+/// Build status of a commit, reported by CI once all of its artifacts are uploaded.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitStatus {
+    Pending,
+    Success,
+    Failed,
+}
+
+impl CommitStatus {
+    /// Whether a commit may move from this status to `next`.
+    /// A failed commit can be retried, but a successful one is final.
+    pub fn can_transition_to(self, next: CommitStatus) -> bool {
+        matches!(
+            (self, next),
+            (CommitStatus::Pending, _) | (CommitStatus::Failed, CommitStatus::Pending)
+        ) || self == next
+    }
+
+    /// Commits stored before statuses existed are considered successful.
+    fn legacy() -> Self {
+        CommitStatus::Success
+    }
+}
+
+impl std::fmt::Display for CommitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CommitStatus::Pending => write!(f, "pending"),
+            CommitStatus::Success => write!(f, "success"),
+            CommitStatus::Failed => write!(f, "failed"),
+        }
+    }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct CommitData {
     pub commit: String,
     #[serde(with = "time::serde::rfc3339")]
     pub time_added: OffsetDateTime,
+    pub status: CommitStatus,
+    #[serde(
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub time_sealed: Option<OffsetDateTime>,
+    #[serde(flatten)]
+    pub metadata: CommitMetadata,
+}
+
+#[derive(Clone)]
+pub struct SealCommitParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub commit: &'a String,
+}
+
+#[derive(Clone)]
+pub struct SetCommitStatusParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub commit: &'a String,
+    pub status: CommitStatus,
+}
+
+#[derive(Clone)]
+pub struct GetCommitParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub commit: &'a String,
 }
 
 #[derive(Clone)]
@@ -42,6 +214,14 @@ pub struct GetLatestCommitParams<'a> {
     pub server: &'a String,
     pub owner: &'a String,
     pub repo: &'a String,
+    /// Only consider commits added strictly before this time.
+    pub before: Option<OffsetDateTime>,
+    /// The number of newer commits to skip, e.g. 1 for the second newest commit.
+    pub skip: usize,
+    /// Only consider commits carrying all of these labels.
+    pub labels: &'a Labels,
+    /// Also consider commits that are pending or failed.
+    pub any_status: bool,
 }
 
 #[derive(Clone)]
@@ -59,6 +239,13 @@ pub struct ListArtifactsParams<'a> {
     pub owner: &'a String,
     pub repo: &'a String,
     pub commit: &'a String,
+    /// Only include artifacts whose path starts with this prefix.
+    pub prefix: Option<&'a String>,
+    /// Only include artifacts whose path matches this glob pattern.
+    pub glob: Option<&'a String>,
+    /// Only include artifacts carrying all of these labels.
+    pub labels: &'a Labels,
+    pub pagination: Pagination<'a>,
 }
 
 #[derive(Clone, Serialize, Debug)]
@@ -67,6 +254,8 @@ pub struct ArtifactData {
     pub path: String,
     #[serde(with = "time::serde::rfc3339")]
     pub time_added: OffsetDateTime,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: Labels,
 }
 
 #[derive(Clone)]
@@ -75,12 +264,26 @@ pub struct CreateCommitParams<'a> {
     pub server: &'a String,
     pub owner: &'a String,
     pub repo: &'a String,
+    pub metadata: &'a CommitMetadata,
+}
+
+#[derive(Clone)]
+pub struct UpdateCommitMetadataParams<'a> {
+    pub commit: &'a String,
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub metadata: &'a CommitMetadata,
 }
 
 #[derive(Clone)]
 pub struct CreateArtifactParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
     pub commit: &'a String,
     pub path: &'a String,
+    pub labels: &'a Labels,
 }
 
 #[derive(Clone)]
@@ -90,388 +293,2364 @@ pub struct CreateRepositoryParams<'a> {
     pub repo: &'a String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct RepoValue {
-    time_added: u128,
+#[derive(Clone)]
+pub struct SetRefParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub name: &'a String,
+    pub commit: &'a String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct CommitValue {
-    time_added: u128,
+#[derive(Clone)]
+pub struct GetRefParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub name: &'a String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct CommitTimeValue {
-    commit: String,
+#[derive(Clone)]
+pub struct ListRefHistoryParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub name: &'a String,
+    pub pagination: Pagination<'a>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ArtifactValue {
-    time_added: u128,
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RefHistoryData {
+    pub commit: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time_updated: OffsetDateTime,
 }
 
-#[allow(dead_code)]
-pub enum Database {
-    RocksDB(TransactionDB),
+#[derive(Clone)]
+pub struct ListRefsParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub pagination: Pagination<'a>,
 }
 
-impl Database {
-    pub fn new_rocksdb(path: &str) -> Result<Self, rocksdb::Error> {
-        let db = TransactionDB::open_default(path)?;
-        Ok(Database::RocksDB(db))
-    }
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RefData {
+    pub name: String,
+    pub commit: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time_updated: OffsetDateTime,
+}
 
-    pub fn transaction(&self) -> Transaction<'_> {
-        match self {
-            Database::RocksDB(db) => Transaction::RocksDB(db.transaction()),
-        }
-    }
+#[derive(Clone)]
+pub struct PromoteParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub channel: &'a String,
+    pub commit: &'a String,
+    /// The authenticated identity that requested the promotion, recorded for auditing.
+    pub actor: Option<&'a String>,
+    /// Who the client says requested the promotion, recorded as given and never verified.
+    pub claimed_actor: Option<&'a String>,
+    /// Why the commit was promoted, recorded for auditing.
+    pub reason: Option<&'a String>,
+}
 
-    pub fn list_repos(&self) -> Result<Vec<RepoData>, Error> {
-        let key_prefix = serialize_key(vec!["repo".as_bytes()]);
-        self.get_by_prefix(
-            key_prefix,
-            |key, value| {
-                // parts: ["repo", server, owner, repo]
-                let key_parts = deserialize_key(key);
-                let server = std::str::from_utf8(&key_parts[1]).unwrap();
-                let owner = std::str::from_utf8(&key_parts[2]).unwrap();
-                let repo = std::str::from_utf8(&key_parts[3]).unwrap();
-                let value = serde_json::from_slice::<RepoValue>(value).unwrap();
-                let time_seconds = value.time_added as i64 / NANOSECONDS_PER_SECOND;
-                let time_added = OffsetDateTime::from_unix_timestamp(time_seconds).unwrap();
-                Ok(RepoData {
-                    server: server.to_string(),
-                    owner: owner.to_string(),
-                    repo: repo.to_string(),
-                    time_added,
-                })
-            },
-            None,
-        )
-    }
+#[derive(Clone)]
+pub struct GetChannelParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub channel: &'a String,
+}
 
-    pub fn exists_commit(&self, params: ExistsCommitParams) -> Result<bool, Error> {
-        let commit_key = serialize_key(vec![
-            "commit".as_bytes(),
-            params.server.as_bytes(),
-            params.owner.as_bytes(),
-            params.repo.as_bytes(),
-            params.commit.as_bytes(),
-        ]);
-        let exists = match self {
-            Database::RocksDB(db) => db.get(commit_key)?.is_some(),
-        };
-        Ok(exists)
-    }
+#[derive(Clone)]
+pub struct ListChannelsParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub pagination: Pagination<'a>,
+}
 
-    pub fn list_repo_commits(
-        &self,
-        params: ListRepoCommitsParams,
-    ) -> Result<Vec<CommitData>, Error> {
-        let key_prefix = serialize_key(vec![
-            "commit_time".as_bytes(),
-            params.server.as_bytes(),
-            params.owner.as_bytes(),
-            params.repo.as_bytes(),
-        ]);
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelData {
+    pub name: String,
+    pub commit: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time_promoted: OffsetDateTime,
+}
 
-        self.get_by_prefix(
-            key_prefix,
-            |key, value| {
-                // parts: ["commit_time", server, owner, repo, time]
-                let key_parts = deserialize_key(key);
-                let time_part = key_parts.last().unwrap();
+#[derive(Clone)]
+pub struct ListPromotionsParams<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub channel: &'a String,
+    pub pagination: Pagination<'a>,
+}
 
-                // time_part is expected to be a u128
-                let time = extract_time(time_part);
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PromotionData {
+    pub commit: String,
+    /// The commit the channel pointed at before, if any.
+    pub previous: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time_promoted: OffsetDateTime,
+    /// The authenticated identity that promoted the commit, if authentication is enabled.
+    pub actor: Option<String>,
+    /// Who the client says promoted the commit, not verified.
+    pub claimed_actor: Option<String>,
+    pub reason: Option<String>,
+}
 
-                let value_str = std::str::from_utf8(value).unwrap();
-                let value = serde_json::from_str::<CommitTimeValue>(value_str).unwrap();
-                Ok(CommitData {
-                    commit: value.commit,
-                    time_added: time,
-                })
-            },
-            Some(true),
-        )
-    }
+#[derive(Clone)]
+pub struct CreateSessionParams<'a> {
+    pub id: &'a String,
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+    pub commit: &'a String,
+    pub metadata: &'a CommitMetadata,
+}
 
-    pub fn get_latest_commit(&self, params: GetLatestCommitParams) -> Result<String, Error> {
-        let mut search_key = serialize_key(vec![
-            "commit_time".as_bytes(),
-            params.server.as_bytes(),
-            params.owner.as_bytes(),
-            params.repo.as_bytes(),
-        ]);
-        search_key.push(b'$');
+/// An upload session, whose staged artifacts become visible together once it is published.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SessionData {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    pub commit: String,
+    pub time_created: u128,
+    pub metadata: CommitMetadata,
+}
+
+#[derive(Clone)]
+pub struct StageArtifactParams<'a> {
+    pub session: &'a String,
+    pub path: &'a String,
+    pub labels: &'a Labels,
+}
+
+/// The kind of change an event reports.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    #[serde(rename = "repo.created")]
+    RepoCreated,
+    #[serde(rename = "commit.created")]
+    CommitCreated,
+    #[serde(rename = "commit.status_changed")]
+    CommitStatusChanged,
+    #[serde(rename = "artifact.created")]
+    ArtifactCreated,
+}
 
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Database::RocksDB(db) => {
-                let mut iter = db.raw_iterator();
-                iter.seek_for_prev(&search_key);
-                if iter.valid() {
-                    let value_raw = iter.value().unwrap();
-                    let value_str = std::str::from_utf8(value_raw).unwrap();
-                    let value = serde_json::from_str::<CommitTimeValue>(value_str).unwrap();
-                    return Ok(value.commit);
-                }
-                Err(Error::Generic("no commits found".to_string()))
-            }
+            EventKind::RepoCreated => write!(f, "repo.created"),
+            EventKind::CommitCreated => write!(f, "commit.created"),
+            EventKind::CommitStatusChanged => write!(f, "commit.status_changed"),
+            EventKind::ArtifactCreated => write!(f, "artifact.created"),
         }
     }
+}
 
-    pub fn exists_artifact(&self, params: ExistsArtifactParams) -> Result<bool, Error> {
-        let exists = self.exists_commit(ExistsCommitParams {
-            server: params.server,
-            owner: params.owner,
-            repo: params.repo,
-            commit: params.commit,
-        })?;
-        if !exists {
-            return Ok(false);
-        }
+/// A change to a repository, as delivered to webhooks and event streams.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<CommitStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_status: Option<CommitStatus>,
+}
 
-        let artifact_key = serialize_key(vec![
-            "artifact".as_bytes(),
-            params.commit.as_bytes(),
-            params.path.as_bytes(),
-        ]);
-        let exists = match self {
-            Database::RocksDB(db) => db.get(artifact_key)?.is_some(),
-        };
-        Ok(exists)
+impl Event {
+    fn new(kind: EventKind, time: u128, server: &str, owner: &str, repo: &str) -> Self {
+        Event {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            kind,
+            time: nanos_to_time(time),
+            server: server.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            commit: None,
+            path: None,
+            status: None,
+            previous_status: None,
+        }
     }
+}
 
-    pub fn list_artifacts(&self, params: ListArtifactsParams) -> Result<Vec<ArtifactData>, Error> {
-        let exists_commit = self.exists_commit(ExistsCommitParams {
-            server: params.server,
-            owner: params.owner,
-            repo: params.repo,
-            commit: params.commit,
-        })?;
-        if !exists_commit {
-            return Err(Error::Generic(format!(
-                "commit does not exist: {}",
-                params.commit
-            )));
-        }
+#[derive(Clone)]
+pub struct CreateWebhookParams<'a> {
+    pub id: &'a String,
+    pub url: &'a String,
+    pub secret: &'a String,
+    /// Only deliver these kinds of events, all of them if empty.
+    pub events: &'a [EventKind],
+    /// Only deliver events of this repository, all repositories if `None`.
+    pub scope: Option<RepoScope<'a>>,
+}
 
-        let key_prefix = serialize_key(vec!["artifact".as_bytes(), params.commit.as_bytes()]);
+#[derive(Clone)]
+pub struct RepoScope<'a> {
+    pub server: &'a String,
+    pub owner: &'a String,
+    pub repo: &'a String,
+}
 
-        self.get_by_prefix(
-            key_prefix,
-            |key, value| {
-                // parts: ["artifact", commit, path]
-                let key_parts = deserialize_key(key);
-                let path_raw = key_parts.last().unwrap();
-                let path = std::str::from_utf8(path_raw).unwrap().to_string();
+#[derive(Clone, Default)]
+pub struct ListWebhooksParams<'a> {
+    /// Only include the webhooks of this repository.
+    pub scope: Option<RepoScope<'a>>,
+    pub pagination: Pagination<'a>,
+}
 
-                let value_str = std::str::from_utf8(value).unwrap();
-                let value = serde_json::from_str::<ArtifactValue>(value_str).unwrap();
-                let time_seconds = value.time_added as i64 / NANOSECONDS_PER_SECOND;
-                let time = OffsetDateTime::from_unix_timestamp(time_seconds).unwrap();
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookData {
+    pub id: String,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<EventKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time_added: OffsetDateTime,
+}
 
-                Ok(ArtifactData {
-                    path,
-                    time_added: time,
-                })
-            },
-            None,
-        )
-    }
+/// A pending delivery of an event to a webhook.
+#[derive(Clone, Debug)]
+pub struct DeliveryData {
+    pub id: String,
+    pub webhook: String,
+    pub event: EventKind,
+    /// The JSON body, kept as is so that retries are signed over the same bytes.
+    pub payload: String,
+    /// The number of failed attempts so far.
+    pub attempts: u32,
+    /// When the delivery is due.
+    pub time_due: u128,
+}
 
-    fn get_by_prefix<T>(
-        &self,
-        key_prefix: Vec<u8>,
-        func: fn(key: &[u8], value: &[u8]) -> Result<T, Error>,
-        reverse: Option<bool>,
-    ) -> Result<Vec<T>, Error> {
-        let mut key_start = key_prefix.clone();
-        key_start.push(b'#');
+#[derive(Clone, Default)]
+pub struct ListEventsParams<'a> {
+    /// Only include events with a greater sequence number.
+    pub after: u64,
+    /// Only include the events of this repository.
+    pub scope: Option<RepoScope<'a>>,
+    pub pagination: Pagination<'a>,
+}
 
-        let mut key_end = key_prefix.clone();
-        key_end.push(b'$');
+#[derive(Clone)]
+pub struct AppendAuditParams<'a> {
+    pub request_id: &'a String,
+    /// What was done, such as `upload` or `promote`.
+    pub action: &'a String,
+    pub method: &'a String,
+    pub uri: &'a String,
+    /// The HTTP status code of the response.
+    pub status: u16,
+    /// The address of the client.
+    pub client: Option<&'a String>,
+    /// Who made the request, if it was authenticated.
+    pub identity: Option<&'a String>,
+    /// The path parameters of the request, such as `server` and `commit`.
+    pub params: &'a BTreeMap<String, String>,
+}
 
-        let mut result: Vec<T> = Vec::new();
-        match self {
-            Database::RocksDB(db) => {
-                let mut iter = db.raw_iterator();
-                let should_reverse = reverse.unwrap_or(false);
-                if should_reverse {
-                    iter.seek_for_prev(&key_end);
-                } else {
-                    iter.seek(&key_start);
-                }
-                while iter.valid() {
-                    let raw_key = iter.key().unwrap();
-                    if !raw_key.starts_with(&key_prefix) {
-                        break;
-                    }
-                    let raw_value = iter.value().unwrap();
-                    let value = func(raw_key, raw_value)?;
-                    result.push(value);
-                    if should_reverse {
-                        iter.prev();
-                    } else {
-                        iter.next();
-                    }
-                }
-                Ok(result)
-            }
-        }
-    }
+#[derive(Clone, Default)]
+pub struct ListAuditParams<'a> {
+    /// Only include entries recorded at or after this time.
+    pub since: Option<OffsetDateTime>,
+    /// Only include entries recorded at or before this time.
+    pub until: Option<OffsetDateTime>,
+    pub action: Option<&'a String>,
+    pub identity: Option<&'a String>,
+    /// Only include entries having all of these path parameters.
+    pub params: BTreeMap<String, String>,
+    pub pagination: Pagination<'a>,
 }
 
-pub enum Transaction<'db> {
-    RocksDB(rocksdb::Transaction<'db, TransactionDB>),
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditData {
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+    pub request_id: String,
+    pub action: String,
+    pub method: String,
+    pub uri: String,
+    pub status: u16,
+    pub client: Option<String>,
+    pub identity: Option<String>,
+    pub params: BTreeMap<String, String>,
 }
 
-impl Transaction<'_> {
-    /// Stores the repository data in the database
-    pub fn create_repo_if_not_exists(
-        &self,
-        time: u128,
-        params: CreateRepositoryParams,
-    ) -> Result<(), Error> {
-        let key = serialize_key(vec![
-            "repo".as_bytes(),
-            params.server.as_bytes(),
-            params.owner.as_bytes(),
-            params.repo.as_bytes(),
-        ]);
-        let value = RepoValue { time_added: time };
+/// What a token may do. Each scope includes the ones before it.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    Read,
+    Write,
+    Admin,
+}
 
+impl std::fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Transaction::RocksDB(tx) => {
-                let exists = tx.get(&key)?.is_some();
-                if exists {
-                    return Ok(());
-                }
-                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
-            }
+            TokenScope::Read => write!(f, "read"),
+            TokenScope::Write => write!(f, "write"),
+            TokenScope::Admin => write!(f, "admin"),
         }
-        Ok(())
     }
+}
 
-    /// Store the commit data in the database.
-    pub fn create_commit_if_not_exists(
-        &self,
-        time: u128,
-        params: CreateCommitParams,
-    ) -> Result<(), Error> {
-        let commit_key = serialize_key(vec![
-            "commit".as_bytes(),
-            params.server.as_bytes(),
-            params.owner.as_bytes(),
-            params.repo.as_bytes(),
-            params.commit.as_bytes(),
-        ]);
-        let commit_value = CommitValue { time_added: time };
+#[derive(Clone)]
+pub struct CreateTokenParams<'a> {
+    pub id: &'a String,
+    pub name: &'a String,
+    /// The hex encoded SHA-256 hash of the token secret.
+    pub hash: &'a String,
+    pub scopes: &'a [TokenScope],
+    /// Only allow access to this server, or any server if `None`.
+    pub server: Option<&'a String>,
+    /// Only allow access to this owner, requires `server`.
+    pub owner: Option<&'a String>,
+    /// Only allow access to this repository, requires `owner`.
+    pub repo: Option<&'a String>,
+}
 
-        let commit_time_key = serialize_key(vec![
-            "commit_time".as_bytes(),
-            params.server.as_bytes(),
-            params.owner.as_bytes(),
-            params.repo.as_bytes(),
-            &time.to_be_bytes(),
-        ]);
-        let commit_time_value = CommitTimeValue {
-            commit: params.commit.clone(),
-        };
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenData {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub hash: String,
+    pub scopes: Vec<TokenScope>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time_added: OffsetDateTime,
+}
 
+/// What an ACL allows a principal to do in a repository.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AclPermission {
+    List,
+    Download,
+    Upload,
+    Delete,
+    Promote,
+}
+
+impl std::fmt::Display for AclPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Transaction::RocksDB(tx) => {
-                let exists = tx.get(&commit_key)?.is_some();
-                if exists {
-                    return Ok(());
-                }
-                tx.put(
-                    commit_key,
-                    serde_json::to_string(&commit_value).unwrap().as_bytes(),
-                )?;
-                tx.put(
-                    commit_time_key,
-                    serde_json::to_string(&commit_time_value)
-                        .unwrap()
-                        .as_bytes(),
-                )?;
-            }
+            AclPermission::List => write!(f, "list"),
+            AclPermission::Download => write!(f, "download"),
+            AclPermission::Upload => write!(f, "upload"),
+            AclPermission::Delete => write!(f, "delete"),
+            AclPermission::Promote => write!(f, "promote"),
         }
-        Ok(())
     }
+}
 
-    /// Store the artifact data in the database.
-    /// If the artifact already exists, return an error.
-    pub fn create_artifact(&self, time: u128, params: CreateArtifactParams) -> Result<(), Error> {
-        let key = serialize_key(vec![
-            "artifact".as_bytes(),
-            params.commit.as_bytes(),
-            params.path.as_bytes(),
-        ]);
-        let value = ArtifactValue { time_added: time };
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AclEntry {
+    /// A token id, `authenticated` for any token, or `*` for anyone.
+    pub principal: String,
+    pub permissions: Vec<AclPermission>,
+}
 
-        match self {
-            Transaction::RocksDB(tx) => {
-                let exists = tx.get(&key)?.is_some();
-                if exists {
-                    return Err(Error::Generic(format!(
-                        "artifact already exists: {}",
-                        params.path
-                    )));
-                }
+/// The server, owner or repository an ACL is attached to.
+#[derive(Clone, Copy)]
+pub struct AclTarget<'a> {
+    pub server: &'a String,
+    /// Requires nothing but `server`.
+    pub owner: Option<&'a String>,
+    /// Requires `owner`.
+    pub repo: Option<&'a String>,
+}
 
-                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
-                Ok(())
-            }
-        }
-    }
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AclData {
+    pub server: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub entries: Vec<AclEntry>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time_updated: OffsetDateTime,
+}
 
-    pub fn commit(self) -> Result<(), rocksdb::Error> {
-        match self {
-            Transaction::RocksDB(tx) => tx.commit(),
-        }
-    }
+#[derive(Serialize, Deserialize)]
+struct RepoValue {
+    time_added: u128,
 }
 
-#[derive(Debug)]
-pub enum Error {
-    RocksDB(rocksdb::Error),
-    Generic(String),
+#[derive(Serialize, Deserialize)]
+struct CommitValue {
+    time_added: u128,
+    #[serde(default = "CommitStatus::legacy")]
+    status: CommitStatus,
+    /// When the commit was sealed, after which its artifacts can't change.
+    #[serde(default)]
+    time_sealed: Option<u128>,
+    #[serde(default)]
+    metadata: CommitMetadata,
 }
 
-impl From<rocksdb::Error> for Error {
-    fn from(e: rocksdb::Error) -> Self {
-        Error::RocksDB(e)
-    }
+#[derive(Serialize, Deserialize)]
+struct CommitTimeValue {
+    commit: String,
 }
 
-impl From<String> for Error {
-    fn from(e: String) -> Self {
-        Error::Generic(e)
-    }
+#[derive(Serialize, Deserialize)]
+struct ArtifactValue {
+    time_added: u128,
+    #[serde(default)]
+    labels: Labels,
 }
 
-fn serialize_key(parts: Vec<&[u8]>) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::new();
-    for i in 0..parts.len() {
-        let part = parts[i];
-        for byte in part {
-            if *byte == b'#' || *byte == b'\\' {
-                result.push(b'\\');
+#[derive(Serialize, Deserialize)]
+struct RefValue {
+    commit: String,
+    time_updated: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RefHistoryValue {
+    commit: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelValue {
+    commit: String,
+    time_promoted: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PromotionValue {
+    commit: String,
+    previous: Option<String>,
+    identity: Option<String>,
+    claimed_actor: Option<String>,
+    reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WebhookValue {
+    url: String,
+    secret: String,
+    events: Vec<EventKind>,
+    server: Option<String>,
+    owner: Option<String>,
+    repo: Option<String>,
+    time_added: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeliveryValue {
+    webhook: String,
+    event: EventKind,
+    payload: String,
+    attempts: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuditValue {
+    action: String,
+    method: String,
+    uri: String,
+    status: u16,
+    client: Option<String>,
+    identity: Option<String>,
+    params: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenValue {
+    name: String,
+    hash: String,
+    scopes: Vec<TokenScope>,
+    server: Option<String>,
+    owner: Option<String>,
+    repo: Option<String>,
+    time_added: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AclValue {
+    entries: Vec<AclEntry>,
+    time_updated: u128,
+}
+
+#[allow(dead_code)]
+pub enum Database {
+    RocksDB(TransactionDB),
+}
+
+impl Database {
+    pub fn new_rocksdb(path: &str) -> Result<Self, rocksdb::Error> {
+        let db = TransactionDB::open_default(path)?;
+        migrate(&db)?;
+        Ok(Database::RocksDB(db))
+    }
+
+    /// Scope `artifact` and `artifact_label` keys, stored by commit only, by the repository too.
+    /// A commit may be found in several repositories, such as forks, so an artifact is only moved
+    /// into those where `has_file` finds its file, which is where it was uploaded. Artifacts found
+    /// in none of them were unreachable and are dropped. Like the migrations run when the database
+    /// is opened it only runs once, but it needs the artifact files, so the server runs it.
+    pub fn migrate_artifact_keys(
+        &self,
+        has_file: impl Fn(ExistsArtifactParams) -> bool,
+    ) -> Result<(), rocksdb::Error> {
+        let Database::RocksDB(db) = self;
+        run_migration(db, "artifact_keys", |tx| {
+            let mut repos: HashMap<Vec<u8>, Vec<[String; 3]>> = HashMap::new();
+            for_each_key(db, "commit", |_, key_parts, _| {
+                // parts: ["commit", server, owner, repo, commit]
+                if let [_, server, owner, repo, commit] = key_parts.as_slice() {
+                    let part = |part: &[u8]| String::from_utf8_lossy(part).to_string();
+                    repos.entry(commit.clone()).or_default().push([
+                        part(server),
+                        part(owner),
+                        part(repo),
+                    ]);
+                }
+                Ok(())
+            })?;
+
+            // namespace, and how many parts its keys had before
+            for (namespace, old_len) in [("artifact", 3), ("artifact_label", 5)] {
+                for_each_key(db, namespace, |key, key_parts, value| {
+                    // parts: ["artifact", commit, path]
+                    // or ["artifact_label", commit, name, value, path]
+                    if key_parts.len() != old_len {
+                        return Ok(());
+                    }
+                    let commit = String::from_utf8_lossy(&key_parts[1]).to_string();
+                    let path = String::from_utf8_lossy(key_parts.last().unwrap()).to_string();
+                    for [server, owner, repo] in repos.get(&key_parts[1]).into_iter().flatten() {
+                        let params = ExistsArtifactParams {
+                            server,
+                            owner,
+                            repo,
+                            commit: &commit,
+                            path: &path,
+                        };
+                        if !has_file(params) {
+                            continue;
+                        }
+                        let mut parts: Vec<&[u8]> = vec![
+                            namespace.as_bytes(),
+                            server.as_bytes(),
+                            owner.as_bytes(),
+                            repo.as_bytes(),
+                        ];
+                        parts.extend(key_parts[1..].iter().map(Vec::as_slice));
+                        tx.put(serialize_key(parts), value)?;
+                    }
+                    tx.delete(key)
+                })?;
             }
-            result.push(*byte);
-        }
-        if i < parts.len() - 1 {
-            result.push(b'#');
-        }
+            Ok(())
+        })
     }
-    result
-}
 
-fn deserialize_key(key: &[u8]) -> Vec<Vec<u8>> {
-    let mut result: Vec<Vec<u8>> = Vec::new();
-    let mut part: Vec<u8> = Vec::new();
+    pub fn transaction(&self) -> Transaction<'_> {
+        match self {
+            Database::RocksDB(db) => Transaction::RocksDB(db.transaction()),
+        }
+    }
+
+    pub fn list_repos(&self, params: ListReposParams) -> Result<Page<RepoData>, Error> {
+        // keys are ordered by server, owner and repo, so scoping and prefixes
+        // become a seek on the leading key parts
+        let mut key_parts = vec!["repo".as_bytes()];
+        if let Some(server) = params.server {
+            key_parts.push(server.as_bytes());
+            if let Some(owner) = params.owner {
+                key_parts.push(owner.as_bytes());
+            }
+        }
+        match params.prefix {
+            Some(prefix) => key_parts.extend(prefix.split('/').map(str::as_bytes)),
+            None => key_parts.push(b""),
+        }
+        let lower = serialize_key(key_parts);
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["repo", server, owner, repo]
+                let key_parts = deserialize_key(key);
+                let server = std::str::from_utf8(&key_parts[1]).unwrap();
+                let owner = std::str::from_utf8(&key_parts[2]).unwrap();
+                let repo = std::str::from_utf8(&key_parts[3]).unwrap();
+                let value = serde_json::from_slice::<RepoValue>(value).unwrap();
+                let time_added = nanos_to_time(value.time_added);
+                if params.since.is_some_and(|since| time_added < since)
+                    || params.until.is_some_and(|until| time_added > until)
+                {
+                    return Ok(None);
+                }
+                let data = RepoData {
+                    server: server.to_string(),
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                    time_added,
+                };
+                if let Some(visible) = params.visible
+                    && !visible(&data)?
+                {
+                    return Ok(None);
+                }
+                Ok(Some(data))
+            },
+            None,
+            params.pagination,
+        )
+    }
+
+    pub fn exists_commit(&self, params: ExistsCommitParams) -> Result<bool, Error> {
+        let commit_key = serialize_key(vec![
+            "commit".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.commit.as_bytes(),
+        ]);
+        let exists = match self {
+            Database::RocksDB(db) => db.get(commit_key)?.is_some(),
+        };
+        Ok(exists)
+    }
+
+    fn get_commit_value(
+        &self,
+        server: &String,
+        owner: &String,
+        repo: &String,
+        commit: &String,
+    ) -> Result<Option<CommitValue>, Error> {
+        let commit_key = serialize_key(vec![
+            "commit".as_bytes(),
+            server.as_bytes(),
+            owner.as_bytes(),
+            repo.as_bytes(),
+            commit.as_bytes(),
+        ]);
+        let value = match self {
+            Database::RocksDB(db) => db.get(commit_key)?,
+        };
+        Ok(value.map(|value| serde_json::from_slice::<CommitValue>(&value).unwrap()))
+    }
+
+    pub fn get_commit(&self, params: GetCommitParams) -> Result<Option<CommitData>, Error> {
+        let value =
+            self.get_commit_value(params.server, params.owner, params.repo, params.commit)?;
+        Ok(value.map(|value| CommitData {
+            commit: params.commit.clone(),
+            time_added: nanos_to_time(value.time_added),
+            status: value.status,
+            time_sealed: value.time_sealed.map(nanos_to_time),
+            metadata: value.metadata,
+        }))
+    }
+
+    pub fn list_repo_commits(
+        &self,
+        params: ListRepoCommitsParams,
+    ) -> Result<Page<CommitData>, Error> {
+        // with a label filter, walk the secondary index of the first label instead,
+        // its values have the same shape as `commit_time`
+        let key_parts: Vec<&[u8]> = match params.labels.iter().next() {
+            Some((name, value)) => vec![
+                "commit_label".as_bytes(),
+                params.server.as_bytes(),
+                params.owner.as_bytes(),
+                params.repo.as_bytes(),
+                name.as_bytes(),
+                value.as_bytes(),
+            ],
+            None => vec![
+                "commit_time".as_bytes(),
+                params.server.as_bytes(),
+                params.owner.as_bytes(),
+                params.repo.as_bytes(),
+            ],
+        };
+        let key_prefix = serialize_key(key_parts.clone());
+        let commit_time_key = |time: u128| {
+            let time = time_part(time);
+            let mut parts = key_parts.clone();
+            parts.push(&time);
+            serialize_key(parts)
+        };
+
+        let lower = match params.since {
+            Some(since) => commit_time_key(time_to_nanos(since)),
+            None => [key_prefix.as_slice(), b"#"].concat(),
+        };
+        let upper = match params.until {
+            Some(until) => commit_time_key(time_to_nanos(until) + 1),
+            None => [key_prefix.as_slice(), b"$"].concat(),
+        };
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["commit_time", server, owner, repo, time]
+                // or ["commit_label", server, owner, repo, name, value, time]
+                let key_parts = deserialize_key(key);
+                let time = extract_time(key_parts.last().unwrap());
+
+                let value_str = std::str::from_utf8(value).unwrap();
+                let value = serde_json::from_str::<CommitTimeValue>(value_str).unwrap();
+                let Some(CommitValue {
+                    status,
+                    time_sealed,
+                    metadata,
+                    ..
+                }) =
+                    self.get_commit_value(params.server, params.owner, params.repo, &value.commit)?
+                else {
+                    return Ok(None);
+                };
+
+                let matches = |filter: Option<&String>, field: &Option<String>| {
+                    filter.is_none_or(|filter| field.as_ref() == Some(filter))
+                };
+                if !matches(params.author, &metadata.author)
+                    || !matches(params.branch, &metadata.branch)
+                    || !matches(params.pipeline, &metadata.pipeline)
+                    || !has_labels(&metadata.labels, params.labels)
+                    || params.status.is_some_and(|filter| filter != status)
+                {
+                    return Ok(None);
+                }
+
+                Ok(Some(CommitData {
+                    commit: value.commit,
+                    time_added: time,
+                    status,
+                    time_sealed: time_sealed.map(nanos_to_time),
+                    metadata,
+                }))
+            },
+            Some(true),
+            params.pagination,
+        )
+    }
+
+    /// Get the latest successful commit added before `params.before`, skipping the `params.skip`
+    /// newest ones.
+    pub fn get_latest_commit(
+        &self,
+        params: GetLatestCommitParams,
+    ) -> Result<Option<String>, Error> {
+        let limit = params.skip.checked_add(1).ok_or_else(|| {
+            Error::InvalidArgument(format!("cannot skip {} commits", params.skip))
+        })?;
+        let page = self.list_repo_commits(ListRepoCommitsParams {
+            server: params.server,
+            owner: params.owner,
+            repo: params.repo,
+            since: None,
+            until: params
+                .before
+                .map(|before| before - time::Duration::nanoseconds(1)),
+            author: None,
+            branch: None,
+            pipeline: None,
+            labels: params.labels,
+            status: if params.any_status {
+                None
+            } else {
+                Some(CommitStatus::Success)
+            },
+            pagination: Pagination {
+                limit: Some(limit),
+                cursor: None,
+            },
+        })?;
+        Ok(page
+            .items
+            .into_iter()
+            .nth(params.skip)
+            .map(|commit| commit.commit))
+    }
+
+    pub fn exists_artifact(&self, params: ExistsArtifactParams) -> Result<bool, Error> {
+        let exists = self.exists_commit(ExistsCommitParams {
+            server: params.server,
+            owner: params.owner,
+            repo: params.repo,
+            commit: params.commit,
+        })?;
+        if !exists {
+            return Ok(false);
+        }
+
+        let artifact_key = artifact_key(
+            params.server,
+            params.owner,
+            params.repo,
+            params.commit,
+            params.path.as_bytes(),
+        );
+        let exists = match self {
+            Database::RocksDB(db) => db.get(artifact_key)?.is_some(),
+        };
+        Ok(exists)
+    }
+
+    pub fn list_artifacts(&self, params: ListArtifactsParams) -> Result<Page<ArtifactData>, Error> {
+        let exists_commit = self.exists_commit(ExistsCommitParams {
+            server: params.server,
+            owner: params.owner,
+            repo: params.repo,
+            commit: params.commit,
+        })?;
+        if !exists_commit {
+            return Err(Error::Generic(format!(
+                "commit does not exist: {}",
+                params.commit
+            )));
+        }
+
+        let pattern = match params.glob {
+            Some(glob) => Some(
+                glob::Pattern::new(glob)
+                    .map_err(|e| Error::InvalidArgument(format!("invalid glob {glob}: {e}")))?,
+            ),
+            None => None,
+        };
+        let match_options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+
+        // seek directly to the paths sharing the prefix, either given explicitly
+        // or taken from the literal part of the glob pattern
+        let path_prefix = match (params.prefix, params.glob) {
+            (Some(prefix), _) => prefix.as_str(),
+            (None, Some(glob)) => glob.split(['*', '?', '[']).next().unwrap(),
+            (None, None) => "",
+        };
+        // with a label filter, walk the secondary index of the first label instead
+        let lower = match params.labels.iter().next() {
+            Some((name, value)) => artifact_label_key(
+                params.server,
+                params.owner,
+                params.repo,
+                params.commit,
+                name,
+                value,
+                path_prefix,
+            ),
+            None => artifact_key(
+                params.server,
+                params.owner,
+                params.repo,
+                params.commit,
+                path_prefix.as_bytes(),
+            ),
+        };
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["artifact", server, owner, repo, commit, path]
+                // or ["artifact_label", server, owner, repo, commit, name, value, path]
+                let key_parts = deserialize_key(key);
+                let path_raw = key_parts.last().unwrap();
+                let path = std::str::from_utf8(path_raw).unwrap().to_string();
+                if let Some(pattern) = &pattern
+                    && !pattern.matches_with(&path, match_options)
+                {
+                    return Ok(None);
+                }
+
+                let value = if params.labels.is_empty() {
+                    serde_json::from_slice::<ArtifactValue>(value).unwrap()
+                } else {
+                    let artifact_key = artifact_key(
+                        params.server,
+                        params.owner,
+                        params.repo,
+                        params.commit,
+                        path_raw,
+                    );
+                    let value = match self {
+                        Database::RocksDB(db) => db.get(artifact_key)?,
+                    };
+                    match value {
+                        Some(value) => serde_json::from_slice::<ArtifactValue>(&value).unwrap(),
+                        None => return Ok(None),
+                    }
+                };
+                if !has_labels(&value.labels, params.labels) {
+                    return Ok(None);
+                }
+
+                Ok(Some(ArtifactData {
+                    path,
+                    time_added: nanos_to_time(value.time_added),
+                    labels: value.labels,
+                }))
+            },
+            None,
+            params.pagination,
+        )
+    }
+
+    /// Get the commit that a ref points at, if the ref exists.
+    pub fn get_ref(&self, params: GetRefParams) -> Result<Option<String>, Error> {
+        let key = serialize_key(vec![
+            "ref".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.name.as_bytes(),
+        ]);
+        let value = match self {
+            Database::RocksDB(db) => db.get(key)?,
+        };
+        Ok(value.map(|value| serde_json::from_slice::<RefValue>(&value).unwrap().commit))
+    }
+
+    pub fn list_refs(&self, params: ListRefsParams) -> Result<Page<RefData>, Error> {
+        let lower = serialize_key(vec![
+            "ref".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            b"",
+        ]);
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["ref", server, owner, repo, name]
+                let key_parts = deserialize_key(key);
+                let name = std::str::from_utf8(key_parts.last().unwrap()).unwrap();
+                let value = serde_json::from_slice::<RefValue>(value).unwrap();
+                Ok(Some(RefData {
+                    name: name.to_string(),
+                    commit: value.commit,
+                    time_updated: nanos_to_time(value.time_updated),
+                }))
+            },
+            None,
+            params.pagination,
+        )
+    }
+
+    /// List the commits a ref has pointed at, newest first.
+    pub fn list_ref_history(
+        &self,
+        params: ListRefHistoryParams,
+    ) -> Result<Page<RefHistoryData>, Error> {
+        let lower = serialize_key(vec![
+            "ref_history".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.name.as_bytes(),
+            b"",
+        ]);
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["ref_history", server, owner, repo, name, time]
+                let key_parts = deserialize_key(key);
+                let time = extract_time(key_parts.last().unwrap());
+                let value = serde_json::from_slice::<RefHistoryValue>(value).unwrap();
+                Ok(Some(RefHistoryData {
+                    commit: value.commit,
+                    time_updated: time,
+                }))
+            },
+            Some(true),
+            params.pagination,
+        )
+    }
+
+    /// Get the commit that a channel points at, if any commit was promoted into it.
+    pub fn get_channel(&self, params: GetChannelParams) -> Result<Option<String>, Error> {
+        let key = serialize_key(vec![
+            "channel".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.channel.as_bytes(),
+        ]);
+        let value = match self {
+            Database::RocksDB(db) => db.get(key)?,
+        };
+        Ok(value.map(|value| {
+            serde_json::from_slice::<ChannelValue>(&value)
+                .unwrap()
+                .commit
+        }))
+    }
+
+    pub fn list_channels(&self, params: ListChannelsParams) -> Result<Page<ChannelData>, Error> {
+        let lower = serialize_key(vec![
+            "channel".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            b"",
+        ]);
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["channel", server, owner, repo, name]
+                let key_parts = deserialize_key(key);
+                let name = std::str::from_utf8(key_parts.last().unwrap()).unwrap();
+                let value = serde_json::from_slice::<ChannelValue>(value).unwrap();
+                Ok(Some(ChannelData {
+                    name: name.to_string(),
+                    commit: value.commit,
+                    time_promoted: nanos_to_time(value.time_promoted),
+                }))
+            },
+            None,
+            params.pagination,
+        )
+    }
+
+    /// List the promotions into a channel, newest first.
+    pub fn list_promotions(
+        &self,
+        params: ListPromotionsParams,
+    ) -> Result<Page<PromotionData>, Error> {
+        let lower = serialize_key(vec![
+            "promotion".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.channel.as_bytes(),
+            b"",
+        ]);
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["promotion", server, owner, repo, channel, time]
+                let key_parts = deserialize_key(key);
+                let time = extract_time(key_parts.last().unwrap());
+                let value = serde_json::from_slice::<PromotionValue>(value).unwrap();
+                Ok(Some(PromotionData {
+                    commit: value.commit,
+                    previous: value.previous,
+                    time_promoted: time,
+                    actor: value.identity,
+                    claimed_actor: value.claimed_actor,
+                    reason: value.reason,
+                }))
+            },
+            Some(true),
+            params.pagination,
+        )
+    }
+
+    pub fn get_session(&self, id: &String) -> Result<Option<SessionData>, Error> {
+        let key = serialize_key(vec!["session".as_bytes(), id.as_bytes()]);
+        let value = match self {
+            Database::RocksDB(db) => db.get(key)?,
+        };
+        Ok(value.map(|value| serde_json::from_slice::<SessionData>(&value).unwrap()))
+    }
+
+    /// List up to `limit` ids of sessions created before `before`.
+    pub fn list_expired_sessions(&self, before: u128, limit: usize) -> Result<Vec<String>, Error> {
+        let lower = serialize_key(vec!["session".as_bytes(), b""]);
+        let upper = prefix_upper_bound(&lower);
+
+        let page = self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["session", id]
+                let value = serde_json::from_slice::<SessionData>(value).unwrap();
+                if value.time_created >= before {
+                    return Ok(None);
+                }
+                let key_parts = deserialize_key(key);
+                Ok(Some(
+                    std::str::from_utf8(&key_parts[1]).unwrap().to_string(),
+                ))
+            },
+            None,
+            Pagination {
+                limit: Some(limit),
+                cursor: None,
+            },
+        )?;
+        Ok(page.items)
+    }
+
+    /// List webhooks ordered by id.
+    pub fn list_webhooks(&self, params: ListWebhooksParams) -> Result<Page<WebhookData>, Error> {
+        let lower = serialize_key(vec!["webhook".as_bytes(), b""]);
+        let upper = prefix_upper_bound(&lower);
+        let scope = params.scope.as_ref();
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                let data = webhook_data(key, value);
+                if let Some(scope) = scope
+                    && (data.server.as_ref() != Some(scope.server)
+                        || data.owner.as_ref() != Some(scope.owner)
+                        || data.repo.as_ref() != Some(scope.repo))
+                {
+                    return Ok(None);
+                }
+                Ok(Some(data))
+            },
+            None,
+            params.pagination,
+        )
+    }
+
+    pub fn get_webhook(&self, id: &String) -> Result<Option<WebhookData>, Error> {
+        let key = serialize_key(vec!["webhook".as_bytes(), id.as_bytes()]);
+        let value = match self {
+            Database::RocksDB(db) => db.get(&key)?,
+        };
+        Ok(value.map(|value| webhook_data(&key, &value)))
+    }
+
+    /// List up to `limit` deliveries due at or before `now`, oldest first.
+    pub fn list_due_deliveries(&self, now: u128, limit: usize) -> Result<Vec<DeliveryData>, Error> {
+        let lower = serialize_key(vec!["webhook_delivery".as_bytes(), b""]);
+        let upper = serialize_key(vec![
+            "webhook_delivery".as_bytes(),
+            &time_part(now.saturating_add(1)),
+        ]);
+
+        let page = self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["webhook_delivery", time, id]
+                let key_parts = deserialize_key(key);
+                let time_due = parse_number_part(&key_parts[1]);
+                let id = std::str::from_utf8(&key_parts[2]).unwrap();
+                let value = serde_json::from_slice::<DeliveryValue>(value).unwrap();
+                Ok(Some(DeliveryData {
+                    id: id.to_string(),
+                    webhook: value.webhook,
+                    event: value.event,
+                    payload: value.payload,
+                    attempts: value.attempts,
+                    time_due,
+                }))
+            },
+            None,
+            Pagination {
+                limit: Some(limit),
+                cursor: None,
+            },
+        )?;
+        Ok(page.items)
+    }
+
+    /// The sequence number of the last event appended, 0 if there is none.
+    pub fn get_last_event_sequence(&self) -> Result<u64, Error> {
+        let lower = serialize_key(vec!["event".as_bytes(), b""]);
+        let upper = prefix_upper_bound(&lower);
+
+        let page = self.get_by_range(
+            lower,
+            upper,
+            |key, _| Ok(Some(event_sequence(key))),
+            Some(true),
+            Pagination {
+                limit: Some(1),
+                cursor: None,
+            },
+        )?;
+        Ok(page.items.first().copied().unwrap_or(0))
+    }
+
+    /// List events with their sequence numbers, oldest first.
+    pub fn list_events(&self, params: ListEventsParams) -> Result<Page<(u64, Event)>, Error> {
+        let lower = serialize_key(vec![
+            "event".as_bytes(),
+            &sequence_part(params.after.saturating_add(1)),
+        ]);
+        let upper = prefix_upper_bound(&serialize_key(vec!["event".as_bytes(), b""]));
+        let scope = params.scope.as_ref();
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                let event = serde_json::from_slice::<Event>(value).unwrap();
+                if let Some(scope) = scope
+                    && (&event.server != scope.server
+                        || &event.owner != scope.owner
+                        || &event.repo != scope.repo)
+                {
+                    return Ok(None);
+                }
+                Ok(Some((event_sequence(key), event)))
+            },
+            None,
+            params.pagination,
+        )
+    }
+
+    /// List audit log entries, newest first.
+    pub fn list_audit(&self, params: ListAuditParams) -> Result<Page<AuditData>, Error> {
+        let audit_key = |time: u128| serialize_key(vec!["audit".as_bytes(), &time_part(time)]);
+        let key_prefix = serialize_key(vec!["audit".as_bytes()]);
+        let lower = match params.since {
+            Some(since) => audit_key(time_to_nanos(since)),
+            None => [key_prefix.as_slice(), b"#"].concat(),
+        };
+        let upper = match params.until {
+            Some(until) => audit_key(time_to_nanos(until) + 1),
+            None => [key_prefix.as_slice(), b"$"].concat(),
+        };
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| {
+                // parts: ["audit", time, request_id]
+                let key_parts = deserialize_key(key);
+                let value = serde_json::from_slice::<AuditValue>(value).unwrap();
+                if params.action.is_some_and(|action| action != &value.action)
+                    || params
+                        .identity
+                        .is_some_and(|identity| Some(identity) != value.identity.as_ref())
+                    || !has_labels(&value.params, &params.params)
+                {
+                    return Ok(None);
+                }
+                Ok(Some(AuditData {
+                    time: extract_time(&key_parts[1]),
+                    request_id: std::str::from_utf8(&key_parts[2]).unwrap().to_string(),
+                    action: value.action,
+                    method: value.method,
+                    uri: value.uri,
+                    status: value.status,
+                    client: value.client,
+                    identity: value.identity,
+                    params: value.params,
+                }))
+            },
+            Some(true),
+            params.pagination,
+        )
+    }
+
+    pub fn get_token(&self, id: &String) -> Result<Option<TokenData>, Error> {
+        let key = serialize_key(vec!["token".as_bytes(), id.as_bytes()]);
+        let value = match self {
+            Database::RocksDB(db) => db.get(&key)?,
+        };
+        Ok(value.map(|value| token_data(&key, &value)))
+    }
+
+    /// List tokens ordered by id.
+    pub fn list_tokens(&self, pagination: Pagination) -> Result<Page<TokenData>, Error> {
+        let lower = serialize_key(vec!["token".as_bytes(), b""]);
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| Ok(Some(token_data(key, value))),
+            None,
+            pagination,
+        )
+    }
+
+    pub fn get_acl(&self, target: AclTarget) -> Result<Option<AclData>, Error> {
+        let key = acl_key(target);
+        let value = match self {
+            Database::RocksDB(db) => db.get(&key)?,
+        };
+        Ok(value.map(|value| acl_data(&key, &value)))
+    }
+
+    /// List ACLs ordered by server, owner and repository, each level before the ones below it.
+    pub fn list_acls(&self, pagination: Pagination) -> Result<Page<AclData>, Error> {
+        let lower = serialize_key(vec!["acl".as_bytes(), b""]);
+        let upper = prefix_upper_bound(&lower);
+
+        self.get_by_range(
+            lower,
+            upper,
+            |key, value| Ok(Some(acl_data(key, value))),
+            None,
+            pagination,
+        )
+    }
+
+    /// Iterates over all keys in `[lower, upper)`, in reverse order if `reverse` is set.
+    /// Items for which `func` returns `None` are skipped and don't count towards the limit.
+    /// When `pagination.cursor` is given, iteration resumes right after the key it encodes.
+    fn get_by_range<T>(
+        &self,
+        lower: Vec<u8>,
+        upper: Vec<u8>,
+        func: impl Fn(&[u8], &[u8]) -> Result<Option<T>, Error>,
+        reverse: Option<bool>,
+        pagination: Pagination,
+    ) -> Result<Page<T>, Error> {
+        if pagination.limit == Some(0) {
+            return Err(Error::InvalidArgument(
+                "limit must be greater than 0".to_string(),
+            ));
+        }
+        let cursor = match pagination.cursor {
+            Some(cursor) => {
+                let key = decode_cursor(cursor)?;
+                if key < lower || key >= upper {
+                    return Err(Error::InvalidArgument(format!("invalid cursor: {cursor}")));
+                }
+                Some(key)
+            }
+            None => None,
+        };
+
+        let mut result: Vec<T> = Vec::new();
+        let mut next_cursor = None;
+        match self {
+            Database::RocksDB(db) => {
+                let mut opts = rocksdb::ReadOptions::default();
+                opts.set_iterate_lower_bound(lower.clone());
+                opts.set_iterate_upper_bound(upper.clone());
+                let mut iter = db.raw_iterator_opt(opts);
+                let should_reverse = reverse.unwrap_or(false);
+                match &cursor {
+                    Some(cursor) if should_reverse => iter.seek_for_prev(cursor),
+                    Some(cursor) => iter.seek(cursor),
+                    None if should_reverse => iter.seek_for_prev(&upper),
+                    None => iter.seek(&lower),
+                }
+                // the cursor points at the last item of the previous page
+                if iter.valid() && cursor.is_some() && iter.key() == cursor.as_deref() {
+                    if should_reverse {
+                        iter.prev();
+                    } else {
+                        iter.next();
+                    }
+                }
+
+                let mut last_key: Vec<u8> = Vec::new();
+                while iter.valid() {
+                    let raw_key = iter.key().unwrap();
+                    let raw_value = iter.value().unwrap();
+                    if let Some(value) = func(raw_key, raw_value)? {
+                        if pagination.limit == Some(result.len()) {
+                            next_cursor = Some(encode_cursor(&last_key));
+                            break;
+                        }
+                        result.push(value);
+                        if pagination.limit.is_some() {
+                            last_key = raw_key.to_vec();
+                        }
+                    }
+                    if should_reverse {
+                        iter.prev();
+                    } else {
+                        iter.next();
+                    }
+                }
+                iter.status()?;
+            }
+        }
+        Ok(Page {
+            items: result,
+            next_cursor,
+        })
+    }
+}
+
+type Migration =
+    fn(&TransactionDB, &rocksdb::Transaction<TransactionDB>) -> Result<(), rocksdb::Error>;
+
+/// Rewrites of keys stored by earlier versions, in the order they're run. Each one is recorded
+/// under `migration#{name}` once done, so that it only runs once.
+const MIGRATIONS: &[(&str, Migration)] = &[("commit_time_keys", migrate_commit_time_keys)];
+
+fn migrate(db: &TransactionDB) -> Result<(), rocksdb::Error> {
+    for (name, migration) in MIGRATIONS {
+        run_migration(db, name, |tx| migration(db, tx))?;
+    }
+    Ok(())
+}
+
+/// Runs `migration` in a transaction recording it as done, unless it already is.
+fn run_migration(
+    db: &TransactionDB,
+    name: &str,
+    migration: impl FnOnce(&rocksdb::Transaction<TransactionDB>) -> Result<(), rocksdb::Error>,
+) -> Result<(), rocksdb::Error> {
+    let key = serialize_key(vec!["migration".as_bytes(), name.as_bytes()]);
+    if db.get(&key)?.is_some() {
+        return Ok(());
+    }
+    let tx = db.transaction();
+    migration(&tx)?;
+    tx.put(key, b"{}")?;
+    tx.commit()
+}
+
+/// Calls `func` with the parts and value of every key in `namespace`.
+fn for_each_key(
+    db: &TransactionDB,
+    namespace: &str,
+    mut func: impl FnMut(&[u8], Vec<Vec<u8>>, &[u8]) -> Result<(), rocksdb::Error>,
+) -> Result<(), rocksdb::Error> {
+    let lower = serialize_key(vec![namespace.as_bytes(), b""]);
+    let mut opts = rocksdb::ReadOptions::default();
+    opts.set_iterate_upper_bound(prefix_upper_bound(&lower));
+    let mut iter = db.raw_iterator_opt(opts);
+    iter.seek(&lower);
+    while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+        func(key, deserialize_key(key), value)?;
+        iter.next();
+    }
+    iter.status()
+}
+
+/// Rewrite the times in `commit_time` keys stored as big-endian bytes, which didn't sort in
+/// time order, into the hex of `time_part`.
+fn migrate_commit_time_keys(
+    db: &TransactionDB,
+    tx: &rocksdb::Transaction<TransactionDB>,
+) -> Result<(), rocksdb::Error> {
+    for_each_key(db, "commit_time", |key, mut key_parts, value| {
+        // parts: ["commit_time", server, owner, repo, time]
+        if key_parts.get(4).is_some_and(|part| part.len() == 16) {
+            let time = u128::from_be_bytes(key_parts[4][..].try_into().unwrap());
+            key_parts[4] = time_part(time);
+            tx.put(
+                serialize_key(key_parts.iter().map(Vec::as_slice).collect()),
+                value,
+            )?;
+            tx.delete(key)?;
+        }
+        Ok(())
+    })
+}
+
+pub enum Transaction<'db> {
+    RocksDB(rocksdb::Transaction<'db, TransactionDB>),
+}
+
+impl Transaction<'_> {
+    /// Stores the repository data in the database.
+    /// Returns the event of the change, or `None` if the repository already exists.
+    pub fn create_repo_if_not_exists(
+        &self,
+        time: u128,
+        params: CreateRepositoryParams,
+    ) -> Result<Option<Event>, Error> {
+        let key = serialize_key(vec![
+            "repo".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+        ]);
+        let value = RepoValue { time_added: time };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let exists = tx.get(&key)?.is_some();
+                if exists {
+                    return Ok(None);
+                }
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+            }
+        }
+        let event = Event::new(
+            EventKind::RepoCreated,
+            time,
+            params.server,
+            params.owner,
+            params.repo,
+        );
+        self.enqueue_event(&event)?;
+        Ok(Some(event))
+    }
+
+    /// Store the commit data in the database.
+    /// Returns the event of the change, or `None` if the commit already exists.
+    pub fn create_commit_if_not_exists(
+        &self,
+        time: u128,
+        params: CreateCommitParams,
+    ) -> Result<Option<Event>, Error> {
+        let commit_key = serialize_key(vec![
+            "commit".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.commit.as_bytes(),
+        ]);
+        let commit_value = CommitValue {
+            time_added: time,
+            status: CommitStatus::Pending,
+            time_sealed: None,
+            metadata: params.metadata.clone(),
+        };
+
+        let commit_time_key = serialize_key(vec![
+            "commit_time".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            &time_part(time),
+        ]);
+        let commit_time_value = CommitTimeValue {
+            commit: params.commit.clone(),
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let exists = tx.get(&commit_key)?.is_some();
+                if exists {
+                    return Ok(None);
+                }
+                tx.put(
+                    commit_key,
+                    serde_json::to_string(&commit_value).unwrap().as_bytes(),
+                )?;
+                tx.put(
+                    commit_time_key,
+                    serde_json::to_string(&commit_time_value)
+                        .unwrap()
+                        .as_bytes(),
+                )?;
+                for (name, value) in &params.metadata.labels {
+                    tx.put(
+                        commit_label_key(
+                            params.server,
+                            params.owner,
+                            params.repo,
+                            name,
+                            value,
+                            time,
+                        ),
+                        serde_json::to_string(&commit_time_value)
+                            .unwrap()
+                            .as_bytes(),
+                    )?;
+                }
+            }
+        }
+        let event = Event {
+            commit: Some(params.commit.clone()),
+            ..Event::new(
+                EventKind::CommitCreated,
+                time,
+                params.server,
+                params.owner,
+                params.repo,
+            )
+        };
+        self.enqueue_event(&event)?;
+        Ok(Some(event))
+    }
+
+    /// Merge the metadata into an existing commit.
+    /// If the commit does not exist, return an error.
+    pub fn update_commit_metadata(&self, params: UpdateCommitMetadataParams) -> Result<(), Error> {
+        let commit_key = serialize_key(vec![
+            "commit".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.commit.as_bytes(),
+        ]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let mut value = match tx.get_for_update(&commit_key, true)? {
+                    Some(value) => serde_json::from_slice::<CommitValue>(&value).unwrap(),
+                    None => {
+                        return Err(Error::Generic(format!(
+                            "commit {} does not exist",
+                            params.commit
+                        )));
+                    }
+                };
+                let commit_time_value = CommitTimeValue {
+                    commit: params.commit.clone(),
+                };
+                for (name, label_value) in &params.metadata.labels {
+                    let time = value.time_added;
+                    match value.metadata.labels.get(name) {
+                        Some(current) if current == label_value => continue,
+                        Some(current) => tx.delete(commit_label_key(
+                            params.server,
+                            params.owner,
+                            params.repo,
+                            name,
+                            current,
+                            time,
+                        ))?,
+                        None => (),
+                    }
+                    tx.put(
+                        commit_label_key(
+                            params.server,
+                            params.owner,
+                            params.repo,
+                            name,
+                            label_value,
+                            time,
+                        ),
+                        serde_json::to_string(&commit_time_value)
+                            .unwrap()
+                            .as_bytes(),
+                    )?;
+                }
+                value.metadata.merge(params.metadata.clone());
+                tx.put(
+                    commit_key,
+                    serde_json::to_string(&value).unwrap().as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Seal an existing commit, so that no more artifacts can be added to it.
+    /// Sealing is permanent, sealing a sealed commit again keeps the original seal.
+    pub fn seal_commit(&self, time: u128, params: SealCommitParams) -> Result<(), Error> {
+        let commit_key = serialize_key(vec![
+            "commit".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.commit.as_bytes(),
+        ]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let mut value = match tx.get_for_update(&commit_key, true)? {
+                    Some(value) => serde_json::from_slice::<CommitValue>(&value).unwrap(),
+                    None => {
+                        return Err(Error::Generic(format!(
+                            "commit {} does not exist",
+                            params.commit
+                        )));
+                    }
+                };
+                if value.time_sealed.is_some() {
+                    return Ok(());
+                }
+                value.time_sealed = Some(time);
+                tx.put(
+                    commit_key,
+                    serde_json::to_string(&value).unwrap().as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return a locked error if the commit is sealed. Every path that modifies
+    /// the artifacts of a commit must call this within its transaction.
+    pub fn ensure_not_sealed(
+        &self,
+        server: &String,
+        owner: &String,
+        repo: &String,
+        commit: &String,
+    ) -> Result<(), Error> {
+        let commit_key = serialize_key(vec![
+            "commit".as_bytes(),
+            server.as_bytes(),
+            owner.as_bytes(),
+            repo.as_bytes(),
+            commit.as_bytes(),
+        ]);
+
+        let value = match self {
+            // lock the commit so that it can't be sealed concurrently
+            Transaction::RocksDB(tx) => tx.get_for_update(&commit_key, false)?,
+        };
+        let sealed = value
+            .map(|value| serde_json::from_slice::<CommitValue>(&value).unwrap())
+            .is_some_and(|value| value.time_sealed.is_some());
+        if sealed {
+            return Err(Error::Locked(format!("commit {commit} is sealed")));
+        }
+        Ok(())
+    }
+
+    /// Move an existing commit to a new status.
+    /// If the transition is not allowed, return a conflict error.
+    /// Returns the event of the change, or `None` if the status is unchanged.
+    pub fn set_commit_status(
+        &self,
+        time: u128,
+        params: SetCommitStatusParams,
+    ) -> Result<Option<Event>, Error> {
+        let commit_key = serialize_key(vec![
+            "commit".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.commit.as_bytes(),
+        ]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let mut value = match tx.get_for_update(&commit_key, true)? {
+                    Some(value) => serde_json::from_slice::<CommitValue>(&value).unwrap(),
+                    None => {
+                        return Err(Error::Generic(format!(
+                            "commit {} does not exist",
+                            params.commit
+                        )));
+                    }
+                };
+                if !value.status.can_transition_to(params.status) {
+                    return Err(Error::Conflict(format!(
+                        "commit {} can't move from {} to {}",
+                        params.commit, value.status, params.status
+                    )));
+                }
+                if value.status == params.status {
+                    return Ok(None);
+                }
+                let previous_status = value.status;
+                value.status = params.status;
+                tx.put(
+                    commit_key,
+                    serde_json::to_string(&value).unwrap().as_bytes(),
+                )?;
+                let event = Event {
+                    commit: Some(params.commit.clone()),
+                    status: Some(params.status),
+                    previous_status: Some(previous_status),
+                    ..Event::new(
+                        EventKind::CommitStatusChanged,
+                        time,
+                        params.server,
+                        params.owner,
+                        params.repo,
+                    )
+                };
+                self.enqueue_event(&event)?;
+                Ok(Some(event))
+            }
+        }
+    }
+
+    /// Store the artifact data in the database.
+    /// If the artifact already exists, return an error.
+    pub fn create_artifact(
+        &self,
+        time: u128,
+        params: CreateArtifactParams,
+    ) -> Result<Event, Error> {
+        let key = artifact_key(
+            params.server,
+            params.owner,
+            params.repo,
+            params.commit,
+            params.path.as_bytes(),
+        );
+        let value = ArtifactValue {
+            time_added: time,
+            labels: params.labels.clone(),
+        };
+
+        self.ensure_not_sealed(params.server, params.owner, params.repo, params.commit)?;
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let exists = tx.get(&key)?.is_some();
+                if exists {
+                    return Err(Error::Generic(format!(
+                        "artifact already exists: {}",
+                        params.path
+                    )));
+                }
+
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+                for (name, label_value) in params.labels {
+                    let label_key = artifact_label_key(
+                        params.server,
+                        params.owner,
+                        params.repo,
+                        params.commit,
+                        name,
+                        label_value,
+                        params.path,
+                    );
+                    tx.put(label_key, b"{}")?;
+                }
+            }
+        }
+        let event = Event {
+            commit: Some(params.commit.clone()),
+            path: Some(params.path.clone()),
+            ..Event::new(
+                EventKind::ArtifactCreated,
+                time,
+                params.server,
+                params.owner,
+                params.repo,
+            )
+        };
+        self.enqueue_event(&event)?;
+        Ok(event)
+    }
+
+    /// Point a channel at a commit and record the promotion.
+    /// Unlike refs, every promotion is recorded, even if the commit doesn't change.
+    pub fn promote(&self, time: u128, params: PromoteParams) -> Result<(), Error> {
+        let key = serialize_key(vec![
+            "channel".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.channel.as_bytes(),
+        ]);
+        let value = ChannelValue {
+            commit: params.commit.clone(),
+            time_promoted: time,
+        };
+
+        let promotion_key = serialize_key(vec![
+            "promotion".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.channel.as_bytes(),
+            &time_part(time),
+        ]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let previous = tx
+                    .get_for_update(&key, true)?
+                    .map(|current| serde_json::from_slice::<ChannelValue>(&current).unwrap())
+                    .map(|current| current.commit);
+                let promotion_value = PromotionValue {
+                    commit: params.commit.clone(),
+                    previous,
+                    identity: params.actor.cloned(),
+                    claimed_actor: params.claimed_actor.cloned(),
+                    reason: params.reason.cloned(),
+                };
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+                tx.put(
+                    promotion_key,
+                    serde_json::to_string(&promotion_value).unwrap().as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Open an upload session for a commit.
+    pub fn create_session(&self, time: u128, params: CreateSessionParams) -> Result<(), Error> {
+        let key = serialize_key(vec!["session".as_bytes(), params.id.as_bytes()]);
+        let value = SessionData {
+            server: params.server.clone(),
+            owner: params.owner.clone(),
+            repo: params.repo.clone(),
+            commit: params.commit.clone(),
+            time_created: time,
+            metadata: params.metadata.clone(),
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record an artifact uploaded into a session, invisible until the session is published.
+    /// The session is rewritten, so that publishing or discarding it meanwhile
+    /// conflicts with the upload.
+    /// If the session doesn't exist or the path is already staged, return an error.
+    pub fn stage_artifact(&self, time: u128, params: StageArtifactParams) -> Result<(), Error> {
+        let session_key = serialize_key(vec!["session".as_bytes(), params.session.as_bytes()]);
+        let key = serialize_key(vec![
+            "session_artifact".as_bytes(),
+            params.session.as_bytes(),
+            params.path.as_bytes(),
+        ]);
+        let value = ArtifactValue {
+            time_added: time,
+            labels: params.labels.clone(),
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let Some(session) = tx.get_for_update(&session_key, true)? else {
+                    return Err(Error::Generic(format!(
+                        "session {} does not exist",
+                        params.session
+                    )));
+                };
+                tx.put(&session_key, session)?;
+                if tx.get(&key)?.is_some() {
+                    return Err(Error::Conflict(format!(
+                        "artifact already staged: {}",
+                        params.path
+                    )));
+                }
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// List the artifacts staged in a session, ordered by path.
+    /// The transaction conflicts with an upload into the session committed meanwhile,
+    /// so none is left out.
+    pub fn list_session_artifacts(&self, id: &String) -> Result<Vec<ArtifactData>, Error> {
+        let key = serialize_key(vec!["session".as_bytes(), id.as_bytes()]);
+        let lower = serialize_key(vec!["session_artifact".as_bytes(), id.as_bytes(), b""]);
+        let upper = prefix_upper_bound(&lower);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.get_for_update(&key, true)?;
+                let mut opts = rocksdb::ReadOptions::default();
+                opts.set_iterate_lower_bound(lower.clone());
+                opts.set_iterate_upper_bound(upper);
+                let mut iter = tx.raw_iterator_opt(opts);
+                iter.seek(&lower);
+                let mut artifacts = Vec::new();
+                while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+                    // parts: ["session_artifact", session, path]
+                    let key_parts = deserialize_key(key);
+                    let path = std::str::from_utf8(key_parts.last().unwrap()).unwrap();
+                    let value = serde_json::from_slice::<ArtifactValue>(value).unwrap();
+                    artifacts.push(ArtifactData {
+                        path: path.to_string(),
+                        time_added: nanos_to_time(value.time_added),
+                        labels: value.labels,
+                    });
+                    iter.next();
+                }
+                iter.status()?;
+                Ok(artifacts)
+            }
+        }
+    }
+
+    /// Remove a session and its staged artifacts.
+    /// If the session doesn't exist, e.g. it was published or aborted concurrently, return an error.
+    pub fn delete_session(&self, id: &String, paths: &[String]) -> Result<(), Error> {
+        let key = serialize_key(vec!["session".as_bytes(), id.as_bytes()]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                if tx.get_for_update(&key, true)?.is_none() {
+                    return Err(Error::Generic(format!("session {id} does not exist")));
+                }
+                tx.delete(key)?;
+                for path in paths {
+                    tx.delete(serialize_key(vec![
+                        "session_artifact".as_bytes(),
+                        id.as_bytes(),
+                        path.as_bytes(),
+                    ]))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Point a ref at a commit, replacing its previous target if any.
+    /// The ref's history records every change of its target.
+    pub fn set_ref(&self, time: u128, params: SetRefParams) -> Result<(), Error> {
+        let key = serialize_key(vec![
+            "ref".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.name.as_bytes(),
+        ]);
+        let value = RefValue {
+            commit: params.commit.clone(),
+            time_updated: time,
+        };
+
+        let history_key = serialize_key(vec![
+            "ref_history".as_bytes(),
+            params.server.as_bytes(),
+            params.owner.as_bytes(),
+            params.repo.as_bytes(),
+            params.name.as_bytes(),
+            &time_part(time),
+        ]);
+        let history_value = RefHistoryValue {
+            commit: params.commit.clone(),
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let unchanged = match tx.get(&key)? {
+                    Some(current) => {
+                        serde_json::from_slice::<RefValue>(&current).unwrap().commit
+                            == *params.commit
+                    }
+                    None => false,
+                };
+                if unchanged {
+                    return Ok(());
+                }
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+                tx.put(
+                    history_key,
+                    serde_json::to_string(&history_value).unwrap().as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_webhook(&self, time: u128, params: CreateWebhookParams) -> Result<(), Error> {
+        let key = serialize_key(vec!["webhook".as_bytes(), params.id.as_bytes()]);
+        let value = WebhookValue {
+            url: params.url.clone(),
+            secret: params.secret.clone(),
+            events: params.events.to_vec(),
+            server: params.scope.as_ref().map(|scope| scope.server.clone()),
+            owner: params.scope.as_ref().map(|scope| scope.owner.clone()),
+            repo: params.scope.as_ref().map(|scope| scope.repo.clone()),
+            time_added: time,
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a webhook. Its pending deliveries are dropped when they are due.
+    /// If the webhook does not exist, return an error.
+    pub fn delete_webhook(&self, id: &String) -> Result<(), Error> {
+        let key = serialize_key(vec!["webhook".as_bytes(), id.as_bytes()]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                if tx.get_for_update(&key, true)?.is_none() {
+                    return Err(Error::Generic(format!("webhook {id} does not exist")));
+                }
+                tx.delete(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue a delivery of the event to every webhook subscribed to it.
+    /// Deliveries are written in the same transaction as the change, so they
+    /// are only sent if the change is committed.
+    fn enqueue_event(&self, event: &Event) -> Result<(), Error> {
+        let lower = serialize_key(vec!["webhook".as_bytes(), b""]);
+        let upper = prefix_upper_bound(&lower);
+        let payload = serde_json::to_string(&event).unwrap();
+        let time = time_to_nanos(event.time);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                let mut opts = rocksdb::ReadOptions::default();
+                opts.set_iterate_lower_bound(lower.clone());
+                opts.set_iterate_upper_bound(upper);
+                let mut iter = tx.raw_iterator_opt(opts);
+                iter.seek(&lower);
+                let mut webhooks = Vec::new();
+                while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+                    webhooks.push(webhook_data(key, value));
+                    iter.next();
+                }
+                iter.status()?;
+
+                for webhook in webhooks {
+                    let subscribed =
+                        webhook.events.is_empty() || webhook.events.contains(&event.kind);
+                    let in_scope = webhook.server.is_none()
+                        || (webhook.server.as_ref() == Some(&event.server)
+                            && webhook.owner.as_ref() == Some(&event.owner)
+                            && webhook.repo.as_ref() == Some(&event.repo));
+                    if !subscribed || !in_scope {
+                        continue;
+                    }
+                    let value = DeliveryValue {
+                        webhook: webhook.id,
+                        event: event.kind,
+                        payload: payload.clone(),
+                        attempts: 0,
+                    };
+                    let id = uuid::Uuid::new_v4().simple().to_string();
+                    tx.put(
+                        delivery_key(time, &id),
+                        serde_json::to_string(&value).unwrap().as_bytes(),
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_token(&self, time: u128, params: CreateTokenParams) -> Result<(), Error> {
+        let key = serialize_key(vec!["token".as_bytes(), params.id.as_bytes()]);
+        let value = TokenValue {
+            name: params.name.clone(),
+            hash: params.hash.clone(),
+            scopes: params.scopes.to_vec(),
+            server: params.server.cloned(),
+            owner: params.owner.cloned(),
+            repo: params.repo.cloned(),
+            time_added: time,
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Revoke a token by removing it.
+    /// If the token does not exist, return an error.
+    pub fn delete_token(&self, id: &String) -> Result<(), Error> {
+        let key = serialize_key(vec!["token".as_bytes(), id.as_bytes()]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                if tx.get_for_update(&key, true)?.is_none() {
+                    return Err(Error::Generic(format!("token {id} does not exist")));
+                }
+                tx.delete(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Attach an ACL to a server, owner or repository, replacing the one already attached.
+    pub fn set_acl(
+        &self,
+        time: u128,
+        target: AclTarget,
+        entries: &[AclEntry],
+    ) -> Result<(), Error> {
+        let value = AclValue {
+            entries: entries.to_vec(),
+            time_updated: time,
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.put(
+                    acl_key(target),
+                    serde_json::to_string(&value).unwrap().as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the ACL of a server, owner or repository.
+    /// If there is none, return an error.
+    pub fn delete_acl(&self, target: AclTarget) -> Result<(), Error> {
+        let key = acl_key(target);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                if tx.get_for_update(&key, true)?.is_none() {
+                    return Err(Error::Generic("acl does not exist".to_string()));
+                }
+                tx.delete(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append an entry to the audit log. Entries are never changed or removed.
+    pub fn append_audit(&self, time: u128, params: AppendAuditParams) -> Result<(), Error> {
+        let key = serialize_key(vec![
+            "audit".as_bytes(),
+            &time_part(time),
+            params.request_id.as_bytes(),
+        ]);
+        let value = AuditValue {
+            action: params.action.clone(),
+            method: params.method.clone(),
+            uri: params.uri.clone(),
+            status: params.status,
+            client: params.client.cloned(),
+            identity: params.identity.cloned(),
+            params: params.params.clone(),
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.put(key, serde_json::to_string(&value).unwrap().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append an event to the event log under the given sequence number.
+    pub fn append_event(&self, sequence: u64, event: &Event) -> Result<(), Error> {
+        let key = serialize_key(vec!["event".as_bytes(), &sequence_part(sequence)]);
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.put(key, serde_json::to_string(event).unwrap().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a delivery from the queue, after it's sent or given up on.
+    pub fn remove_delivery(&self, delivery: &DeliveryData) -> Result<(), Error> {
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.delete(delivery_key(delivery.time_due, &delivery.id))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move a failed delivery to `time_due` and count the failed attempt.
+    pub fn retry_delivery(&self, delivery: &DeliveryData, time_due: u128) -> Result<(), Error> {
+        let value = DeliveryValue {
+            webhook: delivery.webhook.clone(),
+            event: delivery.event,
+            payload: delivery.payload.clone(),
+            attempts: delivery.attempts + 1,
+        };
+
+        match self {
+            Transaction::RocksDB(tx) => {
+                tx.delete(delivery_key(delivery.time_due, &delivery.id))?;
+                tx.put(
+                    delivery_key(time_due, &delivery.id),
+                    serde_json::to_string(&value).unwrap().as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn commit(self) -> Result<(), rocksdb::Error> {
+        match self {
+            Transaction::RocksDB(tx) => tx.commit(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    RocksDB(rocksdb::Error),
+    Generic(String),
+    InvalidArgument(String),
+    Conflict(String),
+    Locked(String),
+}
+
+impl From<rocksdb::Error> for Error {
+    fn from(e: rocksdb::Error) -> Self {
+        Error::RocksDB(e)
+    }
+}
+
+impl From<String> for Error {
+    fn from(e: String) -> Self {
+        Error::Generic(e)
+    }
+}
+
+fn serialize_key(parts: Vec<&[u8]>) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::new();
+    for i in 0..parts.len() {
+        let part = parts[i];
+        for byte in part {
+            if *byte == b'#' || *byte == b'\\' {
+                result.push(b'\\');
+            }
+            result.push(*byte);
+        }
+        if i < parts.len() - 1 {
+            result.push(b'#');
+        }
+    }
+    result
+}
+
+fn deserialize_key(key: &[u8]) -> Vec<Vec<u8>> {
+    let mut result: Vec<Vec<u8>> = Vec::new();
+    let mut part: Vec<u8> = Vec::new();
     let mut escape = false;
     for byte in key {
         if escape {
@@ -485,234 +2664,1576 @@ fn deserialize_key(key: &[u8]) -> Vec<Vec<u8>> {
         } else {
             part.push(*byte);
         }
-    }
-    result.push(part);
-    result
-}
+    }
+    result.push(part);
+    result
+}
+
+fn commit_label_key(
+    server: &str,
+    owner: &str,
+    repo: &str,
+    name: &str,
+    value: &str,
+    time: u128,
+) -> Vec<u8> {
+    serialize_key(vec![
+        "commit_label".as_bytes(),
+        server.as_bytes(),
+        owner.as_bytes(),
+        repo.as_bytes(),
+        name.as_bytes(),
+        value.as_bytes(),
+        &time_part(time),
+    ])
+}
+
+fn artifact_key(server: &str, owner: &str, repo: &str, commit: &str, path: &[u8]) -> Vec<u8> {
+    serialize_key(vec![
+        "artifact".as_bytes(),
+        server.as_bytes(),
+        owner.as_bytes(),
+        repo.as_bytes(),
+        commit.as_bytes(),
+        path,
+    ])
+}
+
+fn artifact_label_key(
+    server: &str,
+    owner: &str,
+    repo: &str,
+    commit: &str,
+    name: &str,
+    value: &str,
+    path: &str,
+) -> Vec<u8> {
+    serialize_key(vec![
+        "artifact_label".as_bytes(),
+        server.as_bytes(),
+        owner.as_bytes(),
+        repo.as_bytes(),
+        commit.as_bytes(),
+        name.as_bytes(),
+        value.as_bytes(),
+        path.as_bytes(),
+    ])
+}
+
+fn token_data(key: &[u8], value: &[u8]) -> TokenData {
+    // parts: ["token", id]
+    let key_parts = deserialize_key(key);
+    let id = std::str::from_utf8(&key_parts[1]).unwrap();
+    let value = serde_json::from_slice::<TokenValue>(value).unwrap();
+    TokenData {
+        id: id.to_string(),
+        name: value.name,
+        hash: value.hash,
+        scopes: value.scopes,
+        server: value.server,
+        owner: value.owner,
+        repo: value.repo,
+        time_added: nanos_to_time(value.time_added),
+    }
+}
+
+fn acl_key(target: AclTarget) -> Vec<u8> {
+    let mut key_parts = vec!["acl".as_bytes(), target.server.as_bytes()];
+    if let Some(owner) = target.owner {
+        key_parts.push(owner.as_bytes());
+        if let Some(repo) = target.repo {
+            key_parts.push(repo.as_bytes());
+        }
+    }
+    serialize_key(key_parts)
+}
+
+fn acl_data(key: &[u8], value: &[u8]) -> AclData {
+    // parts: ["acl", server, owner?, repo?]
+    let key_parts = deserialize_key(key);
+    let part = |i: usize| {
+        key_parts
+            .get(i)
+            .map(|part| std::str::from_utf8(part).unwrap().to_string())
+    };
+    let value = serde_json::from_slice::<AclValue>(value).unwrap();
+    AclData {
+        server: part(1).unwrap(),
+        owner: part(2),
+        repo: part(3),
+        entries: value.entries,
+        time_updated: nanos_to_time(value.time_updated),
+    }
+}
+
+fn webhook_data(key: &[u8], value: &[u8]) -> WebhookData {
+    // parts: ["webhook", id]
+    let key_parts = deserialize_key(key);
+    let id = std::str::from_utf8(&key_parts[1]).unwrap();
+    let value = serde_json::from_slice::<WebhookValue>(value).unwrap();
+    WebhookData {
+        id: id.to_string(),
+        url: value.url,
+        secret: value.secret,
+        events: value.events,
+        server: value.server,
+        owner: value.owner,
+        repo: value.repo,
+        time_added: nanos_to_time(value.time_added),
+    }
+}
+
+fn event_sequence(key: &[u8]) -> u64 {
+    // parts: ["event", sequence]
+    let key_parts = deserialize_key(key);
+    parse_number_part(&key_parts[1]) as u64
+}
+
+fn delivery_key(time_due: u128, id: &str) -> Vec<u8> {
+    serialize_key(vec![
+        "webhook_delivery".as_bytes(),
+        &time_part(time_due),
+        id.as_bytes(),
+    ])
+}
+
+/// Whether `labels` contains every label in `filter`.
+fn has_labels(labels: &Labels, filter: &Labels) -> bool {
+    filter
+        .iter()
+        .all(|(name, value)| labels.get(name) == Some(value))
+}
+
+/// Returns the smallest key greater than every key starting with `prefix`.
+fn prefix_upper_bound(prefix: &[u8]) -> Vec<u8> {
+    let mut upper = prefix.to_vec();
+    while upper.last() == Some(&u8::MAX) {
+        upper.pop();
+    }
+    if let Some(last) = upper.last_mut() {
+        *last += 1;
+    }
+    upper
+}
+
+/// Encodes a raw key as an opaque cursor string.
+fn encode_cursor(key: &[u8]) -> String {
+    key.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::InvalidArgument(format!("invalid cursor: {cursor}"));
+    if !cursor.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..cursor.len())
+        .step_by(2)
+        .map(|i| {
+            cursor
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// Encodes a time as a key part, in fixed-width hex so that keys sort in time order.
+/// Big-endian bytes don't, as `serialize_key` escapes the `#` and `\\` bytes among them.
+fn time_part(time: u128) -> Vec<u8> {
+    format!("{time:032x}").into_bytes()
+}
+
+/// Encodes a sequence number as a key part, in fixed-width hex like `time_part`.
+fn sequence_part(sequence: u64) -> Vec<u8> {
+    format!("{sequence:016x}").into_bytes()
+}
+
+fn parse_number_part(part: &[u8]) -> u128 {
+    u128::from_str_radix(std::str::from_utf8(part).unwrap(), 16).unwrap()
+}
+
+fn extract_time(bytes: &[u8]) -> OffsetDateTime {
+    nanos_to_time(parse_number_part(bytes))
+}
+
+/// Converts nanoseconds since epoch, as stored by writers, into a timestamp
+/// without dropping the sub-second part.
+fn nanos_to_time(time_nano: u128) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp_nanos(time_nano as i128).unwrap()
+}
+
+/// Converts a timestamp into nanoseconds since epoch, clamping times before epoch.
+fn time_to_nanos(time: OffsetDateTime) -> u128 {
+    time.unix_timestamp_nanos().max(0) as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const NANOSECONDS_PER_SECOND: i64 = 1_000_000_000;
+
+    fn remove_db(path: &str) {
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let key = vec![[0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 20, 192].as_ref()];
+        let serialized = serialize_key(key);
+        assert_eq!(
+            serialized,
+            vec![0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 92, 20, 192]
+        );
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let key = vec![0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 92, 20, 192];
+        let deserialized = deserialize_key(&key);
+        assert_eq!(deserialized.len(), 1);
+        assert_eq!(
+            deserialized[0],
+            vec![0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 20, 192]
+        );
+    }
+
+    #[test]
+    fn test_key_simple() {
+        let key = serialize_key(vec![
+            "repo".as_bytes(),
+            "github.com".as_bytes(),
+            "owner".as_bytes(),
+        ]);
+        assert_eq!(key, "repo#github.com#owner".as_bytes());
+
+        let deserialized = deserialize_key(&key);
+        assert_eq!(deserialized.len(), 3);
+        assert_eq!(deserialized[0], "repo".as_bytes());
+        assert_eq!(deserialized[1], "github.com".as_bytes());
+        assert_eq!(deserialized[2], "owner".as_bytes());
+    }
+
+    #[test]
+    fn test_key_separator_escape() {
+        let key = serialize_key(vec![
+            "repo".as_bytes(),
+            "github.com".as_bytes(),
+            "owner#with#hashes".as_bytes(),
+        ]);
+        assert_eq!(key, "repo#github.com#owner\\#with\\#hashes".as_bytes());
+
+        let deserialized = deserialize_key(&key);
+        assert_eq!(deserialized.len(), 3);
+        assert_eq!(deserialized[0], "repo".as_bytes());
+        assert_eq!(deserialized[1], "github.com".as_bytes());
+        assert_eq!(deserialized[2], "owner#with#hashes".as_bytes());
+    }
+
+    #[test]
+    fn test_extract_time() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let extracted = extract_time(&time_part(time));
+        assert_eq!(time as i128, extracted.unix_timestamp_nanos());
+    }
+
+    #[test]
+    fn test_extract_time_from_key() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+
+        let bytes = serialize_key(vec![&time_part(time)]);
+        let deserialized = deserialize_key(&bytes);
+        assert_eq!(deserialized.len(), 1);
+        assert_eq!(deserialized[0], time_part(time));
+
+        let extracted = extract_time(deserialized.last().unwrap());
+        assert_eq!(time as i128, extracted.unix_timestamp_nanos());
+    }
+
+    #[test]
+    fn test_list_repos() {
+        let db = Database::new_rocksdb("data/test_list_repos").unwrap();
+        let tx = db.transaction();
+        let time = 1234567890;
+        let params = CreateRepositoryParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+        };
+        tx.create_repo_if_not_exists(time, params).unwrap();
+        tx.commit().unwrap();
+
+        let repos = db.list_repos(ListReposParams::default()).unwrap().items;
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].server, "github.com");
+        assert_eq!(repos[0].owner, "owner");
+        assert_eq!(repos[0].repo, "repo");
+
+        remove_db("data/test_list_repos");
+    }
+
+    #[test]
+    fn test_list_repos_multiple() {
+        let db = Database::new_rocksdb("data/test_list_repos_multiple").unwrap();
+        let tx = db.transaction();
+        let time = 1234567890;
+        let params = CreateRepositoryParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+        };
+        tx.create_repo_if_not_exists(time, params).unwrap();
+        let params = CreateRepositoryParams {
+            server: &"gitlab.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+        };
+        tx.create_repo_if_not_exists(time, params).unwrap();
+        let params = CreateRepositoryParams {
+            server: &"gitlab.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo-2".to_string(),
+        };
+        tx.create_repo_if_not_exists(time, params).unwrap();
+        tx.commit().unwrap();
+
+        let repos = db.list_repos(ListReposParams::default()).unwrap().items;
+        assert_eq!(repos.len(), 3);
+        assert_eq!(repos[0].server, "github.com");
+        assert_eq!(repos[0].repo, "repo");
+        assert_eq!(repos[1].server, "gitlab.com");
+        assert_eq!(repos[1].repo, "repo");
+        assert_eq!(repos[2].server, "gitlab.com");
+        assert_eq!(repos[2].repo, "repo-2");
+
+        remove_db("data/test_list_repos_multiple");
+    }
+
+    #[test]
+    fn test_list_repos_scoped() {
+        let db = Database::new_rocksdb("data/test_list_repos_scoped").unwrap();
+        let tx = db.transaction();
+        for (i, (server, owner, repo)) in [
+            ("github.com", "owner", "repo"),
+            ("github.com", "owner", "tools"),
+            ("github.com", "owner-2", "repo"),
+            ("gitlab.com", "owner", "repo"),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let params = CreateRepositoryParams {
+                server: &server.to_string(),
+                owner: &owner.to_string(),
+                repo: &repo.to_string(),
+            };
+            let time = (1234567890 + i as u128) * NANOSECONDS_PER_SECOND as u128;
+            tx.create_repo_if_not_exists(time, params).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let list = |params: ListReposParams| {
+            db.list_repos(params)
+                .unwrap()
+                .items
+                .into_iter()
+                .map(|r| format!("{}/{}/{}", r.server, r.owner, r.repo))
+                .collect::<Vec<_>>()
+        };
+
+        let server = "github.com".to_string();
+        let owner = "owner".to_string();
+        assert_eq!(
+            list(ListReposParams {
+                server: Some(&server),
+                ..Default::default()
+            }),
+            vec![
+                "github.com/owner/repo",
+                "github.com/owner/tools",
+                "github.com/owner-2/repo"
+            ]
+        );
+        assert_eq!(
+            list(ListReposParams {
+                server: Some(&server),
+                owner: Some(&owner),
+                ..Default::default()
+            }),
+            vec!["github.com/owner/repo", "github.com/owner/tools"]
+        );
+        assert_eq!(
+            list(ListReposParams {
+                server: Some(&server),
+                owner: Some(&owner),
+                prefix: Some(&"to".to_string()),
+                ..Default::default()
+            }),
+            vec!["github.com/owner/tools"]
+        );
+        assert_eq!(
+            list(ListReposParams {
+                prefix: Some(&"github.com/owner-".to_string()),
+                ..Default::default()
+            }),
+            vec!["github.com/owner-2/repo"]
+        );
+        assert_eq!(
+            list(ListReposParams {
+                since: Some(OffsetDateTime::from_unix_timestamp(1234567892).unwrap()),
+                ..Default::default()
+            }),
+            vec!["github.com/owner-2/repo", "gitlab.com/owner/repo"]
+        );
+
+        remove_db("data/test_list_repos_scoped");
+    }
+
+    #[test]
+    fn test_list_repos_pagination() {
+        let db = Database::new_rocksdb("data/test_list_repos_pagination").unwrap();
+        let tx = db.transaction();
+        for repo in ["repo-1", "repo-2", "repo-3"] {
+            let params = CreateRepositoryParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &repo.to_string(),
+            };
+            tx.create_repo_if_not_exists(1234567890, params).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let page = db
+            .list_repos(ListReposParams {
+                pagination: Pagination {
+                    limit: Some(2),
+                    cursor: None,
+                },
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.items[0].repo, "repo-1");
+        assert_eq!(page.items[1].repo, "repo-2");
+        let cursor = page.next_cursor.unwrap();
+
+        let page = db
+            .list_repos(ListReposParams {
+                pagination: Pagination {
+                    limit: Some(2),
+                    cursor: Some(&cursor),
+                },
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].repo, "repo-3");
+        assert!(page.next_cursor.is_none());
+
+        let err = db
+            .list_repos(ListReposParams {
+                pagination: Pagination {
+                    limit: Some(2),
+                    cursor: Some(&"not-a-cursor".to_string()),
+                },
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)));
+
+        remove_db("data/test_list_repos_pagination");
+    }
+
+    #[test]
+    fn test_list_commits_pagination() {
+        let db = Database::new_rocksdb("data/test_list_commits_pagination").unwrap();
+        let tx = db.transaction();
+        for (i, commit) in ["commit-1", "commit-2", "commit-3"].iter().enumerate() {
+            let params = CreateCommitParams {
+                commit: &commit.to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata::default(),
+            };
+            tx.create_commit_if_not_exists(1234567890 + i as u128, params)
+                .unwrap();
+        }
+        tx.commit().unwrap();
+
+        let mut cursor: Option<String> = None;
+        let mut commits = Vec::new();
+        loop {
+            let page = db
+                .list_repo_commits(ListRepoCommitsParams {
+                    server: &"github.com".to_string(),
+                    owner: &"owner".to_string(),
+                    repo: &"repo".to_string(),
+                    since: None,
+                    until: None,
+                    author: None,
+                    branch: None,
+                    pipeline: None,
+                    labels: &Labels::new(),
+                    status: None,
+                    pagination: Pagination {
+                        limit: Some(1),
+                        cursor: cursor.as_ref(),
+                    },
+                })
+                .unwrap();
+            assert_eq!(page.items.len(), 1);
+            commits.push(page.items[0].commit.clone());
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(commits, vec!["commit-3", "commit-2", "commit-1"]);
+
+        remove_db("data/test_list_commits_pagination");
+    }
+
+    #[test]
+    fn test_list_commits() {
+        let db = Database::new_rocksdb("data/test_list_commits").unwrap();
+        let tx = db.transaction();
+        let time_nano = 1234567890 * NANOSECONDS_PER_SECOND as u128;
+        let params = CreateCommitParams {
+            commit: &"1234567890abcdef".to_string(),
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
+        };
+        tx.create_commit_if_not_exists(time_nano, params).unwrap();
+        tx.commit().unwrap();
+
+        let commits = db
+            .list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: None,
+                until: None,
+                author: None,
+                branch: None,
+                pipeline: None,
+                labels: &Labels::new(),
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].commit, "1234567890abcdef");
+        assert_eq!(
+            commits[0].time_added.unix_timestamp_nanos(),
+            time_nano as i128
+        );
+
+        remove_db("data/test_list_commits");
+    }
+
+    #[test]
+    fn test_time_added_sub_second() {
+        let db = Database::new_rocksdb("data/test_time_added_sub_second").unwrap();
+        let tx = db.transaction();
+        let time_nano = 1234567890 * NANOSECONDS_PER_SECOND as u128 + 123_456_789;
+        tx.create_repo_if_not_exists(
+            time_nano,
+            CreateRepositoryParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+            },
+        )
+        .unwrap();
+        tx.create_commit_if_not_exists(
+            time_nano,
+            CreateCommitParams {
+                commit: &"1234567890abcdef".to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata::default(),
+            },
+        )
+        .unwrap();
+        tx.create_artifact(
+            time_nano,
+            CreateArtifactParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"1234567890abcdef".to_string(),
+                path: &"path/to/artifact".to_string(),
+                labels: &Labels::new(),
+            },
+        )
+        .unwrap();
+        tx.commit().unwrap();
+
+        let repos = db.list_repos(ListReposParams::default()).unwrap().items;
+        assert_eq!(
+            repos[0].time_added.unix_timestamp_nanos(),
+            time_nano as i128
+        );
+
+        let commits = db
+            .list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: None,
+                until: None,
+                author: None,
+                branch: None,
+                pipeline: None,
+                labels: &Labels::new(),
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(
+            commits[0].time_added.unix_timestamp_nanos(),
+            time_nano as i128
+        );
+
+        let artifacts = db
+            .list_artifacts(ListArtifactsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"1234567890abcdef".to_string(),
+                prefix: None,
+                glob: None,
+                labels: &Labels::new(),
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(
+            artifacts[0].time_added.unix_timestamp_nanos(),
+            time_nano as i128
+        );
+
+        let value = serde_json::to_value(&repos[0]).unwrap();
+        assert_eq!(value["timeAdded"], "2009-02-13T23:31:30.123456789Z");
+
+        remove_db("data/test_time_added_sub_second");
+    }
+
+    #[test]
+    fn test_list_commits_order() {
+        let db = Database::new_rocksdb("data/test_list_commits_multiple").unwrap();
+        let tx = db.transaction();
+        let params = CreateCommitParams {
+            commit: &"commit-1".to_string(),
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
+        };
+        tx.create_commit_if_not_exists(1234567890, params).unwrap();
+        let params = CreateCommitParams {
+            commit: &"commit-2".to_string(),
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
+        };
+        tx.create_commit_if_not_exists(1234567891, params).unwrap();
+        tx.commit().unwrap();
+
+        let commits = db
+            .list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: None,
+                until: None,
+                author: None,
+                branch: None,
+                pipeline: None,
+                labels: &Labels::new(),
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].commit, "commit-2");
+        assert_eq!(commits[1].commit, "commit-1");
+
+        remove_db("data/test_list_commits_multiple");
+    }
+
+    #[test]
+    fn test_list_commits_time_range() {
+        let db = Database::new_rocksdb("data/test_list_commits_time_range").unwrap();
+        let tx = db.transaction();
+        for (i, commit) in ["commit-1", "commit-2", "commit-3"].iter().enumerate() {
+            let params = CreateCommitParams {
+                commit: &commit.to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata::default(),
+            };
+            let time = (1234567890 + i as u128) * NANOSECONDS_PER_SECOND as u128;
+            tx.create_commit_if_not_exists(time, params).unwrap();
+        }
+        tx.commit().unwrap();
 
-fn extract_time(bytes: &[u8]) -> OffsetDateTime {
-    let time_nano = u128::from_be_bytes(bytes[0..16].try_into().unwrap());
-    let time_seconds = time_nano as i64 / NANOSECONDS_PER_SECOND;
-    OffsetDateTime::from_unix_timestamp(time_seconds).unwrap()
-}
+        let list = |since: Option<i64>, until: Option<i64>| {
+            db.list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: since.map(|t| OffsetDateTime::from_unix_timestamp(t).unwrap()),
+                until: until.map(|t| OffsetDateTime::from_unix_timestamp(t).unwrap()),
+                author: None,
+                branch: None,
+                pipeline: None,
+                labels: &Labels::new(),
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items
+            .into_iter()
+            .map(|c| c.commit)
+            .collect::<Vec<_>>()
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::{SystemTime, UNIX_EPOCH};
+        assert_eq!(list(Some(1234567891), None), vec!["commit-3", "commit-2"]);
+        assert_eq!(list(None, Some(1234567891)), vec!["commit-2", "commit-1"]);
+        assert_eq!(list(Some(1234567891), Some(1234567891)), vec!["commit-2"]);
+        assert!(list(Some(1234567900), None).is_empty());
 
-    fn remove_db(path: &str) {
-        let _ = std::fs::remove_dir_all(path);
+        remove_db("data/test_list_commits_time_range");
     }
 
     #[test]
-    fn test_serialize() {
-        let key = vec![[0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 20, 192].as_ref()];
-        let serialized = serialize_key(key);
+    fn test_list_commits_time_order() {
+        let path = "data/test_list_commits_time_order";
+        let db = Database::new_rocksdb(path).unwrap();
+        // times whose low byte is `#` or `\` when stored as big-endian bytes
+        let base = (1_700_000_000 * NANOSECONDS_PER_SECOND as u128) & !0xff;
+        let times = [0x22, 0x23, 0x24, 0x5b, 0x5c, 0x5d].map(|low| base | low);
+        let tx = db.transaction();
+        for (i, time) in times.iter().enumerate() {
+            let params = CreateCommitParams {
+                commit: &format!("commit-{i}"),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata::default(),
+            };
+            tx.create_commit_if_not_exists(*time, params).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let list = |since: Option<u128>, until: Option<u128>| {
+            db.list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: since.map(nanos_to_time),
+                until: until.map(nanos_to_time),
+                author: None,
+                branch: None,
+                pipeline: None,
+                labels: &Labels::new(),
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items
+            .into_iter()
+            .map(|c| c.commit)
+            .collect::<Vec<_>>()
+        };
+
         assert_eq!(
-            serialized,
-            vec![0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 92, 20, 192]
+            list(None, None),
+            [
+                "commit-5", "commit-4", "commit-3", "commit-2", "commit-1", "commit-0"
+            ]
         );
-    }
-
-    #[test]
-    fn test_deserialize() {
-        let key = vec![0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 92, 20, 192];
-        let deserialized = deserialize_key(&key);
-        assert_eq!(deserialized.len(), 1);
+        assert_eq!(list(Some(times[1]), Some(times[1])), ["commit-1"]);
         assert_eq!(
-            deserialized[0],
-            vec![0, 0, 0, 0, 0, 0, 0, 0, 23, 122, 45, 94, 28, 92, 20, 192]
+            list(Some(times[1]), Some(times[4])),
+            ["commit-4", "commit-3", "commit-2", "commit-1"]
         );
+        assert_eq!(list(Some(times[5]), None), ["commit-5"]);
+
+        drop(db);
+        remove_db(path);
     }
 
     #[test]
-    fn test_key_simple() {
-        let key = serialize_key(vec![
-            "repo".as_bytes(),
-            "github.com".as_bytes(),
-            "owner".as_bytes(),
-        ]);
-        assert_eq!(key, "repo#github.com#owner".as_bytes());
+    fn test_migrate_commit_time_keys() {
+        let path = "data/test_migrate_commit_time_keys";
+        let base = (1_700_000_000 * NANOSECONDS_PER_SECOND as u128) & !0xff;
+        {
+            // keys as stored before times were encoded in hex
+            let db = TransactionDB::open_default(path).unwrap();
+            for (i, low) in [0x22, 0x23, 0x24].iter().enumerate() {
+                let time = base | low;
+                let commit = format!("commit-{i}");
+                db.put(
+                    serialize_key(vec![
+                        "commit".as_bytes(),
+                        "github.com".as_bytes(),
+                        "owner".as_bytes(),
+                        "repo".as_bytes(),
+                        commit.as_bytes(),
+                    ]),
+                    serde_json::to_string(&CommitValue {
+                        time_added: time,
+                        status: CommitStatus::legacy(),
+                        time_sealed: None,
+                        metadata: CommitMetadata::default(),
+                    })
+                    .unwrap(),
+                )
+                .unwrap();
+                db.put(
+                    serialize_key(vec![
+                        "commit_time".as_bytes(),
+                        "github.com".as_bytes(),
+                        "owner".as_bytes(),
+                        "repo".as_bytes(),
+                        &time.to_be_bytes(),
+                    ]),
+                    serde_json::to_string(&CommitTimeValue { commit }).unwrap(),
+                )
+                .unwrap();
+            }
+        }
 
-        let deserialized = deserialize_key(&key);
-        assert_eq!(deserialized.len(), 3);
-        assert_eq!(deserialized[0], "repo".as_bytes());
-        assert_eq!(deserialized[1], "github.com".as_bytes());
-        assert_eq!(deserialized[2], "owner".as_bytes());
+        let list = |db: &Database| {
+            db.list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: None,
+                until: None,
+                author: None,
+                branch: None,
+                pipeline: None,
+                labels: &Labels::new(),
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items
+            .into_iter()
+            .map(|c| (c.commit, time_to_nanos(c.time_added)))
+            .collect::<Vec<_>>()
+        };
+        let expected = [
+            ("commit-2".to_string(), base | 0x24),
+            ("commit-1".to_string(), base | 0x23),
+            ("commit-0".to_string(), base | 0x22),
+        ];
+        let db = Database::new_rocksdb(path).unwrap();
+        assert_eq!(list(&db), expected);
+        drop(db);
+
+        // migrating again changes nothing
+        let db = Database::new_rocksdb(path).unwrap();
+        assert_eq!(list(&db), expected);
+        drop(db);
+        remove_db(path);
     }
 
     #[test]
-    fn test_key_separator_escape() {
-        let key = serialize_key(vec![
-            "repo".as_bytes(),
-            "github.com".as_bytes(),
-            "owner#with#hashes".as_bytes(),
-        ]);
-        assert_eq!(key, "repo#github.com#owner\\#with\\#hashes".as_bytes());
+    fn test_migrate_artifact_keys() {
+        let path = "data/test_migrate_artifact_keys";
+        {
+            // keys as stored before artifacts were scoped by repository
+            let db = TransactionDB::open_default(path).unwrap();
+            let commit_value = CommitValue {
+                time_added: 1234567890,
+                status: CommitStatus::Pending,
+                time_sealed: None,
+                metadata: CommitMetadata::default(),
+            };
+            for owner in ["owner", "fork"] {
+                db.put(
+                    serialize_key(vec![
+                        "commit".as_bytes(),
+                        "github.com".as_bytes(),
+                        owner.as_bytes(),
+                        "repo".as_bytes(),
+                        "commit-1".as_bytes(),
+                    ]),
+                    serde_json::to_string(&commit_value).unwrap(),
+                )
+                .unwrap();
+            }
+            let artifact_value = ArtifactValue {
+                time_added: 1234567890,
+                labels: Labels::from([("os".to_string(), "linux".to_string())]),
+            };
+            for commit in ["commit-1", "commit-orphan"] {
+                db.put(
+                    serialize_key(vec![
+                        "artifact".as_bytes(),
+                        commit.as_bytes(),
+                        "bin/linux".as_bytes(),
+                    ]),
+                    serde_json::to_string(&artifact_value).unwrap(),
+                )
+                .unwrap();
+                db.put(
+                    serialize_key(vec![
+                        "artifact_label".as_bytes(),
+                        commit.as_bytes(),
+                        "os".as_bytes(),
+                        "linux".as_bytes(),
+                        "bin/linux".as_bytes(),
+                    ]),
+                    "{}",
+                )
+                .unwrap();
+            }
+        }
 
-        let deserialized = deserialize_key(&key);
-        assert_eq!(deserialized.len(), 3);
-        assert_eq!(deserialized[0], "repo".as_bytes());
-        assert_eq!(deserialized[1], "github.com".as_bytes());
-        assert_eq!(deserialized[2], "owner#with#hashes".as_bytes());
+        let linux = Labels::from([("os".to_string(), "linux".to_string())]);
+        let db = Database::new_rocksdb(path).unwrap();
+        // the file was uploaded to `owner/repo` only, the fork shares the commit but not the file
+        db.migrate_artifact_keys(|params| {
+            assert_eq!(params.path, "bin/linux");
+            params.owner == "owner"
+        })
+        .unwrap();
+        // migrating again changes nothing
+        db.migrate_artifact_keys(|_| unreachable!()).unwrap();
+        for (owner, expected) in [("owner", 1), ("fork", 0)] {
+            for labels in [Labels::new(), linux.clone()] {
+                let artifacts = db
+                    .list_artifacts(ListArtifactsParams {
+                        server: &"github.com".to_string(),
+                        owner: &owner.to_string(),
+                        repo: &"repo".to_string(),
+                        commit: &"commit-1".to_string(),
+                        prefix: None,
+                        glob: None,
+                        labels: &labels,
+                        pagination: Pagination::default(),
+                    })
+                    .unwrap()
+                    .items;
+                assert_eq!(artifacts.len(), expected, "{owner}");
+                if let Some(artifact) = artifacts.first() {
+                    assert_eq!(artifact.path, "bin/linux");
+                    assert_eq!(artifact.labels, linux);
+                }
+            }
+        }
+
+        // the old keys, including those of no commit, are gone
+        let Database::RocksDB(raw) = &db;
+        for namespace in ["artifact", "artifact_label"] {
+            let mut parts = Vec::new();
+            for_each_key(raw, namespace, |_, key_parts, _| {
+                parts.push(key_parts);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(parts.len(), 1, "{namespace}");
+            assert_eq!(parts[0][2], b"owner", "{namespace}");
+        }
+        drop(db);
+        remove_db(path);
     }
 
     #[test]
-    fn test_extract_time() {
-        let time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
+    fn test_commit_metadata() {
+        let db = Database::new_rocksdb("data/test_commit_metadata").unwrap();
+        let tx = db.transaction();
+        for (i, (commit, branch)) in [("commit-1", "main"), ("commit-2", "dev")]
+            .iter()
+            .enumerate()
+        {
+            let params = CreateCommitParams {
+                commit: &commit.to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata {
+                    author: Some("alice".to_string()),
+                    branch: Some(branch.to_string()),
+                    parents: vec!["commit-0".to_string()],
+                    ..Default::default()
+                },
+            };
+            tx.create_commit_if_not_exists(1234567890 + i as u128, params)
+                .unwrap();
+        }
+        tx.update_commit_metadata(UpdateCommitMetadataParams {
+            commit: &"commit-2".to_string(),
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            metadata: &CommitMetadata {
+                pipeline: Some("release".to_string()),
+                ..Default::default()
+            },
+        })
+        .unwrap();
+        assert!(
+            tx.update_commit_metadata(UpdateCommitMetadataParams {
+                commit: &"commit-3".to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata::default(),
+            })
+            .is_err()
+        );
+        tx.commit().unwrap();
+
+        let commit = db
+            .get_commit(GetCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit-2".to_string(),
+            })
             .unwrap()
-            .as_nanos();
-        let time_bytes = time.to_be_bytes().to_vec();
-        let extracted = extract_time(&time_bytes);
+            .unwrap();
+        assert_eq!(commit.metadata.author.as_deref(), Some("alice"));
+        assert_eq!(commit.metadata.branch.as_deref(), Some("dev"));
+        assert_eq!(commit.metadata.pipeline.as_deref(), Some("release"));
+        assert_eq!(commit.metadata.parents, vec!["commit-0"]);
+
+        let list = |author: Option<&str>, branch: Option<&str>, pipeline: Option<&str>| {
+            db.list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: None,
+                until: None,
+                author: author.map(str::to_string).as_ref(),
+                branch: branch.map(str::to_string).as_ref(),
+                pipeline: pipeline.map(str::to_string).as_ref(),
+                labels: &Labels::new(),
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items
+            .into_iter()
+            .map(|c| c.commit)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(list(None, None, None), vec!["commit-2", "commit-1"]);
         assert_eq!(
-            time as i64 / NANOSECONDS_PER_SECOND,
-            extracted.unix_timestamp()
+            list(Some("alice"), None, None),
+            vec!["commit-2", "commit-1"]
         );
+        assert_eq!(list(None, Some("main"), None), vec!["commit-1"]);
+        assert_eq!(list(None, None, Some("release")), vec!["commit-2"]);
+        assert!(list(Some("bob"), None, None).is_empty());
+
+        remove_db("data/test_commit_metadata");
     }
 
     #[test]
-    fn test_extract_time_from_key() {
-        let time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-
-        let bytes = serialize_key(vec![&time.to_be_bytes()]);
-        let deserialized = deserialize_key(&bytes);
-        assert_eq!(deserialized.len(), 1);
-        assert_eq!(deserialized[0], time.to_be_bytes());
+    fn test_labels() {
+        let db = Database::new_rocksdb("data/test_labels").unwrap();
+        let tx = db.transaction();
+        for (i, (commit, release)) in [("commit-1", "true"), ("commit-2", "false")]
+            .iter()
+            .enumerate()
+        {
+            let params = CreateCommitParams {
+                commit: &commit.to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata {
+                    labels: Labels::from([("release".to_string(), release.to_string())]),
+                    ..Default::default()
+                },
+            };
+            tx.create_commit_if_not_exists(1234567890 + i as u128, params)
+                .unwrap();
+            tx.set_commit_status(
+                1234567899,
+                SetCommitStatusParams {
+                    server: &"github.com".to_string(),
+                    owner: &"owner".to_string(),
+                    repo: &"repo".to_string(),
+                    commit: &commit.to_string(),
+                    status: CommitStatus::Success,
+                },
+            )
+            .unwrap();
+        }
+        for (path, os, arch) in [
+            ("bin/linux-amd64", "linux", "amd64"),
+            ("bin/linux-arm64", "linux", "arm64"),
+            ("bin/macos-arm64", "macos", "arm64"),
+        ] {
+            let params = CreateArtifactParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit-1".to_string(),
+                path: &path.to_string(),
+                labels: &Labels::from([
+                    ("os".to_string(), os.to_string()),
+                    ("arch".to_string(), arch.to_string()),
+                ]),
+            };
+            tx.create_artifact(1234567890, params).unwrap();
+        }
+        tx.commit().unwrap();
 
-        let extracted = extract_time(deserialized.last().unwrap());
+        let list_artifacts = |labels: &[(&str, &str)]| {
+            db.list_artifacts(ListArtifactsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit-1".to_string(),
+                prefix: None,
+                glob: None,
+                labels: &labels
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items
+            .into_iter()
+            .map(|a| a.path)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(list_artifacts(&[]).len(), 3);
         assert_eq!(
-            time as i64 / NANOSECONDS_PER_SECOND,
-            extracted.unix_timestamp()
+            list_artifacts(&[("arch", "arm64")]),
+            vec!["bin/linux-arm64", "bin/macos-arm64"]
         );
+        assert_eq!(
+            list_artifacts(&[("arch", "arm64"), ("os", "linux")]),
+            vec!["bin/linux-arm64"]
+        );
+        assert!(list_artifacts(&[("arch", "riscv64")]).is_empty());
+
+        let latest = |labels: &Labels| {
+            db.get_latest_commit(GetLatestCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                before: None,
+                skip: 0,
+                labels,
+                any_status: false,
+            })
+            .unwrap()
+        };
+        let release = Labels::from([("release".to_string(), "true".to_string())]);
+        assert_eq!(latest(&Labels::new()).as_deref(), Some("commit-2"));
+        assert_eq!(latest(&release).as_deref(), Some("commit-1"));
+
+        // relabeling moves the commit between index entries
+        let tx = db.transaction();
+        for (commit, value) in [("commit-1", "false"), ("commit-2", "true")] {
+            tx.update_commit_metadata(UpdateCommitMetadataParams {
+                commit: &commit.to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata {
+                    labels: Labels::from([("release".to_string(), value.to_string())]),
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        }
+        tx.commit().unwrap();
+        assert_eq!(latest(&release).as_deref(), Some("commit-2"));
+        let commits = db
+            .list_repo_commits(ListRepoCommitsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                since: None,
+                until: None,
+                author: None,
+                branch: None,
+                pipeline: None,
+                labels: &release,
+                status: None,
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(commits.len(), 1);
+
+        remove_db("data/test_labels");
     }
 
     #[test]
-    fn test_list_repos() {
-        let db = Database::new_rocksdb("data/test_list_repos").unwrap();
+    fn test_commit_status() {
+        let db = Database::new_rocksdb("data/test_commit_status").unwrap();
         let tx = db.transaction();
-        let time = 1234567890;
-        let params = CreateRepositoryParams {
-            server: &"github.com".to_string(),
-            owner: &"owner".to_string(),
-            repo: &"repo".to_string(),
-        };
-        tx.create_repo_if_not_exists(time, params).unwrap();
+        for (i, commit) in ["commit-1", "commit-2"].iter().enumerate() {
+            let params = CreateCommitParams {
+                commit: &commit.to_string(),
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                metadata: &CommitMetadata::default(),
+            };
+            tx.create_commit_if_not_exists(1234567890 + i as u128, params)
+                .unwrap();
+        }
         tx.commit().unwrap();
 
-        let repos = db.list_repos().unwrap();
-        assert_eq!(repos.len(), 1);
-        assert_eq!(repos[0].server, "github.com");
-        assert_eq!(repos[0].owner, "owner");
-        assert_eq!(repos[0].repo, "repo");
+        let set_status = |commit: &str, status: CommitStatus| {
+            let tx = db.transaction();
+            tx.set_commit_status(
+                1234567899,
+                SetCommitStatusParams {
+                    server: &"github.com".to_string(),
+                    owner: &"owner".to_string(),
+                    repo: &"repo".to_string(),
+                    commit: &commit.to_string(),
+                    status,
+                },
+            )?;
+            tx.commit()?;
+            Ok::<(), Error>(())
+        };
+        let latest = |any_status: bool| {
+            db.get_latest_commit(GetLatestCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                before: None,
+                skip: 0,
+                labels: &Labels::new(),
+                any_status,
+            })
+            .unwrap()
+        };
 
-        remove_db("data/test_list_repos");
+        // new commits are pending and invisible to the latest commit
+        assert!(latest(false).is_none());
+        assert_eq!(latest(true).as_deref(), Some("commit-2"));
+
+        set_status("commit-1", CommitStatus::Success).unwrap();
+        set_status("commit-2", CommitStatus::Failed).unwrap();
+        assert_eq!(latest(false).as_deref(), Some("commit-1"));
+
+        // failed commits can be retried, successful ones are final
+        assert!(matches!(
+            set_status("commit-1", CommitStatus::Failed),
+            Err(Error::Conflict(_))
+        ));
+        set_status("commit-2", CommitStatus::Pending).unwrap();
+        set_status("commit-2", CommitStatus::Success).unwrap();
+        assert_eq!(latest(false).as_deref(), Some("commit-2"));
+
+        let commit = db
+            .get_commit(GetCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit-2".to_string(),
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(commit.status, CommitStatus::Success);
+
+        remove_db("data/test_commit_status");
     }
 
     #[test]
-    fn test_list_repos_multiple() {
-        let db = Database::new_rocksdb("data/test_list_repos_multiple").unwrap();
+    fn test_seal_commit() {
+        let db = Database::new_rocksdb("data/test_seal_commit").unwrap();
         let tx = db.transaction();
-        let time = 1234567890;
-        let params = CreateRepositoryParams {
+        let params = CreateCommitParams {
+            commit: &"commit-1".to_string(),
             server: &"github.com".to_string(),
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
         };
-        tx.create_repo_if_not_exists(time, params).unwrap();
-        let params = CreateRepositoryParams {
-            server: &"gitlab.com".to_string(),
+        tx.create_commit_if_not_exists(1234567890, params).unwrap();
+        let params = CreateArtifactParams {
+            server: &"github.com".to_string(),
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
+            commit: &"commit-1".to_string(),
+            path: &"a.txt".to_string(),
+            labels: &Labels::new(),
         };
-        tx.create_repo_if_not_exists(time, params).unwrap();
-        let params = CreateRepositoryParams {
-            server: &"gitlab.com".to_string(),
+        tx.create_artifact(1234567890, params).unwrap();
+        let params = SealCommitParams {
+            server: &"github.com".to_string(),
             owner: &"owner".to_string(),
-            repo: &"repo-2".to_string(),
+            repo: &"repo".to_string(),
+            commit: &"commit-1".to_string(),
         };
-        tx.create_repo_if_not_exists(time, params).unwrap();
+        tx.seal_commit(1234567891, params.clone()).unwrap();
         tx.commit().unwrap();
 
-        let repos = db.list_repos().unwrap();
-        assert_eq!(repos.len(), 3);
-        assert_eq!(repos[0].server, "github.com");
-        assert_eq!(repos[0].repo, "repo");
-        assert_eq!(repos[1].server, "gitlab.com");
-        assert_eq!(repos[1].repo, "repo");
-        assert_eq!(repos[2].server, "gitlab.com");
-        assert_eq!(repos[2].repo, "repo-2");
-
-        remove_db("data/test_list_repos_multiple");
-    }
-
-    #[test]
-    fn test_list_commits() {
-        let db = Database::new_rocksdb("data/test_list_commits").unwrap();
+        // sealing again keeps the original seal
         let tx = db.transaction();
-        let time_nano = 1234567890 * NANOSECONDS_PER_SECOND as u128;
-        let params = CreateCommitParams {
-            commit: &"1234567890abcdef".to_string(),
+        tx.seal_commit(1234567892, params).unwrap();
+        let params = CreateArtifactParams {
             server: &"github.com".to_string(),
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
+            commit: &"commit-1".to_string(),
+            path: &"b.txt".to_string(),
+            labels: &Labels::new(),
         };
-        tx.create_commit_if_not_exists(time_nano, params).unwrap();
+        assert!(matches!(
+            tx.create_artifact(1234567892, params),
+            Err(Error::Locked(_))
+        ));
         tx.commit().unwrap();
 
-        let commits = db
-            .list_repo_commits(ListRepoCommitsParams {
+        let commit = db
+            .get_commit(GetCommitParams {
                 server: &"github.com".to_string(),
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
+                commit: &"commit-1".to_string(),
             })
+            .unwrap()
             .unwrap();
-        assert_eq!(commits.len(), 1);
-        assert_eq!(commits[0].commit, "1234567890abcdef");
-        assert_eq!(
-            commits[0].time_added.unix_timestamp_nanos(),
-            time_nano as i128
-        );
+        assert_eq!(commit.time_sealed, Some(nanos_to_time(1234567891)));
 
-        remove_db("data/test_list_commits");
+        remove_db("data/test_seal_commit");
     }
 
     #[test]
-    fn test_list_commits_order() {
-        let db = Database::new_rocksdb("data/test_list_commits_multiple").unwrap();
-        let tx = db.transaction();
-        let params = CreateCommitParams {
-            commit: &"commit-1".to_string(),
-            server: &"github.com".to_string(),
-            owner: &"owner".to_string(),
-            repo: &"repo".to_string(),
+    fn test_acls() {
+        let db = Database::new_rocksdb("data/test_acls").unwrap();
+        let server = "github.com".to_string();
+        let owner = "owner".to_string();
+        let repo = "repo".to_string();
+        let server_target = AclTarget {
+            server: &server,
+            owner: None,
+            repo: None,
         };
-        tx.create_commit_if_not_exists(1234567890, params).unwrap();
-        let params = CreateCommitParams {
-            commit: &"commit-2".to_string(),
-            server: &"github.com".to_string(),
-            owner: &"owner".to_string(),
-            repo: &"repo".to_string(),
+        let repo_target = AclTarget {
+            server: &server,
+            owner: Some(&owner),
+            repo: Some(&repo),
         };
-        tx.create_commit_if_not_exists(1234567891, params).unwrap();
+        let entries = [AclEntry {
+            principal: "*".to_string(),
+            permissions: vec![AclPermission::List, AclPermission::Download],
+        }];
+        let tx = db.transaction();
+        tx.set_acl(1234567890, repo_target, &entries).unwrap();
+        tx.set_acl(1234567891, server_target, &[]).unwrap();
         tx.commit().unwrap();
 
-        let commits = db
-            .list_repo_commits(ListRepoCommitsParams {
+        let acl = db.get_acl(repo_target).unwrap().unwrap();
+        assert_eq!(acl.owner.as_deref(), Some("owner"));
+        assert_eq!(acl.repo.as_deref(), Some("repo"));
+        assert_eq!(acl.entries[0].permissions, entries[0].permissions);
+        assert!(
+            db.get_acl(AclTarget {
+                server: &server,
+                owner: Some(&owner),
+                repo: None,
+            })
+            .unwrap()
+            .is_none()
+        );
+
+        // servers come before their owners and repositories
+        let acls = db.list_acls(Pagination::default()).unwrap().items;
+        assert_eq!(acls.len(), 2);
+        assert!(acls[0].owner.is_none() && acls[0].entries.is_empty());
+        assert_eq!(acls[1].time_updated, nanos_to_time(1234567890));
+
+        let tx = db.transaction();
+        tx.delete_acl(repo_target).unwrap();
+        assert!(tx.delete_acl(repo_target).is_err());
+        tx.commit().unwrap();
+        assert!(db.get_acl(repo_target).unwrap().is_none());
+
+        remove_db("data/test_acls");
+    }
+
+    #[test]
+    fn test_promote() {
+        let db = Database::new_rocksdb("data/test_promote").unwrap();
+        let tx = db.transaction();
+        for (time, commit) in [(1, "commit-1"), (2, "commit-2"), (3, "commit-2")] {
+            let params = PromoteParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                channel: &"stable".to_string(),
+                commit: &commit.to_string(),
+                actor: Some(&"alice".to_string()),
+                claimed_actor: Some(&"bob".to_string()),
+                reason: None,
+            };
+            tx.promote(time, params).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let commit = db
+            .get_channel(GetChannelParams {
                 server: &"github.com".to_string(),
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
+                channel: &"stable".to_string(),
             })
             .unwrap();
-        assert_eq!(commits.len(), 2);
-        assert_eq!(commits[0].commit, "commit-2");
-        assert_eq!(commits[1].commit, "commit-1");
+        assert_eq!(commit.as_deref(), Some("commit-2"));
 
-        remove_db("data/test_list_commits_multiple");
+        let promotions = db
+            .list_promotions(ListPromotionsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                channel: &"stable".to_string(),
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(promotions.len(), 3);
+        assert_eq!(promotions[0].previous.as_deref(), Some("commit-2"));
+        assert_eq!(promotions[1].previous.as_deref(), Some("commit-1"));
+        assert_eq!(promotions[2].previous, None);
+        assert_eq!(promotions[2].actor.as_deref(), Some("alice"));
+        assert_eq!(promotions[2].claimed_actor.as_deref(), Some("bob"));
+
+        let channels = db
+            .list_channels(ListChannelsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].name, "stable");
+
+        remove_db("data/test_promote");
+    }
+
+    #[test]
+    fn test_session() {
+        let db = Database::new_rocksdb("data/test_session").unwrap();
+        let session = "session-1".to_string();
+
+        let tx = db.transaction();
+        assert!(
+            tx.stage_artifact(
+                1234567890,
+                StageArtifactParams {
+                    session: &session,
+                    path: &"a.txt".to_string(),
+                    labels: &Labels::new(),
+                },
+            )
+            .is_err()
+        );
+        tx.create_session(
+            1234567890,
+            CreateSessionParams {
+                id: &session,
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit-1".to_string(),
+                metadata: &CommitMetadata::default(),
+            },
+        )
+        .unwrap();
+        for path in ["b.txt", "a.txt"] {
+            let params = StageArtifactParams {
+                session: &session,
+                path: &path.to_string(),
+                labels: &Labels::new(),
+            };
+            tx.stage_artifact(1234567890, params).unwrap();
+        }
+        let params = StageArtifactParams {
+            session: &session,
+            path: &"a.txt".to_string(),
+            labels: &Labels::new(),
+        };
+        assert!(matches!(
+            tx.stage_artifact(1234567890, params),
+            Err(Error::Conflict(_))
+        ));
+        tx.commit().unwrap();
+
+        let data = db.get_session(&session).unwrap().unwrap();
+        assert_eq!(data.commit, "commit-1");
+        let tx = db.transaction();
+        let paths: Vec<String> = tx
+            .list_session_artifacts(&session)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.path)
+            .collect();
+        assert_eq!(paths, vec!["a.txt", "b.txt"]);
+        // an upload staged after the session is listed makes the transaction conflict
+        let other = db.transaction();
+        let params = StageArtifactParams {
+            session: &session,
+            path: &"c.txt".to_string(),
+            labels: &Labels::new(),
+        };
+        other.stage_artifact(1234567890, params).unwrap();
+        other.commit().unwrap();
+        tx.delete_session(&session, &paths).unwrap();
+        assert!(tx.commit().is_err());
+
+        assert!(db.list_expired_sessions(1234567890, 10).unwrap().is_empty());
+        assert_eq!(
+            db.list_expired_sessions(1234567891, 10).unwrap(),
+            vec![session.clone()]
+        );
+
+        let tx = db.transaction();
+        let paths: Vec<String> = tx
+            .list_session_artifacts(&session)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.path)
+            .collect();
+        assert_eq!(paths, vec!["a.txt", "b.txt", "c.txt"]);
+        tx.delete_session(&session, &paths).unwrap();
+        tx.commit().unwrap();
+        assert!(db.get_session(&session).unwrap().is_none());
+        let tx = db.transaction();
+        assert!(tx.list_session_artifacts(&session).unwrap().is_empty());
+        assert!(tx.delete_session(&session, &paths).is_err());
+
+        remove_db("data/test_session");
     }
 
     #[test]
@@ -724,6 +4245,7 @@ mod tests {
             server: &"github.com".to_string(),
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
         };
         tx.create_commit_if_not_exists(1234567890, params).unwrap();
         let params = CreateCommitParams {
@@ -731,8 +4253,22 @@ mod tests {
             server: &"github.com".to_string(),
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
         };
         tx.create_commit_if_not_exists(1234567891, params).unwrap();
+        for commit in ["commit-1", "commit-2"] {
+            tx.set_commit_status(
+                1234567899,
+                SetCommitStatusParams {
+                    server: &"github.com".to_string(),
+                    owner: &"owner".to_string(),
+                    repo: &"repo".to_string(),
+                    commit: &commit.to_string(),
+                    status: CommitStatus::Success,
+                },
+            )
+            .unwrap();
+        }
         tx.commit().unwrap();
 
         let commit = db
@@ -740,9 +4276,76 @@ mod tests {
                 server: &"github.com".to_string(),
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
+                before: None,
+                skip: 0,
+                labels: &Labels::new(),
+                any_status: false,
+            })
+            .unwrap();
+        assert_eq!(commit.as_deref(), Some("commit-2"));
+
+        let commit = db
+            .get_latest_commit(GetLatestCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                before: None,
+                skip: 1,
+                labels: &Labels::new(),
+                any_status: false,
+            })
+            .unwrap();
+        assert_eq!(commit.as_deref(), Some("commit-1"));
+
+        let commit = db
+            .get_latest_commit(GetLatestCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                before: None,
+                skip: 2,
+                labels: &Labels::new(),
+                any_status: false,
+            })
+            .unwrap();
+        assert!(commit.is_none());
+
+        let commit = db
+            .get_latest_commit(GetLatestCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                before: Some(nanos_to_time(1234567891)),
+                skip: 0,
+                labels: &Labels::new(),
+                any_status: false,
+            })
+            .unwrap();
+        assert_eq!(commit.as_deref(), Some("commit-1"));
+
+        let commit = db
+            .get_latest_commit(GetLatestCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo-2".to_string(),
+                before: None,
+                skip: 0,
+                labels: &Labels::new(),
+                any_status: false,
             })
             .unwrap();
-        assert_eq!(commit, "commit-2");
+        assert!(commit.is_none());
+
+        let result = db.get_latest_commit(GetLatestCommitParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            before: None,
+            skip: usize::MAX,
+            labels: &Labels::new(),
+            any_status: false,
+        });
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
 
         remove_db("data/test_get_latest_commit");
     }
@@ -759,13 +4362,18 @@ mod tests {
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
             commit: &"1234567890abcdef".to_string(),
+            metadata: &CommitMetadata::default(),
         };
         tx.create_commit_if_not_exists(time_milliseconds, params)
             .unwrap();
 
         let params = CreateArtifactParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
             commit: &"1234567890abcdef".to_string(),
             path: &"path/to/artifact".to_string(),
+            labels: &Labels::new(),
         };
         tx.create_artifact(time_milliseconds, params).unwrap();
         tx.commit().unwrap();
@@ -776,8 +4384,13 @@ mod tests {
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
                 commit: &"1234567890abcdef".to_string(),
+                prefix: None,
+                glob: None,
+                labels: &Labels::new(),
+                pagination: Pagination::default(),
             })
-            .unwrap();
+            .unwrap()
+            .items;
         assert_eq!(artifacts.len(), 1);
         assert_eq!(artifacts[0].path, "path/to/artifact");
         assert_eq!(artifacts[0].time_added.unix_timestamp(), 1234567890);
@@ -798,6 +4411,7 @@ mod tests {
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
                 commit: &"commit-1".to_string(),
+                metadata: &CommitMetadata::default(),
             },
         )
         .unwrap();
@@ -808,23 +4422,36 @@ mod tests {
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
                 commit: &"commit-2".to_string(),
+                metadata: &CommitMetadata::default(),
             },
         )
         .unwrap();
 
         let params1 = CreateArtifactParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
             commit: &"commit-1".to_string(),
             path: &"path/to/artifact-1".to_string(),
+            labels: &Labels::new(),
         };
         tx.create_artifact(time_milliseconds, params1).unwrap();
         let params2 = CreateArtifactParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
             commit: &"commit-1".to_string(),
             path: &"path/to/artifact-2".to_string(),
+            labels: &Labels::new(),
         };
         tx.create_artifact(time_milliseconds, params2).unwrap();
         let params3 = CreateArtifactParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
             commit: &"commit-2".to_string(),
             path: &"path/to/artifact-3".to_string(),
+            labels: &Labels::new(),
         };
         tx.create_artifact(time_milliseconds, params3).unwrap();
         tx.commit().unwrap();
@@ -835,8 +4462,13 @@ mod tests {
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
                 commit: &"commit-1".to_string(),
+                prefix: None,
+                glob: None,
+                labels: &Labels::new(),
+                pagination: Pagination::default(),
             })
-            .unwrap();
+            .unwrap()
+            .items;
         assert_eq!(artifacts_commit_1.len(), 2);
         assert_eq!(artifacts_commit_1[0].path, "path/to/artifact-1");
         assert_eq!(artifacts_commit_1[1].path, "path/to/artifact-2");
@@ -847,22 +4479,106 @@ mod tests {
                 owner: &"owner".to_string(),
                 repo: &"repo".to_string(),
                 commit: &"commit-2".to_string(),
+                prefix: None,
+                glob: None,
+                labels: &Labels::new(),
+                pagination: Pagination::default(),
             })
-            .unwrap();
+            .unwrap()
+            .items;
         assert_eq!(artifacts_commit_2.len(), 1);
         assert_eq!(artifacts_commit_2[0].path, "path/to/artifact-3");
 
         remove_db("data/test_list_artifacts_multiple");
     }
 
+    #[test]
+    fn test_list_artifacts_prefix_glob() {
+        let db = Database::new_rocksdb("data/test_list_artifacts_prefix_glob").unwrap();
+        let tx = db.transaction();
+        tx.create_commit_if_not_exists(
+            1234567890,
+            CreateCommitParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit".to_string(),
+                metadata: &CommitMetadata::default(),
+            },
+        )
+        .unwrap();
+        for path in [
+            "bin/app",
+            "reports/coverage.html",
+            "reports/junit.xml",
+            "reports/nested/index.html",
+        ] {
+            let params = CreateArtifactParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit".to_string(),
+                path: &path.to_string(),
+                labels: &Labels::new(),
+            };
+            tx.create_artifact(1234567890, params).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let list = |prefix: Option<&str>, glob: Option<&str>| {
+            db.list_artifacts(ListArtifactsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                commit: &"commit".to_string(),
+                prefix: prefix.map(|p| p.to_string()).as_ref(),
+                glob: glob.map(|g| g.to_string()).as_ref(),
+                labels: &Labels::new(),
+                pagination: Pagination::default(),
+            })
+            .map(|page| page.items.into_iter().map(|a| a.path).collect::<Vec<_>>())
+        };
+
+        assert_eq!(
+            list(Some("reports/"), None).unwrap(),
+            vec![
+                "reports/coverage.html",
+                "reports/junit.xml",
+                "reports/nested/index.html"
+            ]
+        );
+        assert_eq!(
+            list(None, Some("reports/*.html")).unwrap(),
+            vec!["reports/coverage.html"]
+        );
+        assert_eq!(
+            list(None, Some("**/*.html")).unwrap(),
+            vec!["reports/coverage.html", "reports/nested/index.html"]
+        );
+        assert_eq!(
+            list(Some("bin/"), Some("**/*.html")).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(matches!(
+            list(None, Some("[")).unwrap_err(),
+            Error::InvalidArgument(_)
+        ));
+
+        remove_db("data/test_list_artifacts_prefix_glob");
+    }
+
     #[test]
     fn test_list_artifacts_invalid_commit() {
         let db = Database::new_rocksdb("data/test_list_artifacts_invalid_commit").unwrap();
         let tx = db.transaction();
         let time_milliseconds = 1234567890 * NANOSECONDS_PER_SECOND as u128;
         let params = CreateArtifactParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
             commit: &"1234567890abcdef".to_string(),
             path: &"path/to/artifact".to_string(),
+            labels: &Labels::new(),
         };
         tx.create_artifact(time_milliseconds, params).unwrap();
         tx.commit().unwrap();
@@ -873,6 +4589,10 @@ mod tests {
                 owner: &"does-not-exist".to_string(),
                 repo: &"does-not-exist".to_string(),
                 commit: &"1234567890abcdef".to_string(),
+                prefix: None,
+                glob: None,
+                labels: &Labels::new(),
+                pagination: Pagination::default(),
             })
             .unwrap_err();
         assert!(matches!(err, Error::Generic(_)));
@@ -880,6 +4600,111 @@ mod tests {
         remove_db("data/test_list_artifacts_invalid_commit");
     }
 
+    #[test]
+    fn test_set_ref() {
+        let db = Database::new_rocksdb("data/test_set_ref").unwrap();
+        let tx = db.transaction();
+        let params = SetRefParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            name: &"refs/heads/main".to_string(),
+            commit: &"commit-1".to_string(),
+        };
+        tx.set_ref(1234567890, params).unwrap();
+        let params = SetRefParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            name: &"refs/heads/main".to_string(),
+            commit: &"commit-2".to_string(),
+        };
+        tx.set_ref(1234567891, params).unwrap();
+        let params = SetRefParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
+            name: &"v1.2.0".to_string(),
+            commit: &"commit-1".to_string(),
+        };
+        tx.set_ref(1234567892, params).unwrap();
+        tx.commit().unwrap();
+
+        let commit = db
+            .get_ref(GetRefParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                name: &"refs/heads/main".to_string(),
+            })
+            .unwrap();
+        assert_eq!(commit.as_deref(), Some("commit-2"));
+
+        let commit = db
+            .get_ref(GetRefParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo-2".to_string(),
+                name: &"refs/heads/main".to_string(),
+            })
+            .unwrap();
+        assert!(commit.is_none());
+
+        let refs = db
+            .list_refs(ListRefsParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0].name, "refs/heads/main");
+        assert_eq!(refs[0].commit, "commit-2");
+        assert_eq!(refs[1].name, "v1.2.0");
+        assert_eq!(refs[1].commit, "commit-1");
+
+        remove_db("data/test_set_ref");
+    }
+
+    #[test]
+    fn test_list_ref_history() {
+        let db = Database::new_rocksdb("data/test_list_ref_history").unwrap();
+        let tx = db.transaction();
+        for (time, commit) in [
+            (1, "commit-1"),
+            (2, "commit-2"),
+            (3, "commit-2"),
+            (4, "commit-3"),
+        ] {
+            let params = SetRefParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                name: &"refs/heads/main".to_string(),
+                commit: &commit.to_string(),
+            };
+            tx.set_ref(time, params).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let history = db
+            .list_ref_history(ListRefHistoryParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+                name: &"refs/heads/main".to_string(),
+                pagination: Pagination::default(),
+            })
+            .unwrap()
+            .items;
+        let commits: Vec<&str> = history.iter().map(|item| item.commit.as_str()).collect();
+        assert_eq!(commits, vec!["commit-3", "commit-2", "commit-1"]);
+
+        remove_db("data/test_list_ref_history");
+    }
+
     #[test]
     fn test_create_commit() {
         let db = Database::new_rocksdb("data/test_create_commit").unwrap();
@@ -890,6 +4715,7 @@ mod tests {
             server: &"github.com".to_string(),
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
         };
         tx.create_commit_if_not_exists(time, params).unwrap();
         tx.commit().unwrap();
@@ -907,6 +4733,7 @@ mod tests {
             server: &"github.com".to_string(),
             owner: &"owner".to_string(),
             repo: &"repo".to_string(),
+            metadata: &CommitMetadata::default(),
         };
         tx.create_commit_if_not_exists(time, params.clone())
             .unwrap();
@@ -922,8 +4749,12 @@ mod tests {
         let tx = db.transaction();
         let time = 1234567890;
         let params = CreateArtifactParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
             commit: &"1234567890abcdef".to_string(),
             path: &"path/to/artifact".to_string(),
+            labels: &Labels::new(),
         };
         tx.create_artifact(time, params).unwrap();
         tx.commit().unwrap();
@@ -937,8 +4768,12 @@ mod tests {
         let tx = db.transaction();
         let time = 1234567890;
         let params = CreateArtifactParams {
+            server: &"github.com".to_string(),
+            owner: &"owner".to_string(),
+            repo: &"repo".to_string(),
             commit: &"1234567890abcdef".to_string(),
             path: &"path/to/artifact".to_string(),
+            labels: &Labels::new(),
         };
         tx.create_artifact(time, params.clone()).unwrap();
         let err = tx.create_artifact(time, params.clone()).unwrap_err();
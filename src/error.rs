@@ -10,6 +10,14 @@ pub enum HandleRequestError {
     RocksDBError(rocksdb::Error),
     Generic(String),
     NotFound(String),
+    BadRequest(String),
+    Conflict(String),
+    Locked(String),
+    Unauthorized(String),
+    Forbidden(String),
+    TooManyRequests(String),
+    PayloadTooLarge(String),
+    RequestTimeout(String),
 }
 
 impl fmt::Display for HandleRequestError {
@@ -21,6 +29,14 @@ impl fmt::Display for HandleRequestError {
             HandleRequestError::RocksDBError(e) => write!(f, "RocksDB error: {e}"),
             HandleRequestError::Generic(s) => write!(f, "Generic error: {s}"),
             HandleRequestError::NotFound(s) => write!(f, "{s}"),
+            HandleRequestError::BadRequest(s) => write!(f, "{s}"),
+            HandleRequestError::Conflict(s) => write!(f, "{s}"),
+            HandleRequestError::Locked(s) => write!(f, "{s}"),
+            HandleRequestError::Unauthorized(s) => write!(f, "{s}"),
+            HandleRequestError::Forbidden(s) => write!(f, "{s}"),
+            HandleRequestError::TooManyRequests(s) => write!(f, "{s}"),
+            HandleRequestError::PayloadTooLarge(s) => write!(f, "{s}"),
+            HandleRequestError::RequestTimeout(s) => write!(f, "{s}"),
         }
     }
 }
@@ -66,6 +82,9 @@ impl From<database::Error> for HandleRequestError {
         match e {
             database::Error::RocksDB(e) => Self::RocksDBError(e),
             database::Error::Generic(s) => Self::Generic(s),
+            database::Error::InvalidArgument(s) => Self::BadRequest(s),
+            database::Error::Conflict(s) => Self::Conflict(s),
+            database::Error::Locked(s) => Self::Locked(s),
         }
     }
 }
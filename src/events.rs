@@ -0,0 +1,265 @@
+use std::{collections::VecDeque, convert::Infallible, sync::Arc, sync::Mutex};
+
+use axum::response::sse;
+use futures_util::Stream;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::auth;
+use crate::database;
+
+/// How many events a slow subscriber may fall behind before it catches up from the database.
+const CHANNEL_CAPACITY: usize = 1024;
+/// How many events are read from the database at once when replaying.
+const REPLAY_BATCH_SIZE: usize = 100;
+
+/// Assigns sequence numbers to committed events, appends them to the event log
+/// and broadcasts them to subscribed streams.
+pub struct EventHub {
+    sender: broadcast::Sender<(u64, database::Event)>,
+    /// The sequence number of the last event published. Held while appending,
+    /// so that events are broadcast in sequence order.
+    sequence: Mutex<u64>,
+    /// Cancelled when the server shuts down, ending every stream.
+    shutdown: CancellationToken,
+}
+
+impl EventHub {
+    pub fn new(
+        db: &database::Database,
+        shutdown: CancellationToken,
+    ) -> Result<Self, database::Error> {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Ok(EventHub {
+            sender,
+            sequence: Mutex::new(db.get_last_event_sequence()?),
+            shutdown,
+        })
+    }
+
+    /// Publish events of a committed transaction. The change is already stored,
+    /// so failures are logged instead of failing the request.
+    pub fn publish(&self, db: &database::Database, events: Vec<database::Event>) {
+        if events.is_empty() {
+            return;
+        }
+
+        let mut sequence = self.sequence.lock().unwrap();
+        let first = *sequence + 1;
+        let txn = db.transaction();
+        for (i, event) in events.iter().enumerate() {
+            if let Err(e) = txn.append_event(first + i as u64, event) {
+                warn!("failed to append event {}: {e:?}", event.id);
+                return;
+            }
+        }
+        if let Err(e) = txn.commit() {
+            warn!("failed to append events: {e}");
+            return;
+        }
+        *sequence += events.len() as u64;
+
+        for (i, event) in events.into_iter().enumerate() {
+            // there may be no subscribers
+            let _ = self.sender.send((first + i as u64, event));
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(u64, database::Event)> {
+        self.sender.subscribe()
+    }
+}
+
+/// Only stream the events of this repository.
+#[derive(Clone)]
+pub struct Scope {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+}
+
+impl Scope {
+    fn contains(&self, event: &database::Event) -> bool {
+        event.server == self.server && event.owner == self.owner && event.repo == self.repo
+    }
+}
+
+struct StreamState {
+    db: Arc<database::Database>,
+    receiver: broadcast::Receiver<(u64, database::Event)>,
+    shutdown: CancellationToken,
+    scope: Option<Scope>,
+    /// Only events of repositories it's permitted to list are sent.
+    principal: auth::Principal,
+    /// The sequence number of the last event sent, or to replay from.
+    last: u64,
+    /// Whether events after `last` need to be read from the database first.
+    replay: bool,
+    pending: VecDeque<(u64, database::Event)>,
+}
+
+/// Stream events as SSE, starting after `last_event_id` if given, otherwise with new events.
+pub fn stream(
+    db: Arc<database::Database>,
+    hub: &EventHub,
+    scope: Option<Scope>,
+    principal: auth::Principal,
+    last_event_id: Option<u64>,
+) -> impl Stream<Item = Result<sse::Event, Infallible>> + use<> {
+    // subscribe before replaying, so that no event falls between the two
+    let state = StreamState {
+        db,
+        receiver: hub.subscribe(),
+        shutdown: hub.shutdown.clone(),
+        scope,
+        principal,
+        last: last_event_id.unwrap_or(0),
+        replay: last_event_id.is_some(),
+        pending: VecDeque::new(),
+    };
+
+    futures_util::stream::unfold(state, |mut state| async move {
+        let shutdown = state.shutdown.clone();
+        let (sequence, event) = tokio::select! {
+            biased;
+            // end the response, or graceful shutdown would wait for the client forever
+            _ = shutdown.cancelled() => return None,
+            next = next_event(&mut state) => next?,
+        };
+        state.last = sequence;
+        let event = sse::Event::default()
+            .id(sequence.to_string())
+            .event(event.kind.to_string())
+            .json_data(&event)
+            .unwrap();
+        Some((Ok(event), state))
+    })
+}
+
+async fn next_event(state: &mut StreamState) -> Option<(u64, database::Event)> {
+    loop {
+        if let Some((sequence, event)) = state.pending.pop_front() {
+            if visible(state, &event) {
+                return Some((sequence, event));
+            }
+            // skip it, so that the next batch is read after it
+            state.last = sequence;
+            continue;
+        }
+        if state.replay {
+            match replay(state) {
+                Ok(true) => continue,
+                Ok(false) => state.replay = false,
+                Err(e) => {
+                    warn!("failed to replay events: {e:?}");
+                    return None;
+                }
+            }
+        }
+
+        match state.receiver.recv().await {
+            Ok((sequence, event)) => {
+                let in_scope = state
+                    .scope
+                    .as_ref()
+                    .is_none_or(|scope| scope.contains(&event));
+                if sequence > state.last && in_scope && visible(state, &event) {
+                    return Some((sequence, event));
+                }
+            }
+            // fell behind the channel, catch up from the event log
+            Err(broadcast::error::RecvError::Lagged(_)) => state.replay = true,
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn visible(state: &StreamState, event: &database::Event) -> bool {
+    state
+        .principal
+        .permitted(
+            &state.db,
+            &event.server,
+            &event.owner,
+            &event.repo,
+            database::AclPermission::List,
+        )
+        .unwrap_or_else(|e| {
+            warn!("failed to check acl of event {}: {e:?}", event.id);
+            false
+        })
+}
+
+/// Read the next batch of events after `state.last` into `state.pending`.
+/// Returns whether any event was read.
+fn replay(state: &mut StreamState) -> Result<bool, database::Error> {
+    let page = state.db.list_events(database::ListEventsParams {
+        after: state
+            .pending
+            .back()
+            .map_or(state.last, |(sequence, _)| *sequence),
+        scope: state.scope.as_ref().map(|scope| database::RepoScope {
+            server: &scope.server,
+            owner: &scope.owner,
+            repo: &scope.repo,
+        }),
+        pagination: database::Pagination {
+            limit: Some(REPLAY_BATCH_SIZE),
+            cursor: None,
+        },
+    })?;
+    let read = !page.items.is_empty();
+    state.pending.extend(page.items);
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(repo: &str) -> database::Event {
+        serde_json::from_value(serde_json::json!({
+            "id": repo,
+            "type": "repo.created",
+            "time": "2024-01-01T00:00:00Z",
+            "server": "github.com",
+            "owner": "owner",
+            "repo": repo,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sequence_survives_restart() {
+        let path = "data/test_event_sequence";
+        let _ = std::fs::remove_dir_all(path);
+        let db = database::Database::new_rocksdb(path).unwrap();
+
+        let hub = EventHub::new(&db, CancellationToken::new()).unwrap();
+        let mut receiver = hub.subscribe();
+        hub.publish(&db, vec![event("repo-1"), event("repo-2")]);
+        assert_eq!(receiver.try_recv().unwrap().0, 1);
+        assert_eq!(receiver.try_recv().unwrap().0, 2);
+
+        let hub = EventHub::new(&db, CancellationToken::new()).unwrap();
+        let mut receiver = hub.subscribe();
+        hub.publish(&db, vec![event("repo-3")]);
+        assert_eq!(receiver.try_recv().unwrap().0, 3);
+
+        let page = db
+            .list_events(database::ListEventsParams {
+                after: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        let repos: Vec<(u64, &str)> = page
+            .items
+            .iter()
+            .map(|(sequence, event)| (*sequence, event.repo.as_str()))
+            .collect();
+        assert_eq!(repos, [(2, "repo-2"), (3, "repo-3")]);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
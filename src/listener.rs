@@ -0,0 +1,256 @@
+use std::{
+    env::var,
+    fmt, io,
+    net::SocketAddr,
+    os::{
+        fd::{FromRawFd, RawFd},
+        unix::{fs::FileTypeExt, fs::PermissionsExt, net::UnixDatagram},
+    },
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio_util::either::Either;
+use tracing::warn;
+
+use crate::config::ListenerConfig;
+
+/// The first file descriptor passed by systemd socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+const BACKLOG: i32 = 1024;
+
+pub type Stream = Either<TcpStream, UnixStream>;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    /// With the path of the socket file if it was created here, removed when dropped.
+    Unix(UnixListener, Option<String>),
+}
+
+impl Listener {
+    /// Accept a connection, with the address of the client if it's over TCP.
+    pub async fn accept(&self) -> io::Result<(Stream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                // clients over IPv4 on a dual stack socket are seen as IPv4-mapped IPv6
+                let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+                Ok((Either::Left(stream), Some(addr)))
+            }
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Either::Right(stream), None))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{addr}"),
+                Err(_) => write!(f, "tcp"),
+            },
+            Listener::Unix(listener, _) => match listener.local_addr() {
+                Ok(addr) => match addr.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "unix"),
+                },
+                Err(_) => write!(f, "unix"),
+            },
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, Some(path)) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Bind every listener in `configs`.
+pub fn bind(configs: &[ListenerConfig]) -> io::Result<Vec<Listener>> {
+    let mut listeners = vec![];
+    for config in configs {
+        match config {
+            ListenerConfig::Tcp(addr) => listeners.push(bind_tcp(*addr)?),
+            ListenerConfig::Unix { path, mode } => listeners.push(bind_unix(path, *mode)?),
+            ListenerConfig::Systemd => listeners.extend(systemd_listeners()?),
+        }
+    }
+    Ok(listeners)
+}
+
+fn bind_tcp(addr: SocketAddr) -> io::Result<Listener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(Listener::Tcp(TcpListener::from_std(socket.into())?))
+}
+
+fn bind_unix(path: &String, mode: Option<u32>) -> io::Result<Listener> {
+    // a socket left behind by a previous run would fail the bind
+    if let Ok(metadata) = std::fs::symlink_metadata(path)
+        && metadata.file_type().is_socket()
+    {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let listener = Listener::Unix(listener, Some(path.clone()));
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+/// The sockets passed by systemd, if they are meant for this process.
+fn systemd_listeners() -> io::Result<Vec<Listener>> {
+    let pid = var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    if pid != Some(std::process::id()) {
+        return Err(io::Error::other("no sockets passed by systemd"));
+    }
+    let fds: RawFd = var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse().ok())
+        .ok_or_else(|| io::Error::other("invalid LISTEN_FDS"))?;
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds)
+        .map(|fd| {
+            // SAFETY: systemd passes these descriptors to this process, and they are only taken once
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            from_socket(socket)
+        })
+        .collect()
+}
+
+fn from_socket(socket: Socket) -> io::Result<Listener> {
+    socket.set_cloexec(true)?;
+    socket.set_nonblocking(true)?;
+    let addr = socket.local_addr()?;
+    if addr.as_socket().is_some() {
+        Ok(Listener::Tcp(TcpListener::from_std(socket.into())?))
+    } else if addr.is_unix() {
+        Ok(Listener::Unix(UnixListener::from_std(socket.into())?, None))
+    } else {
+        Err(io::Error::other("unsupported socket passed by systemd"))
+    }
+}
+
+/// Tell systemd about the state of the service, such as `READY=1`, if it's watching.
+pub fn notify(state: &str) {
+    let Ok(path) = var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = notify_to(&path, state) {
+        warn!("failed to notify systemd of {state}: {e}");
+    }
+}
+
+fn notify_to(path: &str, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::IntoRawFd;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn dual_stack() {
+        let listener = match bind_tcp("[::]:0".parse().unwrap()) {
+            Ok(listener) => listener,
+            // IPv6 is disabled on this host
+            Err(_) => return,
+        };
+        let Listener::Tcp(tcp) = &listener else {
+            panic!("not a tcp listener");
+        };
+        let port = tcp.local_addr().unwrap().port();
+
+        for ip in ["127.0.0.1", "::1"] {
+            let client = TcpStream::connect((ip, port)).await.unwrap();
+            let (_, addr) = listener.accept().await.unwrap();
+            assert_eq!(addr.unwrap().ip(), ip.parse::<std::net::IpAddr>().unwrap());
+            drop(client);
+        }
+    }
+
+    #[tokio::test]
+    async fn unix_socket() {
+        let dir = "data/test_listener_unix_socket";
+        std::fs::create_dir_all(dir).unwrap();
+        let path = format!("{dir}/artifact-store.sock");
+        // a stale socket is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let listener = bind_unix(&path, Some(0o660)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        assert_eq!(listener.to_string(), format!("unix:{path}"));
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        let (mut stream, addr) = listener.accept().await.unwrap();
+        assert_eq!(addr, None);
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        drop(listener);
+        assert!(!std::fs::exists(&path).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn inherited_sockets() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        // SAFETY: the descriptor was just released by the listener
+        let socket = unsafe { Socket::from_raw_fd(tcp.into_raw_fd()) };
+        let listener = from_socket(socket).unwrap();
+        assert!(matches!(listener, Listener::Tcp(_)));
+        assert_eq!(listener.to_string(), addr.to_string());
+
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (_, remote) = listener.accept().await.unwrap();
+        assert_eq!(remote, Some(client.local_addr().unwrap()));
+    }
+
+    #[test]
+    fn notify_systemd() {
+        let dir = "data/test_listener_notify";
+        std::fs::create_dir_all(dir).unwrap();
+        let path = format!("{dir}/notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(&path, "READY=1").unwrap();
+        let mut buf = [0; 16];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,86 +1,211 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use axum::extract::Request;
-use hyper::server::conn::http1;
+use axum::extract::{ConnectInfo, Request};
 use hyper::{body::Incoming, service};
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
 use signal::unix::SignalKind;
-use tokio::{net::TcpListener, signal, sync::watch};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{signal, sync::watch};
+use tokio_util::sync::CancellationToken;
 use tower_service::Service;
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
+mod auth;
 mod config;
 mod database;
 mod error;
+mod events;
+mod listener;
+mod oidc;
+mod presign;
+mod ratelimit;
 mod router;
 mod storage;
+mod tls;
+mod webhook;
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt().with_target(false).json().init();
+    let conf = match config::load(std::env::args().skip(1)) {
+        Ok(config::Command::Serve(conf)) => *conf,
+        Ok(config::Command::PrintConfig(output) | config::Command::Help(output)) => {
+            print!("{output}");
+            return;
+        }
+        Err(err) => {
+            eprint!("{err}");
+            std::process::exit(2);
+        }
+    };
+
+    let subscriber = tracing_subscriber::fmt()
+        .with_target(false)
+        .with_max_level(conf.log.level);
+    match conf.log.format {
+        config::LogFormat::Json => subscriber.json().init(),
+        config::LogFormat::Text => subscriber.init(),
+    }
 
-    let conf = config::load();
-    let db = database::Database::new_rocksdb(&conf.rocksdb_path).unwrap();
+    let db = Arc::new(
+        database::Database::new_rocksdb(&conf.rocksdb_path)
+            .unwrap_or_else(|err| fatal(format!("failed to open {}: {err}", conf.rocksdb_path))),
+    );
+    db.migrate_artifact_keys(|params| storage::artifact_file_exists(&conf.artifact_path, params))
+        .unwrap_or_else(|err| fatal(format!("failed to migrate {}: {err}", conf.rocksdb_path)));
+    tokio::spawn(webhook::run(Arc::clone(&db)));
+    tokio::spawn(storage::run_session_expiry(
+        Arc::clone(&db),
+        conf.artifact_path.clone(),
+        conf.timeouts.session_ttl,
+    ));
 
-    let port = 3001;
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    info!(message = "starting server", port = addr.port());
+    let tls = conf
+        .tls
+        .map(|config| Arc::new(tls::Reloader::new(config).unwrap_or_else(|err| fatal(err))));
+    if let Some(tls) = &tls {
+        tokio::spawn(Arc::clone(tls).watch());
+    }
 
-    let listener = TcpListener::bind(&addr).await.unwrap();
-    let app = router::router(conf.artifact_path, db);
+    let shutdown = CancellationToken::new();
+    let listeners = listener::bind(&conf.listeners)
+        .unwrap_or_else(|err| fatal(format!("failed to listen: {err}")));
+    let app = router::router(
+        router::RouterConfig {
+            artifact_path: conf.artifact_path,
+            auth: conf.auth,
+            limits: conf.limits,
+            timeouts: conf.timeouts,
+            shutdown: shutdown.clone(),
+        },
+        db,
+    );
 
     let (close_tx, close_rx) = watch::channel(());
 
+    // HTTP/2 is detected by its preface, whether negotiated with ALPN or with prior knowledge
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(conf.timeouts.header_read);
+
+    for listener in listeners {
+        info!(
+            message = "starting server",
+            address = listener.to_string(),
+            tls = tls.is_some()
+        );
+        tokio::spawn(accept_connections(
+            listener,
+            builder.clone(),
+            app.clone(),
+            tls.clone(),
+            close_rx.clone(),
+        ));
+    }
+    drop(close_rx);
+    listener::notify("READY=1");
+
+    shutdown_signal().await;
+    listener::notify("STOPPING=1");
+    shutdown.cancel();
+
+    debug!("waiting for {} tasks to finish", close_tx.receiver_count());
+    close_tx.closed().await;
+}
+
+/// Log an error that keeps the server from starting, and exit.
+fn fatal(message: impl std::fmt::Display) -> ! {
+    error!("{message}");
+    std::process::exit(1);
+}
+
+/// Accept connections on `listener` until a shutdown signal, serving each in its own task.
+/// Every task holds `close_rx` until it's done.
+async fn accept_connections(
+    listener: listener::Listener,
+    builder: auto::Builder<TokioExecutor>,
+    app: axum::Router,
+    tls: Option<Arc<tls::Reloader>>,
+    close_rx: watch::Receiver<()>,
+) {
     loop {
         let (socket, remote_addr) = tokio::select! {
-            result = listener.accept() => {
-                result.unwrap()
-            }
+            result = listener.accept() => match result {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("failed to accept connection on {listener}: {err}");
+                    continue;
+                }
+            },
             _ = shutdown_signal() => {
-                debug!("signal received, not accepting new connections");
+                debug!("signal received, not accepting new connections on {listener}");
                 break;
             }
         };
-        debug!("connection {remote_addr} accepted");
+        let peer = match remote_addr {
+            Some(addr) => addr.to_string(),
+            None => listener.to_string(),
+        };
+        debug!("connection {peer} accepted");
 
         let tower_service = app.clone();
         let close_rx = close_rx.clone();
+        let tls = tls.clone();
+        let builder = builder.clone();
         tokio::spawn(async move {
-            let socket = TokioIo::new(socket);
-            let hyper_service = service::service_fn(move |request: Request<Incoming>| {
-                tower_service.clone().call(request)
-            });
-
-            let conn = http1::Builder::new()
-                .serve_connection(socket, hyper_service)
-                .with_upgrades();
-            let mut conn = std::pin::pin!(conn);
-
-            loop {
-                tokio::select! {
-                    result = conn.as_mut() => {
-                        if let Err(err) = result {
-                            debug!("failed to serve connection: {err:#}");
-                        }
-                        break;
-                    }
-                    _ = shutdown_signal() => {
-                        debug!("signal received, starting graceful shutdown");
-                        conn.as_mut().graceful_shutdown();
+            match tls {
+                Some(tls) => match tls.accept(socket).await {
+                    Ok(stream) => {
+                        serve_connection(stream, &builder, tower_service, remote_addr).await
                     }
-                }
+                    Err(err) => debug!("tls handshake with {peer} failed: {err}"),
+                },
+                None => serve_connection(socket, &builder, tower_service, remote_addr).await,
             }
 
-            debug!("connection {remote_addr} closed");
+            debug!("connection {peer} closed");
             drop(close_rx);
         });
     }
+}
 
-    drop(close_rx);
-    drop(listener);
+/// Serve HTTP/1.1 or HTTP/2 on a connection until it's closed, or gracefully shut down on a signal.
+async fn serve_connection<I>(
+    io: I,
+    builder: &auto::Builder<TokioExecutor>,
+    tower_service: axum::Router,
+    remote_addr: Option<SocketAddr>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let socket = TokioIo::new(io);
+    let hyper_service = service::service_fn(move |mut request: Request<Incoming>| {
+        if let Some(remote_addr) = remote_addr {
+            request.extensions_mut().insert(ConnectInfo(remote_addr));
+        }
+        tower_service.clone().call(request)
+    });
 
-    debug!("waiting for {} tasks to finish", close_tx.receiver_count());
-    close_tx.closed().await;
+    let conn = builder.serve_connection_with_upgrades(socket, hyper_service);
+    let mut conn = std::pin::pin!(conn);
+
+    loop {
+        tokio::select! {
+            result = conn.as_mut() => {
+                if let Err(err) = result {
+                    debug!("failed to serve connection: {err:#}");
+                }
+                break;
+            }
+            _ = shutdown_signal() => {
+                debug!("signal received, starting graceful shutdown");
+                conn.as_mut().graceful_shutdown();
+            }
+        }
+    }
 }
 
 async fn shutdown_signal() {
@@ -106,3 +231,95 @@ async fn shutdown_signal() {
         _ = terminate => {},
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use http_body_util::BodyExt;
+    use hyper::client::conn::{http1 as client_http1, http2 as client_http2};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn start_server(header_read_timeout: Duration) -> SocketAddr {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(header_read_timeout);
+        let app = axum::Router::new().route(
+            "/",
+            get(|request: Request| async move { format!("{:?}", request.version()) }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (socket, remote_addr) = listener.accept().await.unwrap();
+                let (builder, app) = (builder.clone(), app.clone());
+                tokio::spawn(async move {
+                    serve_connection(socket, &builder, app, Some(remote_addr)).await
+                });
+            }
+        });
+        addr
+    }
+
+    fn request(addr: SocketAddr) -> hyper::Request<Body> {
+        hyper::Request::builder()
+            .uri(format!("http://{addr}/"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn body(response: hyper::Response<Incoming>) -> String {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serve_http1_and_h2c() {
+        let addr = start_server(Duration::from_secs(10)).await;
+
+        let io = TokioIo::new(TcpStream::connect(addr).await.unwrap());
+        let (mut sender, conn) = client_http1::handshake(io).await.unwrap();
+        tokio::spawn(conn);
+        let response = sender.send_request(request(addr)).await.unwrap();
+        assert_eq!(body(response).await, "HTTP/1.1");
+
+        // prior knowledge, multiplexing requests on one connection
+        let io = TokioIo::new(TcpStream::connect(addr).await.unwrap());
+        let (sender, conn) = client_http2::handshake(TokioExecutor::new(), io)
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let responses = futures_util::future::join_all((0..10).map(|_| {
+            let mut sender = sender.clone();
+            async move { sender.send_request(request(addr)).await.unwrap() }
+        }))
+        .await;
+        for response in responses {
+            assert_eq!(response.version(), hyper::Version::HTTP_2);
+            assert_eq!(body(response).await, "HTTP/2.0");
+        }
+    }
+
+    #[tokio::test]
+    async fn header_read_timeout() {
+        let addr = start_server(Duration::from_millis(200)).await;
+
+        // headers that never end
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n")
+            .await
+            .unwrap();
+        let mut response = vec![];
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+            .await
+            .expect("connection kept open")
+            .unwrap();
+        assert!(response.is_empty() || response.starts_with(b"HTTP/1.1 408"));
+    }
+}
@@ -0,0 +1,364 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::{DecodingKey, Validation, jwk::JwkSet};
+use serde_json::{Map, Value};
+use tracing::{debug, warn};
+
+use crate::config::{JwksSource, OidcConfig};
+use crate::error::HandleRequestError;
+
+/// How long keys are used before they are read again.
+const JWKS_CACHE_DURATION: Duration = Duration::from_secs(10 * 60);
+/// Keys are read again on an unknown key id, or after failing to read them, but not more
+/// often than this, so that tokens with made up key ids can't flood the issuer.
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a verified token was issued for.
+#[derive(Debug, PartialEq)]
+pub struct Claims {
+    /// The `sub` claim, identifying the job in the audit log.
+    pub subject: String,
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    pub commit: String,
+}
+
+/// Verifies OIDC ID tokens issued to CI jobs.
+pub struct Verifier {
+    config: OidcConfig,
+    client: reqwest::Client,
+    keys: Mutex<KeyCache>,
+    /// Held while keys are read, so that requests waiting for them make a single read.
+    refresh: tokio::sync::Mutex<()>,
+}
+
+/// The last keys read, kept when reading them again fails.
+struct KeyCache {
+    keys: Option<JwkSet>,
+    /// Until when the keys are used without reading them again.
+    fresh_until: Instant,
+    /// Until when the keys aren't read again, even for an unknown key id.
+    retry_after: Instant,
+}
+
+impl Verifier {
+    pub fn new(config: OidcConfig) -> Self {
+        Verifier {
+            config,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap(),
+            keys: Mutex::new(KeyCache {
+                keys: None,
+                fresh_until: Instant::now(),
+                retry_after: Instant::now(),
+            }),
+            refresh: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Verify the signature, issuer, audience and expiry of `token`,
+    /// and map its claims onto the repository and commit it was issued for.
+    pub async fn verify(&self, token: &str) -> Result<Claims, HandleRequestError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            HandleRequestError::Unauthorized(format!("invalid oidc token: {e}"))
+        };
+
+        let header = jsonwebtoken::decode_header(token).map_err(|e| invalid(&e))?;
+        let jwk = self
+            .find_key(header.kid.as_deref())
+            .await?
+            .ok_or_else(|| invalid(&"unknown key"))?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| invalid(&e))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        let claims = jsonwebtoken::decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(|e| invalid(&e))?
+            .claims;
+
+        let claim = |name: &str| {
+            claims
+                .get(name)
+                .and_then(Value::as_str)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| invalid(&format!("missing {name} claim")))
+        };
+        let repository = claim(&self.config.repository_claim)?;
+        let (owner, repo) = repository
+            .rsplit_once('/')
+            .ok_or_else(|| invalid(&format!("invalid {} claim", self.config.repository_claim)))?;
+        Ok(Claims {
+            subject: claim("sub").unwrap_or(repository).to_string(),
+            server: self.config.server.clone(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            commit: claim(&self.config.commit_claim)?.to_string(),
+        })
+    }
+
+    /// Find the key with `kid`, or the only key if the token doesn't name one.
+    async fn find_key(
+        &self,
+        kid: Option<&str>,
+    ) -> Result<Option<jsonwebtoken::jwk::Jwk>, HandleRequestError> {
+        let find = |keys: &JwkSet| match kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        };
+
+        let cached = |keys: &KeyCache| {
+            let now = Instant::now();
+            let key = keys.keys.as_ref().and_then(find);
+            let fresh = now < keys.fresh_until && key.is_some();
+            (fresh || now < keys.retry_after).then_some(key)
+        };
+
+        if let Some(key) = cached(&self.keys.lock().unwrap()) {
+            return Ok(key);
+        }
+        let _refresh = self.refresh.lock().await;
+        // read meanwhile by the request holding the lock before
+        if let Some(key) = cached(&self.keys.lock().unwrap()) {
+            return Ok(key);
+        }
+
+        let result = self.read_keys().await;
+        let now = Instant::now();
+        let mut cached = self.keys.lock().unwrap();
+        cached.retry_after = now + JWKS_MIN_REFRESH_INTERVAL;
+        match result {
+            Ok(keys) => {
+                let key = find(&keys);
+                cached.keys = Some(keys);
+                cached.fresh_until = now + JWKS_CACHE_DURATION;
+                Ok(key)
+            }
+            // the issuer may be down for a while, its keys rarely change meanwhile
+            Err(e) => match cached.keys.as_ref() {
+                Some(keys) => {
+                    warn!("using the last jwks read: {e}");
+                    Ok(find(keys))
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn read_keys(&self) -> Result<JwkSet, HandleRequestError> {
+        let body = match &self.config.jwks {
+            JwksSource::File(path) => tokio::fs::read(path)
+                .await
+                .map_err(|e| format!("failed to read jwks from {path}: {e}"))?,
+            JwksSource::Url(url) => {
+                debug!("fetching jwks from {url}");
+                self.client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| format!("failed to fetch jwks from {url}: {e}"))?
+                    .bytes()
+                    .await
+                    .map_err(|e| format!("failed to fetch jwks from {url}: {e}"))?
+                    .to_vec()
+            }
+        };
+        Ok(serde_json::from_slice(&body).map_err(|e| format!("invalid jwks: {e}"))?)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use base64::Engine;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    pub const ISSUER: &str = "https://token.example.dev";
+    pub const AUDIENCE: &str = "https://artifacts.example.dev";
+
+    /// A locally generated signing key, and the JWKS to verify its tokens with.
+    pub struct TestKey {
+        kid: String,
+        encoding_key: EncodingKey,
+        pub jwks: String,
+    }
+
+    impl TestKey {
+        pub fn generate(kid: &str) -> Self {
+            let rng = ring::rand::SystemRandom::new();
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+            let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            let x = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(key_pair.public_key().as_ref());
+            let jwks = serde_json::json!({
+                "keys": [{"kty": "OKP", "crv": "Ed25519", "alg": "EdDSA", "kid": kid, "x": x}],
+            });
+            TestKey {
+                kid: kid.to_string(),
+                encoding_key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+                jwks: jwks.to_string(),
+            }
+        }
+
+        /// Sign a token for `repository` and `sha`, valid for an hour.
+        pub fn sign(&self, repository: &str, sha: &str) -> String {
+            self.sign_claims(serde_json::json!({
+                "iss": ISSUER,
+                "aud": AUDIENCE,
+                "sub": format!("repo:{repository}:ref:refs/heads/main"),
+                "exp": jsonwebtoken::get_current_timestamp() + 3600,
+                "repository": repository,
+                "sha": sha,
+            }))
+        }
+
+        pub fn sign_claims(&self, claims: Value) -> String {
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some(self.kid.clone());
+            jsonwebtoken::encode(&header, &claims, &self.encoding_key).unwrap()
+        }
+    }
+
+    pub fn config(jwks: JwksSource) -> OidcConfig {
+        OidcConfig {
+            issuer: ISSUER.to_string(),
+            audience: AUDIENCE.to_string(),
+            jwks,
+            server: "github.com".to_string(),
+            repository_claim: "repository".to_string(),
+            commit_claim: "sha".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_token() {
+        let path = "data/test_oidc_verify_token";
+        std::fs::create_dir_all(path).unwrap();
+        let key = TestKey::generate("key-1");
+        std::fs::write(format!("{path}/jwks.json"), &key.jwks).unwrap();
+        let verifier = Verifier::new(config(JwksSource::File(format!("{path}/jwks.json"))));
+
+        let claims = verifier
+            .verify(&key.sign("owner/repo", "commit-1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            claims,
+            Claims {
+                subject: "repo:owner/repo:ref:refs/heads/main".to_string(),
+                server: "github.com".to_string(),
+                owner: "owner".to_string(),
+                repo: "repo".to_string(),
+                commit: "commit-1".to_string(),
+            }
+        );
+
+        let exp = jsonwebtoken::get_current_timestamp() + 3600;
+        let rejected = [
+            // wrong issuer, audience, expired, no commit, no repository owner
+            serde_json::json!({"iss": "https://other.example.dev", "aud": AUDIENCE, "exp": exp, "repository": "owner/repo", "sha": "commit-1"}),
+            serde_json::json!({"iss": ISSUER, "aud": "other", "exp": exp, "repository": "owner/repo", "sha": "commit-1"}),
+            serde_json::json!({"iss": ISSUER, "aud": AUDIENCE, "exp": 1000, "repository": "owner/repo", "sha": "commit-1"}),
+            serde_json::json!({"iss": ISSUER, "aud": AUDIENCE, "exp": exp, "repository": "owner/repo"}),
+            serde_json::json!({"iss": ISSUER, "aud": AUDIENCE, "exp": exp, "repository": "repo", "sha": "commit-1"}),
+        ];
+        for claims in rejected {
+            let result = verifier.verify(&key.sign_claims(claims.clone())).await;
+            assert!(
+                matches!(result, Err(HandleRequestError::Unauthorized(_))),
+                "{claims}"
+            );
+        }
+
+        // signed by a key that isn't in the JWKS, under a known and an unknown key id
+        for kid in ["key-1", "key-2"] {
+            let token = TestKey::generate(kid).sign("owner/repo", "commit-1");
+            let result = verifier.verify(&token).await;
+            assert!(matches!(result, Err(HandleRequestError::Unauthorized(_))));
+        }
+        assert!(verifier.verify("not.a.token").await.is_err());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetch_and_cache_jwks() {
+        let key = TestKey::generate("key-1");
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/jwks",
+            axum::routing::get({
+                let fetches = Arc::clone(&fetches);
+                let jwks = key.jwks.clone();
+                async move || {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    jwks
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let verifier = Verifier::new(config(JwksSource::Url(format!("http://{addr}/jwks"))));
+
+        for commit in ["commit-1", "commit-2"] {
+            let claims = verifier.verify(&key.sign("owner/repo", commit)).await;
+            assert_eq!(claims.unwrap().commit, commit);
+        }
+        // an unknown key id doesn't refetch right away
+        let token = TestKey::generate("key-2").sign("owner/repo", "commit-1");
+        assert!(verifier.verify(&token).await.is_err());
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn keep_jwks_when_fetch_fails() {
+        let key = TestKey::generate("key-1");
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/jwks",
+            axum::routing::get({
+                let fetches = Arc::clone(&fetches);
+                let jwks = key.jwks.clone();
+                async move || match fetches.fetch_add(1, Ordering::SeqCst) {
+                    0 => Ok(jwks),
+                    _ => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let verifier = Verifier::new(config(JwksSource::Url(format!("http://{addr}/jwks"))));
+
+        let token = key.sign("owner/repo", "commit-1");
+        assert!(verifier.verify(&token).await.is_ok());
+        {
+            let mut cached = verifier.keys.lock().unwrap();
+            cached.fresh_until = Instant::now();
+            cached.retry_after = Instant::now();
+        }
+
+        // the expired keys are still used while the issuer fails,
+        // and it isn't asked again right away
+        for _ in 0..2 {
+            assert!(verifier.verify(&token).await.is_ok());
+        }
+        let token = TestKey::generate("key-2").sign("owner/repo", "commit-1");
+        assert!(verifier.verify(&token).await.is_err());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,130 @@
+use axum::http::Method;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::auth;
+
+/// The longest a pre-signed URL may be valid for, 7 days.
+pub const MAX_EXPIRES_IN: u64 = 7 * 24 * 60 * 60;
+
+/// The query parameters of a pre-signed URL.
+#[derive(Deserialize)]
+pub struct SignedQuery {
+    /// When the URL expires, in seconds since epoch.
+    pub expires: u64,
+    /// The hex encoded HMAC-SHA256 of the method, path and expiry.
+    pub signature: String,
+}
+
+fn mac(secret: &str, method: &Method, path: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{method}\n{path}\n{expires}").as_bytes());
+    mac
+}
+
+/// Sign `method` on `path` until `expires`.
+pub fn sign(secret: &str, method: &Method, path: &str, expires: u64) -> String {
+    hex::encode(mac(secret, method, path, expires).finalize().into_bytes())
+}
+
+/// Whether `query` signs `method` on `path`, and hasn't expired at `now`.
+/// A URL signed for `GET` is also valid for `HEAD`.
+pub fn verify(secret: &str, method: &Method, path: &str, query: &SignedQuery, now: u64) -> bool {
+    if query.expires < now {
+        return false;
+    }
+    let method = if method == Method::HEAD {
+        &Method::GET
+    } else {
+        method
+    };
+    let Ok(signature) = hex::decode(&query.signature) else {
+        return false;
+    };
+    mac(secret, method, path, query.expires)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+/// The repository and commit of an artifact path, `/{server}/{owner}/{repo}/{commit}/{path}`,
+/// decoded like the route params of a request to it.
+pub fn target(path: &str) -> Option<auth::Target> {
+    let parts: Vec<&str> = path.strip_prefix('/')?.splitn(5, '/').collect();
+    if parts.len() < 5 || parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+    let decode = |part: &str| {
+        percent_decode_str(part)
+            .decode_utf8()
+            .ok()
+            .map(String::from)
+    };
+    let commit = decode(parts[3])?;
+    if commit.starts_with('@') {
+        return None;
+    }
+    Some(auth::Target {
+        server: Some(decode(parts[0])?),
+        owner: Some(decode(parts[1])?),
+        repo: Some(decode(parts[2])?),
+        commit: Some(commit),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let path = "/github.com/owner/repo/commit/a.txt";
+        let query = SignedQuery {
+            expires: 1000,
+            signature: sign("secret", &Method::GET, path, 1000),
+        };
+        assert!(verify("secret", &Method::GET, path, &query, 1000));
+        assert!(verify("secret", &Method::HEAD, path, &query, 999));
+
+        assert!(!verify("secret", &Method::GET, path, &query, 1001));
+        assert!(!verify("secret", &Method::PUT, path, &query, 1000));
+        assert!(!verify("other", &Method::GET, path, &query, 1000));
+        assert!(!verify(
+            "secret",
+            &Method::GET,
+            "/github.com/owner/repo/commit/b.txt",
+            &query,
+            1000
+        ));
+
+        let extended = SignedQuery {
+            expires: 2000,
+            signature: query.signature.clone(),
+        };
+        assert!(!verify("secret", &Method::GET, path, &extended, 1000));
+        let invalid = SignedQuery {
+            expires: 1000,
+            signature: "not hex".to_string(),
+        };
+        assert!(!verify("secret", &Method::GET, path, &invalid, 1000));
+    }
+
+    #[test]
+    fn test_target() {
+        let decoded = target("/github.com/owner/repo%2Dx/commit/dir/a%20b.txt").unwrap();
+        assert_eq!(decoded.repo.as_deref(), Some("repo-x"));
+        assert_eq!(decoded.commit.as_deref(), Some("commit"));
+
+        for path in [
+            "github.com/owner/repo/commit/a.txt",
+            "/github.com/owner/repo/commit",
+            "/github.com//repo/commit/a.txt",
+            "/github.com/owner/repo/@latest/a.txt",
+            "/github.com/owner/repo/%40latest/a.txt",
+            "/github.com/owner/repo%FF/commit/a.txt",
+        ] {
+            assert!(target(path).is_none(), "{path}");
+        }
+    }
+}
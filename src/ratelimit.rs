@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::body::Body;
+use futures_util::StreamExt;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+
+use crate::config::{LimitsConfig, Rate};
+
+/// Once there are this many buckets, full ones are forgotten, as they would start full anyway.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Whom a bucket limits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Client(IpAddr),
+    Identity(String),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Rate, now: Instant) -> Self {
+        Bucket {
+            tokens: rate.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst);
+        self.updated = now;
+    }
+
+    /// How long until the bucket holds `tokens`, or `Duration::MAX` if the rate is too slow
+    /// for it to be a duration.
+    fn wait_for(&self, rate: Rate, tokens: f64) -> Duration {
+        let seconds = ((tokens - self.tokens) / rate.per_second).max(0.0);
+        Duration::try_from_secs_f64(seconds).unwrap_or(Duration::MAX)
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    requests: Option<Bucket>,
+    /// May go into debt, as bytes are only known once transferred.
+    bytes: Option<Bucket>,
+}
+
+/// Token-bucket rate limits on requests and bytes, and a cap on uploads in progress.
+pub struct Limiter {
+    config: LimitsConfig,
+    buckets: Mutex<HashMap<Key, Buckets>>,
+    uploads: Option<Arc<Semaphore>>,
+}
+
+impl Limiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        Limiter {
+            uploads: config
+                .max_concurrent_uploads
+                .map(|max| Arc::new(Semaphore::new(max))),
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.requests.is_some() || self.config.bytes.is_some()
+    }
+
+    /// Take a request from the bucket of each key. If any of them is empty, or in debt for bytes,
+    /// nothing is taken and how long to wait is returned.
+    pub fn check(&self, keys: &[Key], now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, buckets| !self.is_full(buckets, now));
+        }
+
+        let mut wait = Duration::ZERO;
+        for key in keys {
+            let buckets = buckets.entry(key.clone()).or_default();
+            if let Some(rate) = self.config.requests {
+                let bucket = buckets
+                    .requests
+                    .get_or_insert_with(|| Bucket::new(rate, now));
+                bucket.refill(rate, now);
+                wait = wait.max(bucket.wait_for(rate, 1.0));
+            }
+            if let Some(rate) = self.config.bytes {
+                let bucket = buckets.bytes.get_or_insert_with(|| Bucket::new(rate, now));
+                bucket.refill(rate, now);
+                wait = wait.max(bucket.wait_for(rate, 0.0));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(key).and_then(|b| b.requests.as_mut()) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Charge transferred bytes to each key.
+    pub fn charge(&self, keys: &[Key], bytes: usize, now: Instant) {
+        let Some(rate) = self.config.bytes else {
+            return;
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        for key in keys {
+            let buckets = buckets.entry(key.clone()).or_default();
+            let bucket = buckets.bytes.get_or_insert_with(|| Bucket::new(rate, now));
+            bucket.refill(rate, now);
+            bucket.tokens -= bytes as f64;
+        }
+    }
+
+    /// Charge the bytes of `body` to `keys` as it's streamed.
+    pub fn meter(self: &Arc<Self>, body: Body, keys: Vec<Key>) -> Body {
+        if self.config.bytes.is_none() || keys.is_empty() {
+            return body;
+        }
+        let limiter = Arc::clone(self);
+        Body::from_stream(body.into_data_stream().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                limiter.charge(&keys, chunk.len(), Instant::now());
+            }
+        }))
+    }
+
+    /// Take a slot for an upload, held until the permit is dropped.
+    pub fn start_upload(&self) -> Result<Option<OwnedSemaphorePermit>, TryAcquireError> {
+        match &self.uploads {
+            Some(uploads) => Arc::clone(uploads).try_acquire_owned().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn is_full(&self, buckets: &mut Buckets, now: Instant) -> bool {
+        let full = |bucket: &mut Option<Bucket>, rate: Option<Rate>| match (bucket, rate) {
+            (Some(bucket), Some(rate)) => {
+                bucket.refill(rate, now);
+                bucket.tokens >= rate.burst
+            }
+            _ => true,
+        };
+        full(&mut buckets.requests, self.config.requests)
+            && full(&mut buckets.bytes, self.config.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests: Option<Rate>, bytes: Option<Rate>) -> Limiter {
+        Limiter::new(LimitsConfig {
+            requests,
+            bytes,
+            max_concurrent_uploads: Some(1),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_request_rate() {
+        let limiter = limiter(
+            Some(Rate {
+                per_second: 2.0,
+                burst: 3.0,
+            }),
+            None,
+        );
+        let client = [Key::Client("127.0.0.1".parse().unwrap())];
+        let identity = [Key::Identity("token".to_string())];
+        let both = [identity[0].clone(), client[0].clone()];
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(&client, now), Ok(()));
+        }
+        assert_eq!(limiter.check(&client, now), Err(Duration::from_millis(500)));
+        // other keys have their own bucket, but a request needs every one of its keys
+        assert_eq!(limiter.check(&identity, now), Ok(()));
+        assert!(limiter.check(&both, now).is_err());
+        assert_eq!(limiter.check(&identity, now), Ok(()));
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check(&client, later), Ok(()));
+        assert!(limiter.check(&client, later).is_err());
+        // refills up to the burst only
+        let much_later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(&client, much_later), Ok(()));
+        }
+        assert!(limiter.check(&client, much_later).is_err());
+    }
+
+    #[test]
+    fn test_slow_rate() {
+        let limiter = limiter(
+            Some(Rate {
+                per_second: 1e-300,
+                burst: 1.0,
+            }),
+            None,
+        );
+        let client = [Key::Client("127.0.0.1".parse().unwrap())];
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(&client, now), Ok(()));
+        assert_eq!(limiter.check(&client, now), Err(Duration::MAX));
+    }
+
+    #[test]
+    fn test_byte_rate() {
+        let limiter = limiter(
+            None,
+            Some(Rate {
+                per_second: 100.0,
+                burst: 100.0,
+            }),
+        );
+        let keys = [Key::Identity("token".to_string())];
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(&keys, now), Ok(()));
+        limiter.charge(&keys, 100, now);
+        // an empty bucket isn't in debt yet
+        assert_eq!(limiter.check(&keys, now), Ok(()));
+        limiter.charge(&keys, 300, now);
+        assert_eq!(limiter.check(&keys, now), Err(Duration::from_secs(3)));
+        assert_eq!(limiter.check(&keys, now + Duration::from_secs(3)), Ok(()));
+    }
+
+    #[test]
+    fn test_concurrent_uploads() {
+        let limiter = limiter(None, None);
+        let upload = limiter.start_upload().unwrap();
+        assert!(upload.is_some());
+        assert!(limiter.start_upload().is_err());
+        drop(upload);
+        assert!(limiter.start_upload().unwrap().is_some());
+
+        let unlimited = Limiter::new(LimitsConfig::default());
+        assert!(unlimited.start_upload().unwrap().is_none());
+    }
+}
@@ -1,57 +1,197 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use axum::{
     Json, Router,
     body::Body,
-    extract::{Path, State},
-    response::{Html, IntoResponse},
-    routing::{get, put},
+    extract::{ConnectInfo, Extension, MatchedPath, Path, Query, RawPathParams, Request, State},
+    http::{HeaderValue, Method},
+    middleware::{self, Next},
+    response::{
+        Html, IntoResponse, Response,
+        sse::{KeepAlive, Sse},
+    },
+    routing::{delete, get, post, put},
 };
-use hyper::{StatusCode, header};
+use hyper::{HeaderMap, StatusCode, header};
 use serde::Serialize;
 use tokio::sync::RwLock;
-use tower_http::timeout::TimeoutLayer;
+use tokio_util::sync::CancellationToken;
 use tower_http::{
     LatencyUnit,
     trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
 };
-use tracing::Level;
+use tracing::{Level, error};
 
+use crate::config::{AuthConfig, LimitsConfig, TimeoutsConfig};
 use crate::storage;
-use crate::{database, error::HandleRequestError};
+use crate::{auth, database, error::HandleRequestError, events, oidc, presign, ratelimit};
 
-const TIMEOUT_SECONDS: u64 = 10;
+/// The route of uploading and downloading artifacts, the only one pre-signed URLs are for.
+const ARTIFACT_ROUTE: &str = "/{server}/{owner}/{repo}/{commit}/{*path}";
 
 type SharedState = Arc<RwLock<RouterState>>;
 
 pub struct RouterState {
     pub artifact_path: String,
-    pub db: database::Database,
+    pub auth: AuthConfig,
+    pub oidc: Option<oidc::Verifier>,
+    pub db: Arc<database::Database>,
+    pub events: events::EventHub,
+    pub limiter: Arc<ratelimit::Limiter>,
+    pub uploads: storage::UploadLimits,
+    pub timeouts: TimeoutsConfig,
 }
 
-pub fn router(artifact_path: String, db: database::Database) -> Router {
-    let shared_state = SharedState::new(RwLock::new(RouterState { artifact_path, db }));
+pub struct RouterConfig {
+    pub artifact_path: String,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    pub timeouts: TimeoutsConfig,
+    /// Cancelled on shutdown, to end long-lived responses such as event streams.
+    pub shutdown: CancellationToken,
+}
+
+impl RouterConfig {
+    /// A config with authentication and limits disabled.
+    #[cfg(test)]
+    pub fn new(artifact_path: String) -> Self {
+        RouterConfig {
+            artifact_path,
+            auth: AuthConfig::default(),
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+}
+
+/// `db` can be shared with background tasks, such as the webhook worker, by passing an `Arc`.
+pub fn router(config: RouterConfig, db: impl Into<Arc<database::Database>>) -> Router {
+    let db = db.into();
+    let events = events::EventHub::new(&db, config.shutdown).unwrap();
+    let shared_state = SharedState::new(RwLock::new(RouterState {
+        artifact_path: config.artifact_path,
+        oidc: config.auth.oidc.clone().map(oidc::Verifier::new),
+        auth: config.auth,
+        db,
+        events,
+        uploads: storage::UploadLimits::new(&config.limits, &config.timeouts),
+        limiter: Arc::new(ratelimit::Limiter::new(config.limits)),
+        timeouts: config.timeouts,
+    }));
 
     Router::new()
         .route("/", get(index_handler))
         .route("/robots.txt", get(robots_handler))
         .route("/ping", get(ping_handler))
         .route("/repositories", get(list_repos_handler))
+        .route("/{server}", get(list_repos_handler))
+        .route("/{server}/{owner}", get(list_repos_handler))
         .route("/{server}/{owner}/{repo}", get(list_commits_handler))
         .route(
             "/{server}/{owner}/{repo}/{commit}",
-            get(list_artifacts_handler),
+            get(list_artifacts_handler).put(put_commit_handler),
+        )
+        .route(
+            "/{server}/{owner}/{repo}/@commits/{commit}",
+            get(get_commit_handler),
+        )
+        .route(
+            "/{server}/{owner}/{repo}/@commits/{commit}/status",
+            put(set_commit_status_handler),
+        )
+        .route(
+            "/{server}/{owner}/{repo}/@commits/{commit}/seal",
+            put(seal_commit_handler),
+        )
+        .route("/{server}/{owner}/{repo}/@refs", get(list_refs_handler))
+        .route(
+            "/{server}/{owner}/{repo}/@channels",
+            get(list_channels_handler),
+        )
+        .route(
+            "/{server}/{owner}/{repo}/@channels/{channel}",
+            get(list_promotions_handler).post(promote_handler),
+        )
+        .route(
+            "/{server}/{owner}/{repo}/@refs/{*name}",
+            get(list_ref_history_handler).put(set_ref_handler),
+        )
+        .route("/@events", get(events_handler))
+        .route("/{server}/{owner}/{repo}/@events", get(repo_events_handler))
+        .route("/audit", get(list_audit_handler))
+        .route(
+            "/@tokens",
+            get(list_tokens_handler).post(create_token_handler),
+        )
+        .route("/@tokens/{id}", delete(delete_token_handler))
+        .route("/@presign", post(presign_handler))
+        .route("/@acls", get(list_acls_handler))
+        .route(
+            "/@acls/{server}",
+            get(get_acl_handler)
+                .put(set_acl_handler)
+                .delete(delete_acl_handler),
+        )
+        .route(
+            "/@acls/{server}/{owner}",
+            get(get_acl_handler)
+                .put(set_acl_handler)
+                .delete(delete_acl_handler),
+        )
+        .route(
+            "/@acls/{server}/{owner}/{repo}",
+            get(get_acl_handler)
+                .put(set_acl_handler)
+                .delete(delete_acl_handler),
+        )
+        .route(
+            "/@webhooks",
+            get(list_webhooks_handler).post(create_webhook_handler),
+        )
+        .route("/@webhooks/{id}", delete(delete_webhook_handler))
+        .route(
+            "/{server}/{owner}/{repo}/@webhooks",
+            get(list_repo_webhooks_handler).post(create_repo_webhook_handler),
+        )
+        .route(
+            "/{server}/{owner}/{repo}/@commits/{commit}/sessions",
+            post(create_session_handler),
         )
+        .route("/@sessions/{session}", delete(abort_session_handler))
         .route(
-            "/{server}/{owner}/{repo}/{commit}/{*path}",
-            put(upload_handler),
+            "/@sessions/{session}/publish",
+            post(publish_session_handler),
         )
         .route(
-            "/{server}/{owner}/{repo}/{commit}/{*path}",
-            get(download_handler),
+            "/@sessions/{session}/artifacts/{*path}",
+            put(session_upload_handler),
         )
-        .layer((
+        .route(ARTIFACT_ROUTE, put(upload_handler))
+        .route(ARTIFACT_ROUTE, get(download_handler))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&shared_state),
+            identity_limit_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&shared_state),
+            auth_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&shared_state),
+            audit_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&shared_state),
+            client_limit_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&shared_state),
+            timeout_middleware,
+        ))
+        .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
                 .on_response(
@@ -59,14 +199,327 @@ pub fn router(artifact_path: String, db: database::Database) -> Router {
                         .level(Level::INFO)
                         .latency_unit(LatencyUnit::Micros),
                 ),
-            TimeoutLayer::with_status_code(
-                StatusCode::REQUEST_TIMEOUT,
-                Duration::from_secs(TIMEOUT_SECONDS),
-            ),
-        ))
+        )
         .with_state(Arc::clone(&shared_state))
 }
 
+/// Whether a request uploads an artifact, streaming its body to disk.
+fn is_upload(method: &Method, matched_path: &MatchedPath) -> bool {
+    *method == Method::PUT
+        && matches!(
+            matched_path.as_str(),
+            ARTIFACT_ROUTE | "/@sessions/{session}/artifacts/{*path}"
+        )
+}
+
+/// Answer with 408 if a request takes too long. Uploads have no overall deadline,
+/// as their body has an idle timeout instead.
+async fn timeout_middleware(
+    State(state): State<SharedState>,
+    matched_path: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Response {
+    if is_upload(request.method(), &matched_path) {
+        return next.run(request).await;
+    }
+    let timeout = state.read().await.timeouts.request;
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => SimpleResponse {
+            code: 408,
+            message: "request timed out".to_string(),
+        }
+        .into_response(),
+    }
+}
+
+/// Limit the requests and bytes of each client IP, before anything else is done.
+async fn client_limit_middleware(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = Arc::clone(&state.read().await.limiter);
+    let keys = match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => vec![ratelimit::Key::Client(addr.ip())],
+        None => vec![],
+    };
+    limit(&limiter, keys, request, next).await
+}
+
+/// Limit the requests and bytes of each authenticated identity, and the uploads in progress.
+async fn identity_limit_middleware(
+    State(state): State<SharedState>,
+    matched_path: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = Arc::clone(&state.read().await.limiter);
+    let upload = is_upload(request.method(), &matched_path);
+    // held until the upload is stored
+    let _permit = match upload.then(|| limiter.start_upload()) {
+        Some(Err(_)) => {
+            return too_many_requests(
+                "too many uploads in progress".to_string(),
+                Duration::from_secs(1),
+            );
+        }
+        Some(Ok(permit)) => permit,
+        None => None,
+    };
+
+    let keys = match request
+        .extensions()
+        .get::<auth::Principal>()
+        .and_then(auth::Principal::identity)
+    {
+        Some(identity) => vec![ratelimit::Key::Identity(identity.0)],
+        None => vec![],
+    };
+    limit(&limiter, keys, request, next).await
+}
+
+async fn limit(
+    limiter: &Arc<ratelimit::Limiter>,
+    keys: Vec<ratelimit::Key>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if keys.is_empty() || !limiter.is_enabled() {
+        return next.run(request).await;
+    }
+    if let Err(wait) = limiter.check(&keys, Instant::now()) {
+        return too_many_requests("rate limit exceeded".to_string(), wait);
+    }
+
+    let (parts, body) = request.into_parts();
+    let request = Request::from_parts(parts, limiter.meter(body, keys.clone()));
+    let (parts, body) = next.run(request).await.into_parts();
+    Response::from_parts(parts, limiter.meter(body, keys))
+}
+
+fn too_many_requests(message: String, wait: Duration) -> Response {
+    let mut response =
+        SimpleResponse::from(HandleRequestError::TooManyRequests(message)).into_response();
+    let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+/// Record every request that may change the store in the audit log, whether it succeeded or not.
+async fn audit_middleware(
+    State(state): State<SharedState>,
+    matched_path: MatchedPath,
+    params: RawPathParams,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    if matches!(method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(request).await;
+    }
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let request_id = match request.headers().get("x-request-id") {
+        Some(id) if !id.is_empty() => String::from_utf8_lossy(id.as_bytes()).to_string(),
+        _ => uuid::Uuid::new_v4().simple().to_string(),
+    };
+    let mut entry = storage::AuditEntry {
+        request_id,
+        action: audit_action(&method, matched_path.as_str()),
+        method: method.to_string(),
+        uri: request.uri().path().to_string(),
+        status: 0,
+        client: request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.to_string()),
+        identity: None,
+        params: params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    };
+
+    let mut response = next.run(request).await;
+    entry.status = response.status().as_u16();
+    entry.identity = response
+        .extensions()
+        .get::<auth::Identity>()
+        .map(|identity| identity.0.clone());
+    if let Err(e) = storage::record_audit(&state.read().await.db, time, &entry) {
+        error!(
+            "failed to record request {} in audit log: {e}",
+            entry.request_id
+        );
+    }
+    if let Ok(value) = HeaderValue::from_str(&entry.request_id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+/// Check the bearer token of every request, if authentication is enabled, and the ACL of the
+/// repository it's about. The principal is added to the request, for filtering listings,
+/// and the identity to the response, for the audit log.
+async fn auth_middleware(
+    State(state): State<SharedState>,
+    matched_path: MatchedPath,
+    params: RawPathParams,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let state_guard = state.read().await;
+    let required = auth::required_scope(request.method(), matched_path.as_str());
+    let (true, Some(required)) = (state_guard.auth.enabled, required) else {
+        drop(state_guard);
+        request
+            .extensions_mut()
+            .insert(auth::Principal::Unrestricted);
+        return next.run(request).await;
+    };
+
+    if let Ok(Query(signed)) = Query::<presign::SignedQuery>::try_from_uri(request.uri()) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        // the ACL was checked on the repository of the path when it was signed,
+        // so it must be the one the request is routed to
+        let routed = request_target(&state_guard.db, &params).ok();
+        let valid = matched_path.as_str() == ARTIFACT_ROUTE
+            && routed.is_some()
+            && routed == presign::target(request.uri().path())
+            && state_guard
+                .auth
+                .presign_secret
+                .as_ref()
+                .is_some_and(|secret| {
+                    presign::verify(secret, request.method(), request.uri().path(), &signed, now)
+                });
+        drop(state_guard);
+        if !valid {
+            let e = HandleRequestError::Unauthorized("invalid or expired signature".to_string());
+            return SimpleResponse::from(e).into_response();
+        }
+        request
+            .extensions_mut()
+            .insert(auth::Principal::Unrestricted);
+        return next.run(request).await;
+    }
+
+    let target = match request_target(&state_guard.db, &params) {
+        Ok(target) => target,
+        Err(e) => return SimpleResponse::from(e).into_response(),
+    };
+    let principal = match auth::authorize(
+        &state_guard.db,
+        &state_guard.auth,
+        state_guard.oidc.as_ref(),
+        request.headers(),
+        required,
+        &target,
+    )
+    .await
+    {
+        Ok(principal) => principal,
+        Err(e) => {
+            let unauthorized = matches!(e, HandleRequestError::Unauthorized(_));
+            let mut response = SimpleResponse::from(e).into_response();
+            if unauthorized {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            return response;
+        }
+    };
+
+    if let Err(e) = auth::check_permission(
+        &state_guard.db,
+        &principal,
+        request.method(),
+        matched_path.as_str(),
+        &target,
+    ) {
+        let mut response = SimpleResponse::from(e).into_response();
+        if let Some(identity) = principal.identity() {
+            response.extensions_mut().insert(identity);
+        }
+        return response;
+    }
+    drop(state_guard);
+
+    let identity = principal.identity();
+    request.extensions_mut().insert(principal);
+    let mut response = next.run(request).await;
+    if let Some(identity) = identity {
+        response.extensions_mut().insert(identity);
+    }
+    response
+}
+
+/// The repository a request is about, from its path, or from its upload session.
+fn request_target(
+    db: &database::Database,
+    params: &RawPathParams,
+) -> Result<auth::Target, HandleRequestError> {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    if let Some(session) = param("session") {
+        return Ok(match db.get_session(&session)? {
+            Some(session) => auth::Target {
+                server: Some(session.server),
+                owner: Some(session.owner),
+                repo: Some(session.repo),
+                commit: Some(session.commit),
+            },
+            None => auth::Target::default(),
+        });
+    }
+    Ok(auth::Target {
+        server: param("server"),
+        owner: param("owner"),
+        repo: param("repo"),
+        commit: param("commit"),
+    })
+}
+
+/// Name the operation of a mutating request by its route.
+fn audit_action(method: &Method, route: &str) -> String {
+    let action = match (method.as_str(), route) {
+        ("PUT", "/{server}/{owner}/{repo}/{commit}/{*path}") => "upload",
+        ("PUT", "/{server}/{owner}/{repo}/{commit}") => "set_commit_metadata",
+        ("PUT", "/{server}/{owner}/{repo}/@commits/{commit}/status") => "set_commit_status",
+        ("PUT", "/{server}/{owner}/{repo}/@commits/{commit}/seal") => "seal",
+        ("POST", "/{server}/{owner}/{repo}/@channels/{channel}") => "promote",
+        ("PUT", "/{server}/{owner}/{repo}/@refs/{*name}") => "set_ref",
+        ("POST", "/{server}/{owner}/{repo}/@commits/{commit}/sessions") => "create_session",
+        ("PUT", "/@sessions/{session}/artifacts/{*path}") => "upload_session_artifact",
+        ("POST", "/@sessions/{session}/publish") => "publish_session",
+        ("DELETE", "/@sessions/{session}") => "abort_session",
+        ("POST", "/@webhooks") | ("POST", "/{server}/{owner}/{repo}/@webhooks") => "create_webhook",
+        ("DELETE", "/@webhooks/{id}") => "delete_webhook",
+        ("POST", "/@tokens") => "create_token",
+        ("DELETE", "/@tokens/{id}") => "revoke_token",
+        ("POST", "/@presign") => "presign",
+        ("PUT", route) if route.starts_with("/@acls/") => "set_acl",
+        ("DELETE", route) if route.starts_with("/@acls/") => "delete_acl",
+        (method, route) => return format!("{method} {route}"),
+    };
+    action.to_string()
+}
+
 async fn index_handler() -> Html<&'static str> {
     Html("<h1>Artifact Store</h1>")
 }
@@ -85,563 +538,3372 @@ struct SimpleResponse {
     message: String,
 }
 
-async fn list_repos_handler(State(state): State<SharedState>) -> impl IntoResponse {
-    let db = &state.read().await.db;
-    let response = match storage::list_repos(db).await {
-        Ok(res) => res,
-        Err(e) => {
-            let response = SimpleResponse {
-                code: 500,
-                message: format!("{e}"),
-            };
-            return serde_json::to_string(&response).unwrap();
+impl From<HandleRequestError> for SimpleResponse {
+    fn from(e: HandleRequestError) -> Self {
+        let code = match e {
+            HandleRequestError::NotFound(_) => 404,
+            HandleRequestError::BadRequest(_) => 400,
+            HandleRequestError::Conflict(_) => 409,
+            HandleRequestError::Locked(_) => 423,
+            HandleRequestError::Unauthorized(_) => 401,
+            HandleRequestError::Forbidden(_) => 403,
+            HandleRequestError::TooManyRequests(_) => 429,
+            HandleRequestError::PayloadTooLarge(_) => 413,
+            HandleRequestError::RequestTimeout(_) => 408,
+            _ => 500,
+        };
+        SimpleResponse {
+            code,
+            message: format!("{e}"),
         }
-    };
+    }
+}
+
+impl IntoResponse for SimpleResponse {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, Json(self)).into_response()
+    }
+}
 
-    serde_json::to_string(&response).unwrap()
+async fn list_repos_handler(
+    params: Option<Path<storage::ListReposParams>>,
+    Query(query): Query<storage::ListReposQuery>,
+    State(state): State<SharedState>,
+    Extension(principal): Extension<auth::Principal>,
+) -> Response {
+    let params = params.map(|Path(params)| params).unwrap_or_default();
+    let db = &state.read().await.db;
+    match storage::list_repos(db, &principal, params, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
 }
 
 async fn list_commits_handler(
     Path(params): Path<storage::ListCommitsParams>,
+    Query(query): Query<storage::ListCommitsQuery>,
     State(state): State<SharedState>,
-) -> impl IntoResponse {
+) -> Response {
     let db = &state.read().await.db;
-    let response = match storage::list_commits(db, params).await {
-        Ok(res) => res,
-        Err(e) => {
-            let response = SimpleResponse {
-                code: 500,
-                message: format!("{e}"),
-            };
-            return serde_json::to_string(&response).unwrap();
-        }
-    };
-
-    serde_json::to_string(&response).unwrap()
+    match storage::list_commits(db, params, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
 }
 
 async fn list_artifacts_handler(
     Path(params): Path<storage::ListArtifactsParams>,
+    Query(query): Query<storage::ListArtifactsQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_artifacts(db, params, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn list_refs_handler(
+    Path(params): Path<storage::ListRefsParams>,
+    Query(query): Query<storage::PaginationQuery>,
     State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_refs(db, params, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn list_ref_history_handler(
+    Path(params): Path<storage::RefParams>,
+    Query(query): Query<storage::PaginationQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_ref_history(db, params, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn set_ref_handler(
+    Path(params): Path<storage::RefParams>,
+    State(state): State<SharedState>,
+    Json(request): Json<storage::SetRefRequest>,
 ) -> impl IntoResponse {
     let db = &state.read().await.db;
-    let response = match storage::list_artifacts(db, params).await {
-        Ok(res) => res,
-        Err(e) => match e {
-            HandleRequestError::NotFound(message) => {
-                let response = SimpleResponse { code: 404, message };
-                return serde_json::to_string(&response).unwrap();
-            }
-            _ => {
-                let response = SimpleResponse {
-                    code: 500,
-                    message: format!("{e}"),
-                };
-                return serde_json::to_string(&response).unwrap();
-            }
-        },
-    };
+    if let Err(e) = storage::set_ref(db, params, request).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn get_commit_handler(
+    Path(params): Path<storage::CommitParams>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::get_commit(db, params).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn put_commit_handler(
+    Path(params): Path<storage::CommitParams>,
+    State(state): State<SharedState>,
+    Json(metadata): Json<database::CommitMetadata>,
+) -> impl IntoResponse {
+    let db = &state.read().await.db;
+    let events = &state.read().await.events;
+    if let Err(e) = storage::put_commit(db, events, params, metadata).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn set_commit_status_handler(
+    Path(params): Path<storage::CommitParams>,
+    State(state): State<SharedState>,
+    Json(request): Json<storage::SetCommitStatusRequest>,
+) -> impl IntoResponse {
+    let db = &state.read().await.db;
+    let events = &state.read().await.events;
+    if let Err(e) = storage::set_commit_status(db, events, params, request).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn list_channels_handler(
+    Path(params): Path<storage::ListRefsParams>,
+    Query(query): Query<storage::PaginationQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_channels(db, params, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn list_promotions_handler(
+    Path(params): Path<storage::ChannelParams>,
+    Query(query): Query<storage::PaginationQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_promotions(db, params, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn promote_handler(
+    Path(params): Path<storage::ChannelParams>,
+    State(state): State<SharedState>,
+    Extension(principal): Extension<auth::Principal>,
+    Json(request): Json<storage::PromoteRequest>,
+) -> impl IntoResponse {
+    let db = &state.read().await.db;
+    if let Err(e) = storage::promote(db, &principal, params, request).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn seal_commit_handler(
+    Path(params): Path<storage::CommitParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let db = &state.read().await.db;
+    if let Err(e) = storage::seal_commit(db, params).await {
+        return SimpleResponse::from(e);
+    }
 
-    serde_json::to_string(&response).unwrap()
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
 }
 
 async fn upload_handler(
     Path(params): Path<storage::UploadParams>,
     State(state): State<SharedState>,
+    headers: HeaderMap,
     body: Body,
 ) -> impl IntoResponse {
     let artifact_path = &state.read().await.artifact_path;
     let db = &state.read().await.db;
-    match storage::store_file(artifact_path, db, params, body).await {
-        Ok(_) => (),
-        Err(e) => {
-            let response = SimpleResponse {
-                code: 500,
-                message: format!("{e}"),
-            };
-            return Json(response);
-        }
+    let events = &state.read().await.events;
+    let uploads = &state.read().await.uploads;
+    if let Err(e) =
+        storage::store_file(artifact_path, db, events, params, &headers, body, uploads).await
+    {
+        return SimpleResponse::from(e);
     }
 
-    let response = SimpleResponse {
+    SimpleResponse {
         code: 200,
         message: String::from("OK"),
-    };
-    Json(response)
+    }
 }
 
-async fn download_handler(
-    Path(params): Path<storage::DownloadParams>,
+async fn list_tokens_handler(
+    Query(query): Query<storage::PaginationQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_tokens(db, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn create_token_handler(
+    State(state): State<SharedState>,
+    Json(request): Json<storage::CreateTokenRequest>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::create_token(db, request).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn delete_token_handler(
+    Path(params): Path<storage::TokenParams>,
     State(state): State<SharedState>,
 ) -> impl IntoResponse {
-    let artifact_path = &state.read().await.artifact_path;
     let db = &state.read().await.db;
-    let (filename, body) = match storage::prepare_download_file(artifact_path, db, params).await {
-        Ok(result) => result,
-        Err(e) => match e {
-            HandleRequestError::NotFound(message) => {
-                return Err((StatusCode::NOT_FOUND, message.to_string()));
-            }
-            _ => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e}"))),
-        },
-    };
+    if let Err(e) = storage::delete_token(db, params).await {
+        return SimpleResponse::from(e);
+    }
 
-    let headers = [(
-        header::CONTENT_DISPOSITION,
-        format!("attachment; filename=\"{filename}\""),
-    )];
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
 
-    Ok((headers, body))
+/// Sign a URL if the request could upload or download the artifact itself.
+async fn presign_handler(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(request): Json<storage::PresignRequest>,
+) -> Response {
+    let state = state.read().await;
+    let mut identity = None;
+    if state.auth.enabled {
+        let principal = match authorize_presign(&state, &headers, &request).await {
+            Ok(principal) => principal,
+            Err(e) => return SimpleResponse::from(e).into_response(),
+        };
+        identity = principal.identity();
+    }
+
+    let mut response = match storage::presign(state.auth.presign_secret.as_ref(), request).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    };
+    if let Some(identity) = identity {
+        response.extensions_mut().insert(identity);
+    }
+    response
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::http::Request;
-    use http_body_util::BodyExt;
-    use tower::Service;
-    use tower::ServiceExt;
+async fn authorize_presign(
+    state: &RouterState,
+    headers: &HeaderMap,
+    request: &storage::PresignRequest,
+) -> Result<auth::Principal, HandleRequestError> {
+    let method = request.method()?;
+    let target = request.target()?;
+    let required = auth::required_scope(&method, ARTIFACT_ROUTE).unwrap();
+    let principal = auth::authorize(
+        &state.db,
+        &state.auth,
+        state.oidc.as_ref(),
+        headers,
+        required,
+        &target,
+    )
+    .await?;
+    auth::check_permission(&state.db, &principal, &method, ARTIFACT_ROUTE, &target)?;
+    Ok(principal)
+}
 
-    #[tokio::test]
-    async fn index_route() {
+async fn list_acls_handler(
+    Query(query): Query<storage::PaginationQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_acls(db, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn get_acl_handler(
+    Path(params): Path<storage::AclParams>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::get_acl(db, params).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn set_acl_handler(
+    Path(params): Path<storage::AclParams>,
+    State(state): State<SharedState>,
+    Json(request): Json<storage::SetAclRequest>,
+) -> impl IntoResponse {
+    let db = &state.read().await.db;
+    if let Err(e) = storage::set_acl(db, params, request).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn delete_acl_handler(
+    Path(params): Path<storage::AclParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let db = &state.read().await.db;
+    if let Err(e) = storage::delete_acl(db, params).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn list_audit_handler(
+    Query(query): Query<storage::ListAuditQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_audit(db, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn events_handler(
+    State(state): State<SharedState>,
+    Extension(principal): Extension<auth::Principal>,
+    headers: HeaderMap,
+) -> Response {
+    stream_events(&state, None, principal, &headers).await
+}
+
+async fn repo_events_handler(
+    Path(params): Path<storage::RepoParams>,
+    State(state): State<SharedState>,
+    Extension(principal): Extension<auth::Principal>,
+    headers: HeaderMap,
+) -> Response {
+    let scope = events::Scope {
+        server: params.server,
+        owner: params.owner,
+        repo: params.repo,
+    };
+    stream_events(&state, Some(scope), principal, &headers).await
+}
+
+async fn stream_events(
+    state: &SharedState,
+    scope: Option<events::Scope>,
+    principal: auth::Principal,
+    headers: &HeaderMap,
+) -> Response {
+    let last_event_id = match storage::last_event_id(headers) {
+        Ok(id) => id,
+        Err(e) => return SimpleResponse::from(e).into_response(),
+    };
+    let state = state.read().await;
+    let stream = events::stream(
+        Arc::clone(&state.db),
+        &state.events,
+        scope,
+        principal,
+        last_event_id,
+    );
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn list_webhooks_handler(
+    Query(query): Query<storage::PaginationQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_webhooks(db, None, query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn list_repo_webhooks_handler(
+    Path(params): Path<storage::RepoParams>,
+    Query(query): Query<storage::PaginationQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::list_webhooks(db, Some(params), query).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn create_webhook_handler(
+    State(state): State<SharedState>,
+    Json(request): Json<storage::CreateWebhookRequest>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::create_webhook(db, None, request).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn create_repo_webhook_handler(
+    Path(params): Path<storage::RepoParams>,
+    State(state): State<SharedState>,
+    Json(request): Json<storage::CreateWebhookRequest>,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::create_webhook(db, Some(params), request).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn delete_webhook_handler(
+    Path(params): Path<storage::WebhookParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let db = &state.read().await.db;
+    if let Err(e) = storage::delete_webhook(db, params).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn create_session_handler(
+    Path(params): Path<storage::CommitParams>,
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Response {
+    let db = &state.read().await.db;
+    match storage::create_session(db, params, &headers).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => SimpleResponse::from(e).into_response(),
+    }
+}
+
+async fn session_upload_handler(
+    Path(params): Path<storage::SessionUploadParams>,
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let artifact_path = &state.read().await.artifact_path;
+    let db = &state.read().await.db;
+    let uploads = &state.read().await.uploads;
+    if let Err(e) =
+        storage::store_session_file(artifact_path, db, params, &headers, body, uploads).await
+    {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn publish_session_handler(
+    Path(params): Path<storage::SessionParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let artifact_path = &state.read().await.artifact_path;
+    let db = &state.read().await.db;
+    let events = &state.read().await.events;
+    if let Err(e) = storage::publish_session(artifact_path, db, events, params).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn abort_session_handler(
+    Path(params): Path<storage::SessionParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let artifact_path = &state.read().await.artifact_path;
+    let db = &state.read().await.db;
+    if let Err(e) = storage::abort_session(artifact_path, db, params).await {
+        return SimpleResponse::from(e);
+    }
+
+    SimpleResponse {
+        code: 200,
+        message: String::from("OK"),
+    }
+}
+
+async fn download_handler(
+    Path(params): Path<storage::DownloadParams>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    let artifact_path = &state.read().await.artifact_path;
+    let db = &state.read().await.db;
+    let (filename, body) = match storage::prepare_download_file(artifact_path, db, params).await {
+        Ok(result) => result,
+        Err(e) => match e {
+            HandleRequestError::NotFound(message) => {
+                return Err((StatusCode::NOT_FOUND, message.to_string()));
+            }
+            HandleRequestError::BadRequest(message) => {
+                return Err((StatusCode::BAD_REQUEST, message.to_string()));
+            }
+            _ => return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{e}"))),
+        },
+    };
+
+    let headers = [(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{filename}\""),
+    )];
+
+    Ok((headers, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use futures_util::StreamExt;
+    use http_body_util::BodyExt;
+    use tower::Service;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn index_route() {
         let artifact_path = String::from("data/artifacts");
         let db = database::Database::new_rocksdb("data/router/test_index_route").unwrap();
-        let app = router(artifact_path, db);
+        let app = router(RouterConfig::new(artifact_path), db);
 
         let response = app
             .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
             .await
             .unwrap();
 
-        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"<h1>Artifact Store</h1>");
+
+        std::fs::remove_dir_all("data/router/test_index_route").unwrap();
+    }
+
+    #[tokio::test]
+    async fn robots_route() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_robots_route").unwrap();
+        let app = router(RouterConfig::new(artifact_path), db);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/robots.txt")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"User-agent: *\nDisallow: /");
+
+        std::fs::remove_dir_all("data/router/test_robots_route").unwrap();
+    }
+
+    #[tokio::test]
+    async fn ping_route() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_ping_route").unwrap();
+        let app = router(RouterConfig::new(artifact_path), db);
+
+        let response = app
+            .oneshot(Request::builder().uri("/ping").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"pong");
+
+        std::fs::remove_dir_all("data/router/test_ping_route").unwrap();
+    }
+
+    async fn send_request(
+        mut app: &mut Router,
+        method: &str,
+        uri: &str,
+        body: Body,
+    ) -> hyper::Response<Body> {
+        let request = Request::builder()
+            .uri(uri)
+            .method(method)
+            .body(body)
+            .unwrap();
+        ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap()
+    }
+
+    async fn send_json_request(
+        mut app: &mut Router,
+        method: &str,
+        uri: &str,
+        body: &str,
+    ) -> hyper::Response<Body> {
+        let request = Request::builder()
+            .uri(uri)
+            .method(method)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap()
+    }
+
+    async fn set_commit_status(app: &mut Router, repo: &str, commit: &str, status: &str) {
+        let response = send_json_request(
+            app,
+            "PUT",
+            &format!("{repo}/@commits/{commit}/status"),
+            &format!(r#"{{"status": "{status}"}}"#),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn upload_download_empty() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_upload_download_empty").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_upload_download_empty.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["code"], 200);
+        assert_eq!(value["message"], "OK");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit/dir/test_upload_download_empty.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+
+        std::fs::remove_dir_all("data/router/test_upload_download_empty").unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_download_binary() {
+        let artifact_path = String::from("data/artifacts");
+        let db =
+            database::Database::new_rocksdb("data/router/test_upload_download_binary").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_upload_download_binary.txt",
+            Body::from("test_upload_download_binary"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["code"], 200);
+        assert_eq!(value["message"], "OK");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit/dir/test_upload_download_binary.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+        assert!(&body[..] == b"test_upload_download_binary");
+
+        std::fs::remove_dir_all("data/router/test_upload_download_binary").unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_download_latest() {
+        let artifact_path = String::from("data/artifacts");
+        let db =
+            database::Database::new_rocksdb("data/router/test_upload_download_latest").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-latest/commit/dir/test_upload_download_latest.txt",
+            Body::from("test_upload_download_latest"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["code"], 200);
+        assert_eq!(value["message"], "OK");
+
+        // the commit is pending until CI marks it successful
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-latest/@latest/dir/test_upload_download_latest.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-latest/@latest:any/dir/test_upload_download_latest.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        set_commit_status(
+            &mut app,
+            "/git.example.dev/owner/repo-latest",
+            "commit",
+            "success",
+        )
+        .await;
+
+        let response = send_json_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-latest/@commits/commit/status",
+            r#"{"status": "failed"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-latest/@latest/dir/test_upload_download_latest.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+        assert!(&body[..] == b"test_upload_download_latest");
+
+        std::fs::remove_dir_all("data/router/test_upload_download_latest").unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_not_exist() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_download_not_exist").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit/dir/test_download_not_exist.txt",
+            Body::from("test_download_not_exist"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+
+        std::fs::remove_dir_all("data/router/test_download_not_exist").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_repo() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_repo").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_list_repo.txt",
+            Body::from("test_list_repo"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(&mut app, "GET", "/repositories", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["repos"].as_array().unwrap().len(), 1);
+        assert_eq!(value["repos"][0]["server"], "git.example.dev");
+        assert_eq!(value["repos"][0]["owner"], "owner");
+        assert_eq!(value["repos"][0]["repo"], "repo");
+
+        std::fs::remove_dir_all("data/router/test_list_repo").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_repo_multiple() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_repo_multiple").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_list_repo_multiple.txt",
+            Body::from("test_list_repo_multiple"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-2/commit-2/dir/test_list_repo_multiple.txt",
+            Body::from("test_list_repo_multiple-2"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(&mut app, "GET", "/repositories", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["repos"].as_array().unwrap().len(), 2);
+
+        std::fs::remove_dir_all("data/router/test_list_repo_multiple").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_commits() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_commit").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_list_commit.txt",
+            Body::from("test_list_commit"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["server"], "git.example.dev");
+        assert_eq!(value["owner"], "owner");
+        assert_eq!(value["repo"], "repo");
+        assert_eq!(value["commits"].as_array().unwrap().len(), 1);
+        assert_eq!(value["commits"][0]["commit"], "commit");
+
+        std::fs::remove_dir_all("data/router/test_list_commit").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_commits_multiple() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_commit_multiple").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit-1/dir/test-1.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit-2/dir/test-2.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["server"], "git.example.dev");
+        assert_eq!(value["owner"], "owner");
+        assert_eq!(value["repo"], "repo");
+        assert_eq!(value["commits"].as_array().unwrap().len(), 2);
+
+        std::fs::remove_dir_all("data/router/test_list_commit_multiple").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_artifacts() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_artifacts").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_list_artifacts.txt",
+            Body::from("test_list_artifacts"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["server"], "git.example.dev");
+        assert_eq!(value["owner"], "owner");
+        assert_eq!(value["repo"], "repo");
+        assert_eq!(value["commit"], "commit");
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 1);
+        assert_eq!(value["artifacts"][0]["path"], "dir/test_list_artifacts.txt");
+
+        std::fs::remove_dir_all("data/router/test_list_artifacts").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_artifacts_multiple() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_artifacts_multi").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_list_artifacts_multi.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo/commit/dir/test_list_artifacts_multi-2.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!body.is_empty());
+
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["server"], "git.example.dev");
+        assert_eq!(value["owner"], "owner");
+        assert_eq!(value["repo"], "repo");
+        assert_eq!(value["commit"], "commit");
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 2);
+
+        std::fs::remove_dir_all("data/router/test_list_artifacts_multi").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_artifacts_pagination() {
+        let artifact_path = String::from("data/artifacts");
+        let db =
+            database::Database::new_rocksdb("data/router/test_list_artifacts_pagination").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for path in ["a.txt", "b.txt", "c.txt"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo/commit-pagination/{path}"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit-pagination?limit=2",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 2);
+        assert_eq!(value["artifacts"][0]["path"], "a.txt");
+        assert_eq!(value["artifacts"][1]["path"], "b.txt");
+        let cursor = value["nextCursor"].as_str().unwrap();
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            &format!("/git.example.dev/owner/repo/commit-pagination?limit=2&cursor={cursor}"),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 1);
+        assert_eq!(value["artifacts"][0]["path"], "c.txt");
+        assert!(value["nextCursor"].is_null());
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit-pagination?cursor=zz",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["code"], 400);
+
+        std::fs::remove_dir_all("data/router/test_list_artifacts_pagination").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_artifacts_default_page_size() {
+        let artifact_path = String::from("data/artifacts");
+        let db =
+            database::Database::new_rocksdb("data/router/test_list_artifacts_default_page_size")
+                .unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for i in 0..101 {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo/commit-page-size/{i:03}.txt"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // without a limit, a listing still comes in pages
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit-page-size",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 100);
+        let cursor = value["nextCursor"].as_str().unwrap();
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            &format!("/git.example.dev/owner/repo/commit-page-size?cursor={cursor}"),
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 1);
+        assert_eq!(value["artifacts"][0]["path"], "100.txt");
+        assert!(value["nextCursor"].is_null());
+
+        std::fs::remove_dir_all("data/router/test_list_artifacts_default_page_size").unwrap();
+    }
+
+    #[tokio::test]
+    async fn error_status_codes() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_error_status_codes").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-errors/commit-errors/a.txt",
+            Body::from("a"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let repo = "/git.example.dev/owner/repo-errors";
+        for (uri, status) in [
+            (format!("{repo}/commit-missing"), StatusCode::NOT_FOUND),
+            (
+                "/repositories?cursor=zz".to_string(),
+                StatusCode::BAD_REQUEST,
+            ),
+            (format!("{repo}?cursor=zz"), StatusCode::BAD_REQUEST),
+            (
+                format!("{repo}/commit-errors?cursor=zz"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (format!("{repo}/@refs?cursor=zz"), StatusCode::BAD_REQUEST),
+            (
+                format!("{repo}/@refs/main?cursor=zz"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("{repo}/@commits/commit-missing"),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                format!("{repo}/@channels?cursor=zz"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("{repo}/@channels/stable?cursor=zz"),
+                StatusCode::BAD_REQUEST,
+            ),
+            ("/@webhooks?cursor=zz".to_string(), StatusCode::BAD_REQUEST),
+            (
+                format!("{repo}/@webhooks?cursor=zz"),
+                StatusCode::BAD_REQUEST,
+            ),
+            ("/audit?cursor=zz".to_string(), StatusCode::BAD_REQUEST),
+            ("/@tokens?cursor=zz".to_string(), StatusCode::BAD_REQUEST),
+            ("/@acls?cursor=zz".to_string(), StatusCode::BAD_REQUEST),
+        ] {
+            let response = send_request(&mut app, "GET", &uri, Body::empty()).await;
+            assert_eq!(response.status(), status, "{uri}");
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/json",
+                "{uri}"
+            );
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+            assert_eq!(value["code"], status.as_u16(), "{uri}");
+        }
+
+        std::fs::remove_dir_all("data/router/test_error_status_codes").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_artifacts_filter() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_artifacts_filter").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for path in ["bin/app", "reports/junit.xml", "reports/coverage.html"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo/commit-filter/{path}"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit-filter?prefix=reports/",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 2);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo/commit-filter?glob=reports/*.xml",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 1);
+        assert_eq!(value["artifacts"][0]["path"], "reports/junit.xml");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo?since=2000-01-01T00:00:00Z&until=2000-12-31T00:00:00Z",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["commits"].as_array().unwrap().len(), 0);
+
+        std::fs::remove_dir_all("data/router/test_list_artifacts_filter").unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_repo_scoped() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_list_repo_scoped").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for (i, repo) in [
+            "git.example.dev/owner/repo",
+            "git.example.dev/owner-2/repo",
+            "git.other.dev/owner/repo",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/{repo}/commit-{i}/test_list_repo_scoped.txt"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = send_request(&mut app, "GET", "/git.example.dev", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["repos"].as_array().unwrap().len(), 2);
+
+        let response =
+            send_request(&mut app, "GET", "/git.example.dev/owner-2", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["repos"].as_array().unwrap().len(), 1);
+        assert_eq!(value["repos"][0]["server"], "git.example.dev");
+        assert_eq!(value["repos"][0]["owner"], "owner-2");
+
+        std::fs::remove_dir_all("data/router/test_list_repo_scoped").unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_download_ref() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_upload_download_ref").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for commit in ["commit-ref-1", "commit-ref-2"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo-ref/{commit}/test_upload_download_ref.txt"),
+                Body::from(commit),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = send_json_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-ref/@refs/refs/heads/main",
+            r#"{"commit": "commit-ref-1"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // commits named like expressions couldn't be read back
+        for commit in ["@ref:main", "@latest"] {
+            let repo = "/git.example.dev/owner/repo-ref";
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("{repo}/{commit}/test_upload_download_ref.txt"),
+                Body::from(commit),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let response =
+                send_json_request(&mut app, "PUT", &format!("{repo}/{commit}"), "{}").await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let response = send_request(
+                &mut app,
+                "POST",
+                &format!("{repo}/@commits/{commit}/sessions"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = send_json_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-ref/@refs/v1.2.0",
+            r#"{"commit": "commit-ref-2"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_json_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-ref/@refs/refs/heads/missing",
+            r#"{"commit": "does-not-exist"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-ref/@ref:main/test_upload_download_ref.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"commit-ref-1");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-ref/@tag:v1.2.0/test_upload_download_ref.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"commit-ref-2");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-ref/@ref:dev/test_upload_download_ref.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-ref/@refs",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["refs"].as_array().unwrap().len(), 2);
+
+        std::fs::remove_dir_all("data/router/test_upload_download_ref").unwrap();
+    }
+
+    #[tokio::test]
+    async fn commit_metadata() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_commit_metadata").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let response = app
+            .call(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/git.example.dev/owner/repo-meta/commit-meta-1/test_commit_metadata.txt")
+                    .header("X-Commit-Author", "alice")
+                    .header("X-Commit-Branch", "main")
+                    .header("X-Commit-Parents", "commit-meta-0, commit-meta-00")
+                    .header("X-CI-Run-ID", "42")
+                    .body(Body::from("commit-meta-1"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_json_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-meta/commit-meta-2",
+            r#"{"author": "bob", "branch": "dev", "message": "Fix build", "pipeline": "nightly"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-meta/@commits/commit-meta-1",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["commit"], "commit-meta-1");
+        assert_eq!(value["author"], "alice");
+        assert_eq!(value["branch"], "main");
+        assert_eq!(value["ciRunId"], "42");
+        assert_eq!(
+            value["parents"],
+            serde_json::json!(["commit-meta-0", "commit-meta-00"])
+        );
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-meta/@commits/@latest:any",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["commit"], "commit-meta-2");
+        assert_eq!(value["message"], "Fix build");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-meta?branch=dev",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let commits = value["commits"].as_array().unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0]["commit"], "commit-meta-2");
+        assert_eq!(commits[0]["pipeline"], "nightly");
+
+        std::fs::remove_dir_all("data/router/test_commit_metadata").unwrap();
+    }
+
+    #[tokio::test]
+    async fn labels() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_labels").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for (commit, release, path, arch) in [
+            ("commit-label-1", "true", "amd64.bin", "amd64"),
+            ("commit-label-1", "true", "arm64.bin", "arm64"),
+            ("commit-label-2", "false", "arm64.bin", "arm64"),
+        ] {
+            let response = app
+                .call(
+                    Request::builder()
+                        .method("PUT")
+                        .uri(format!("/git.example.dev/owner/repo-label/{commit}/{path}"))
+                        .header("X-Commit-Labels", format!("release={release}"))
+                        .header("X-Artifact-Labels", format!("os=linux, arch={arch}"))
+                        .body(Body::from(commit))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        set_commit_status(
+            &mut app,
+            "/git.example.dev/owner/repo-label",
+            "commit-label-1",
+            "success",
+        )
+        .await;
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-label/commit-label-1?label=arch=arm64",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let artifacts = value["artifacts"].as_array().unwrap();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0]["path"], "arm64.bin");
+        assert_eq!(artifacts[0]["labels"]["os"], "linux");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-label/@label:release=true/arm64.bin",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"commit-label-1");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-label?label=release=false",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let commits = value["commits"].as_array().unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0]["commit"], "commit-label-2");
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-label/@label:release/arm64.bin",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        std::fs::remove_dir_all("data/router/test_labels").unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_session() {
+        let artifact_path = String::from("data/artifacts");
+        let db =
+            Arc::new(database::Database::new_rocksdb("data/router/test_upload_session").unwrap());
+        let mut app = router(RouterConfig::new(artifact_path.clone()), Arc::clone(&db));
+
+        let open_session = |app: &mut Router, commit: &str| {
+            let uri = format!("/git.example.dev/owner/repo-session/@commits/{commit}/sessions");
+            let mut app = app.clone();
+            async move {
+                let response = send_request(&mut app, "POST", &uri, Body::empty()).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+                value["session"].as_str().unwrap().to_string()
+            }
+        };
+
+        let session = open_session(&mut app, "commit-session-1").await;
+        for path in ["a.txt", "dir/b.txt"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/@sessions/{session}/artifacts/{path}"),
+                Body::from(path),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // staged artifacts are invisible
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-session/commit-session-1/a.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send_request(
+            &mut app,
+            "POST",
+            &format!("/@sessions/{session}/publish"),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-session/commit-session-1",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["artifacts"].as_array().unwrap().len(), 2);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-session/commit-session-1/dir/b.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"dir/b.txt");
+
+        let response = send_request(
+            &mut app,
+            "POST",
+            &format!("/@sessions/{session}/publish"),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // an aborted session leaves nothing behind
+        let session = open_session(&mut app, "commit-session-2").await;
+        let response = send_request(
+            &mut app,
+            "PUT",
+            &format!("/@sessions/{session}/artifacts/a.txt"),
+            Body::from("a.txt"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "DELETE",
+            &format!("/@sessions/{session}"),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!std::path::Path::new(&format!("data/artifacts/.sessions/{session}")).exists());
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-session/commit-session-2",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["code"], 404);
+
+        // a session failing to publish doesn't touch the artifacts of the commit
+        let session = open_session(&mut app, "commit-session-3").await;
+        let response = send_request(
+            &mut app,
+            "PUT",
+            &format!("/@sessions/{session}/artifacts/a.txt"),
+            Body::from("session"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let artifact = "/git.example.dev/owner/repo-session/commit-session-3/a.txt";
+        let response = send_request(&mut app, "PUT", artifact, Body::from("plain")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send_request(
+            &mut app,
+            "POST",
+            &format!("/@sessions/{session}/publish"),
+            Body::empty(),
+        )
+        .await;
+        assert_ne!(response.status(), StatusCode::OK);
+        let response = send_request(&mut app, "GET", artifact, Body::empty()).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"plain");
+        let staged = format!("{artifact_path}/.sessions/{session}/a.txt");
+        assert_eq!(std::fs::read_to_string(staged).unwrap(), "session");
+
+        // sessions left open expire with everything uploaded into them
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        assert_eq!(
+            storage::expire_sessions(&artifact_path, &db, now).unwrap(),
+            1
+        );
+        assert!(!std::path::Path::new(&format!("{artifact_path}/.sessions/{session}")).exists());
+        let response = send_request(
+            &mut app,
+            "POST",
+            &format!("/@sessions/{session}/publish"),
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // sessions don't take over an artifact path of a commit
+        let artifact = "/git.example.dev/owner/repo-session/commit-session-4/@sessions";
+        let response = send_request(&mut app, "PUT", artifact, Body::from("artifact")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(&mut app, "GET", artifact, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"artifact");
+
+        drop(app);
+        drop(db);
+        std::fs::remove_dir_all("data/router/test_upload_session").unwrap();
+    }
+
+    #[tokio::test]
+    async fn release_channels() {
+        let artifact_path = String::from("data/artifacts");
+        let db = database::Database::new_rocksdb("data/router/test_release_channels").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for commit in ["commit-channel-1", "commit-channel-2", "commit-channel-3"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo-channel/{commit}/app.bin"),
+                Body::from(commit),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        for commit in ["commit-channel-1", "commit-channel-2"] {
+            set_commit_status(
+                &mut app,
+                "/git.example.dev/owner/repo-channel",
+                commit,
+                "success",
+            )
+            .await;
+        }
+
+        for (body, status) in [
+            (
+                r#"{"commit": "commit-channel-1", "claimedActor": "alice", "reason": "1.0"}"#,
+                StatusCode::OK,
+            ),
+            (r#"{"commit": "@latest"}"#, StatusCode::OK),
+            (r#"{"commit": "commit-channel-3"}"#, StatusCode::CONFLICT),
+            (r#"{"commit": "commit-missing"}"#, StatusCode::NOT_FOUND),
+        ] {
+            let response = send_json_request(
+                &mut app,
+                "POST",
+                "/git.example.dev/owner/repo-channel/@channels/stable",
+                body,
+            )
+            .await;
+            assert_eq!(response.status(), status, "{body}");
+        }
+
+        for (expression, expected) in [
+            ("@channel:stable", "commit-channel-2"),
+            ("@channel:stable~1", "commit-channel-1"),
+        ] {
+            let response = send_request(
+                &mut app,
+                "GET",
+                &format!("/git.example.dev/owner/repo-channel/{expression}/app.bin"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK, "{expression}");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], expected.as_bytes(), "{expression}");
+        }
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-channel/@channel:beta/app.bin",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-channel/@channels/stable",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let promotions = value["promotions"].as_array().unwrap();
+        assert_eq!(promotions.len(), 2);
+        assert_eq!(promotions[0]["previous"], "commit-channel-1");
+        // without authentication, the actor given by the client is only a claim
+        assert_eq!(promotions[1]["actor"], serde_json::Value::Null);
+        assert_eq!(promotions[1]["claimedActor"], "alice");
+        assert_eq!(promotions[1]["reason"], "1.0");
 
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-channel/@channels",
+            Body::empty(),
+        )
+        .await;
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert_eq!(&body[..], b"<h1>Artifact Store</h1>");
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["channels"][0]["name"], "stable");
+        assert_eq!(value["channels"][0]["commit"], "commit-channel-2");
 
-        std::fs::remove_dir_all("data/router/test_index_route").unwrap();
+        std::fs::remove_dir_all("data/router/test_release_channels").unwrap();
     }
 
     #[tokio::test]
-    async fn robots_route() {
+    async fn sealed_commit() {
         let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_robots_route").unwrap();
-        let app = router(artifact_path, db);
+        let db = database::Database::new_rocksdb("data/router/test_sealed_commit").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        for commit in ["commit-seal-1", "commit-seal-2"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo-seal/{commit}/a.txt"),
+                Body::from(commit),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
 
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/robots.txt")
-                    .body(Body::empty())
-                    .unwrap(),
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-seal/@commits/commit-seal-1/seal",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        set_commit_status(
+            &mut app,
+            "/git.example.dev/owner/repo-seal",
+            "commit-seal-2",
+            "success",
+        )
+        .await;
+        let response = send_json_request(
+            &mut app,
+            "POST",
+            "/git.example.dev/owner/repo-seal/@channels/stable",
+            r#"{"commit": "commit-seal-2", "seal": true}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for commit in ["commit-seal-1", "commit-seal-2"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo-seal/{commit}/b.txt"),
+                Body::from(commit),
             )
-            .await
-            .unwrap();
+            .await;
+            assert_eq!(response.status(), StatusCode::LOCKED, "{commit}");
+
+            let response = send_request(
+                &mut app,
+                "GET",
+                &format!("/git.example.dev/owner/repo-seal/@commits/{commit}"),
+                Body::empty(),
+            )
+            .await;
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+            assert!(value["timeSealed"].is_string(), "{commit}");
+        }
 
-        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        // a fork with the same commit has artifacts of its own
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/fork/repo-seal/commit-seal-1/b.txt",
+            Body::from("fork"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for (repo, paths) in [
+            ("owner/repo-seal", vec!["a.txt"]),
+            ("fork/repo-seal", vec!["b.txt"]),
+        ] {
+            let response = send_request(
+                &mut app,
+                "GET",
+                &format!("/git.example.dev/{repo}/commit-seal-1"),
+                Body::empty(),
+            )
+            .await;
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+            let artifacts = value["artifacts"].as_array().unwrap();
+            let artifacts = artifacts.iter().map(|a| &a["path"]).collect::<Vec<_>>();
+            assert_eq!(artifacts, paths, "{repo}");
+        }
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-seal/commit-seal-1/b.txt",
+            Body::empty(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
+        let response = send_request(
+            &mut app,
+            "POST",
+            "/git.example.dev/owner/repo-seal/@commits/commit-seal-1/sessions",
+            Body::empty(),
+        )
+        .await;
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert_eq!(&body[..], b"User-agent: *\nDisallow: /");
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let session = value["session"].as_str().unwrap();
+        let response = send_request(
+            &mut app,
+            "PUT",
+            &format!("/@sessions/{session}/artifacts/c.txt"),
+            Body::from("c.txt"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::LOCKED);
 
-        std::fs::remove_dir_all("data/router/test_robots_route").unwrap();
+        std::fs::remove_dir_all("data/router/test_sealed_commit").unwrap();
     }
 
     #[tokio::test]
-    async fn ping_route() {
+    async fn webhooks() {
         let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_ping_route").unwrap();
-        let app = router(artifact_path, db);
+        let db = Arc::new(database::Database::new_rocksdb("data/router/test_webhooks").unwrap());
+        let mut app = router(RouterConfig::new(artifact_path), Arc::clone(&db));
 
-        let response = app
-            .oneshot(Request::builder().uri("/ping").body(Body::empty()).unwrap())
-            .await
-            .unwrap();
+        let response = send_json_request(
+            &mut app,
+            "POST",
+            "/@webhooks",
+            r#"{"url": "http://127.0.0.1:9/hook", "secret": "s3cret"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["secret"], "s3cret");
+        let global = value["id"].as_str().unwrap().to_string();
 
-        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let response = send_json_request(
+            &mut app,
+            "POST",
+            "/git.example.dev/owner/repo-hooks/@webhooks",
+            r#"{"url": "https://hooks.example.dev/", "events": ["commit.status_changed"]}"#,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert!(!value["secret"].as_str().unwrap().is_empty());
+
+        for request in [
+            r#"{"url": "ftp://hooks.example.dev/"}"#,
+            r#"{"url": "not a url"}"#,
+            r#"{"url": "https://hooks.example.dev/", "secret": ""}"#,
+        ] {
+            let response = send_json_request(&mut app, "POST", "/@webhooks", request).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{request}");
+        }
 
+        let response = send_request(&mut app, "GET", "/@webhooks", Body::empty()).await;
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert_eq!(&body[..], b"pong");
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["webhooks"].as_array().unwrap().len(), 2);
+        assert!(value["webhooks"][0].get("secret").is_none());
 
-        std::fs::remove_dir_all("data/router/test_ping_route").unwrap();
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/git.example.dev/owner/repo-hooks/@webhooks",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let webhooks = value["webhooks"].as_array().unwrap();
+        assert_eq!(webhooks.len(), 1);
+        assert_eq!(webhooks[0]["repo"], "repo-hooks");
+        assert_eq!(webhooks[0]["events"][0], "commit.status_changed");
+
+        // repo.created, commit.created and artifact.created for the global webhook
+        let response = send_request(
+            &mut app,
+            "PUT",
+            "/git.example.dev/owner/repo-hooks/commit-hooks/a.txt",
+            Body::from("a"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // commit.status_changed for both webhooks
+        set_commit_status(
+            &mut app,
+            "/git.example.dev/owner/repo-hooks",
+            "commit-hooks",
+            "success",
+        )
+        .await;
+        let deliveries = db.list_due_deliveries(u128::MAX, 100).unwrap();
+        assert_eq!(deliveries.len(), 5);
+        let payload = deliveries
+            .iter()
+            .find(|delivery| delivery.event == database::EventKind::ArtifactCreated)
+            .map(|delivery| delivery.payload.clone())
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(value["type"], "artifact.created");
+        assert_eq!(value["commit"], "commit-hooks");
+        assert_eq!(value["path"], "a.txt");
+
+        let uri = format!("/@webhooks/{global}");
+        let response = send_request(&mut app, "DELETE", &uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(&mut app, "DELETE", &uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        drop(app);
+        drop(db);
+        std::fs::remove_dir_all("data/router/test_webhooks").unwrap();
     }
 
-    async fn send_request(
+    async fn send_authorized_request(
         mut app: &mut Router,
         method: &str,
         uri: &str,
+        token: Option<&str>,
         body: Body,
     ) -> hyper::Response<Body> {
-        let request = Request::builder()
+        let mut request = Request::builder()
             .uri(uri)
             .method(method)
-            .body(body)
-            .unwrap();
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
         ServiceExt::<Request<Body>>::ready(&mut app)
             .await
             .unwrap()
-            .call(request)
+            .call(request.body(body).unwrap())
             .await
             .unwrap()
     }
 
+    async fn create_token(app: &mut Router, request: &str) -> String {
+        let response = send_authorized_request(
+            app,
+            "POST",
+            "/@tokens",
+            Some("bootstrap"),
+            Body::from(request.to_string()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK, "{request}");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        value["token"].as_str().unwrap().to_string()
+    }
+
     #[tokio::test]
-    async fn upload_download_empty() {
-        let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_upload_download_empty").unwrap();
-        let mut app = router(artifact_path, db);
+    async fn token_auth() {
+        let config = RouterConfig {
+            artifact_path: String::from("data/artifacts"),
+            auth: AuthConfig {
+                enabled: true,
+                anonymous_read: false,
+                admin_token: Some("bootstrap".to_string()),
+                oidc: None,
+                presign_secret: None,
+            },
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            shutdown: CancellationToken::new(),
+        };
+        let db = database::Database::new_rocksdb("data/router/test_token_auth").unwrap();
+        let mut app = router(config, db);
+
+        let reader = create_token(&mut app, r#"{"name": "reader", "scopes": ["read"]}"#).await;
+        let writer = create_token(
+            &mut app,
+            r#"{"name": "ci", "scopes": ["write"], "server": "git.example.dev", "owner": "owner", "repo": "repo-auth"}"#,
+        )
+        .await;
+        for request in [
+            r#"{"name": "", "scopes": ["read"]}"#,
+            r#"{"name": "none", "scopes": []}"#,
+            r#"{"name": "repo only", "scopes": ["read"], "repo": "repo-auth"}"#,
+        ] {
+            let response = send_authorized_request(
+                &mut app,
+                "POST",
+                "/@tokens",
+                Some("bootstrap"),
+                Body::from(request),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{request}");
+        }
 
-        let response = send_request(
+        let upload = "/git.example.dev/owner/repo-auth/commit-auth/a.txt";
+        let response =
+            send_authorized_request(&mut app, "PUT", upload, None, Body::from("a")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        for token in ["bootstrap2", "not-a-token", "abc.def"] {
+            let response =
+                send_authorized_request(&mut app, "PUT", upload, Some(token), Body::from("a"))
+                    .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{token}");
+        }
+        // a valid id with a wrong secret
+        let (id, _) = writer.split_once('.').unwrap();
+        let forged = format!("{id}.{}", "0".repeat(64));
+        let response =
+            send_authorized_request(&mut app, "PUT", upload, Some(&forged), Body::from("a")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response =
+            send_authorized_request(&mut app, "PUT", upload, Some(&reader), Body::from("a")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response =
+            send_authorized_request(&mut app, "PUT", upload, Some(&writer), Body::from("a")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // the writer is restricted to its repository
+        let response = send_authorized_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_upload_download_empty.txt",
+            "/git.example.dev/owner/repo-other/commit-auth-2/a.txt",
+            Some(&writer),
+            Body::from("a"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // promotions are recorded with the identity of the token, whoever the client claims to be
+        let repo = "/git.example.dev/owner/repo-auth";
+        for (method, uri, body) in [
+            (
+                "PUT",
+                format!("{repo}/@commits/commit-auth/status"),
+                r#"{"status": "success"}"#,
+            ),
+            (
+                "POST",
+                format!("{repo}/@channels/stable"),
+                r#"{"commit": "commit-auth", "claimedActor": "someone-else"}"#,
+            ),
+        ] {
+            let response =
+                send_authorized_request(&mut app, method, &uri, Some(&writer), Body::from(body))
+                    .await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+        let response = send_authorized_request(
+            &mut app,
+            "GET",
+            &format!("{repo}/@channels/stable"),
+            Some(&reader),
             Body::empty(),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
-
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
         let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["code"], 200);
-        assert_eq!(value["message"], "OK");
+        assert_eq!(value["promotions"][0]["actor"], id);
+        assert_eq!(value["promotions"][0]["claimedActor"], "someone-else");
+
+        // write scope includes read, anonymous reads are disabled
+        let download = "/git.example.dev/owner/repo-auth/commit-auth/a.txt";
+        for (token, status) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some(reader.as_str()), StatusCode::OK),
+            (Some(writer.as_str()), StatusCode::OK),
+        ] {
+            let response =
+                send_authorized_request(&mut app, "GET", download, token, Body::empty()).await;
+            assert_eq!(response.status(), status, "{token:?}");
+        }
+        let response = send_authorized_request(&mut app, "GET", "/ping", None, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let response = send_request(
+        // admin routes need admin scope
+        let response =
+            send_authorized_request(&mut app, "GET", "/@tokens", Some(&writer), Body::empty())
+                .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = send_authorized_request(
             &mut app,
             "GET",
-            "/git.example.dev/owner/repo/commit/dir/test_upload_download_empty.txt",
+            "/@tokens",
+            Some("bootstrap"),
             Body::empty(),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
-
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(body.is_empty());
-
-        std::fs::remove_dir_all("data/router/test_upload_download_empty").unwrap();
-    }
-
-    #[tokio::test]
-    async fn upload_download_binary() {
-        let artifact_path = String::from("data/artifacts");
-        let db =
-            database::Database::new_rocksdb("data/router/test_upload_download_binary").unwrap();
-        let mut app = router(artifact_path, db);
-
-        let response = send_request(
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let tokens = value["tokens"].as_array().unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens.iter().all(|token| token.get("hash").is_none()));
+        let ci = tokens.iter().find(|token| token["name"] == "ci").unwrap();
+        assert_eq!(ci["id"], id);
+        assert_eq!(ci["scopes"][0], "write");
+        assert_eq!(ci["repo"], "repo-auth");
+
+        let response = send_authorized_request(
             &mut app,
-            "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_upload_download_binary.txt",
-            Body::from("test_upload_download_binary"),
+            "GET",
+            "/audit?action=upload",
+            Some("bootstrap"),
+            Body::empty(),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
-
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
         let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["code"], 200);
-        assert_eq!(value["message"], "OK");
+        let identities: Vec<&serde_json::Value> = value["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| &entry["identity"])
+            .collect();
+        assert!(identities.contains(&&serde_json::Value::from(id)));
+        assert!(identities.contains(&&serde_json::Value::Null));
 
-        let response = send_request(
+        let response = send_authorized_request(
             &mut app,
-            "GET",
-            "/git.example.dev/owner/repo/commit/dir/test_upload_download_binary.txt",
+            "DELETE",
+            &format!("/@tokens/{id}"),
+            Some("bootstrap"),
             Body::empty(),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
+        let response =
+            send_authorized_request(&mut app, "GET", download, Some(&writer), Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
-        assert!(&body[..] == b"test_upload_download_binary");
-
-        std::fs::remove_dir_all("data/router/test_upload_download_binary").unwrap();
+        std::fs::remove_dir_all("data/router/test_token_auth").unwrap();
     }
 
     #[tokio::test]
-    async fn upload_download_latest() {
-        let artifact_path = String::from("data/artifacts");
-        let db =
-            database::Database::new_rocksdb("data/router/test_upload_download_latest").unwrap();
-        let mut app = router(artifact_path, db);
+    async fn anonymous_read() {
+        let config = RouterConfig {
+            artifact_path: String::from("data/artifacts"),
+            auth: AuthConfig {
+                enabled: true,
+                anonymous_read: true,
+                admin_token: None,
+                oidc: None,
+                presign_secret: None,
+            },
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            shutdown: CancellationToken::new(),
+        };
+        let db = database::Database::new_rocksdb("data/router/test_anonymous_read").unwrap();
+        let mut app = router(config, db);
 
+        let response = send_request(&mut app, "GET", "/repositories", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(&mut app, "GET", "/audit", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
         let response = send_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo-latest/commit/dir/test_upload_download_latest.txt",
-            Body::from("test_upload_download_latest"),
+            "/git.example.dev/owner/repo-anonymous/commit-anonymous/a.txt",
+            Body::from("a"),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
+        std::fs::remove_dir_all("data/router/test_anonymous_read").unwrap();
+    }
+
+    async fn list_repo_names(app: &mut Router, uri: &str, token: Option<&str>) -> Vec<String> {
+        let response = send_authorized_request(app, "GET", uri, token, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
         let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["code"], 200);
-        assert_eq!(value["message"], "OK");
+        value["repos"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|repo| format!("{}/{}", repo["owner"], repo["repo"]).replace('"', ""))
+            .collect()
+    }
 
-        let response = send_request(
+    #[tokio::test]
+    async fn repo_acls() {
+        let config = RouterConfig {
+            artifact_path: String::from("data/artifacts"),
+            auth: AuthConfig {
+                enabled: true,
+                anonymous_read: true,
+                admin_token: Some("bootstrap".to_string()),
+                oidc: None,
+                presign_secret: None,
+            },
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            shutdown: CancellationToken::new(),
+        };
+        let db = database::Database::new_rocksdb("data/router/test_repo_acls").unwrap();
+        let mut app = router(config, db);
+
+        let team = create_token(&mut app, r#"{"name": "team", "scopes": ["write"]}"#).await;
+        let outsider = create_token(&mut app, r#"{"name": "outsider", "scopes": ["write"]}"#).await;
+        let (team_id, _) = team.split_once('.').unwrap();
+        let (outsider_id, _) = outsider.split_once('.').unwrap();
+        for upload in [
+            "/git.example.dev/oss/lib/commit-acl-oss/a.txt",
+            "/git.example.dev/internal/secret/commit-acl/a.txt",
+        ] {
+            let response = send_authorized_request(
+                &mut app,
+                "PUT",
+                upload,
+                Some("bootstrap"),
+                Body::from("a"),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        for (uri, acl) in [
+            (
+                "/@acls/git.example.dev/oss",
+                r#"{"entries": [{"principal": "*", "permissions": ["list", "download"]}]}"#
+                    .to_string(),
+            ),
+            (
+                "/@acls/git.example.dev/internal",
+                format!(
+                    r#"{{"entries": [{{"principal": "{team_id}", "permissions": ["list", "download", "upload"]}}]}}"#
+                ),
+            ),
+        ] {
+            let response =
+                send_authorized_request(&mut app, "PUT", uri, Some(&team), Body::from(acl.clone()))
+                    .await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let response =
+                send_authorized_request(&mut app, "PUT", uri, Some("bootstrap"), Body::from(acl))
+                    .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // invisible repositories are filtered out of listings
+        assert_eq!(
+            list_repo_names(&mut app, "/git.example.dev", None).await,
+            ["oss/lib"]
+        );
+        assert_eq!(
+            list_repo_names(&mut app, "/repositories", Some(&outsider)).await,
+            ["oss/lib"]
+        );
+        assert_eq!(
+            list_repo_names(&mut app, "/repositories", Some(&team)).await,
+            ["internal/secret", "oss/lib"]
+        );
+
+        let secret = "/git.example.dev/internal/secret";
+        let download = "/git.example.dev/internal/secret/commit-acl/a.txt";
+        for (method, uri, token, status) in [
+            ("GET", secret, None, StatusCode::FORBIDDEN),
+            (
+                "GET",
+                secret,
+                Some(outsider.as_str()),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "GET",
+                download,
+                Some(outsider.as_str()),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "PUT",
+                download,
+                Some(outsider.as_str()),
+                StatusCode::FORBIDDEN,
+            ),
+            ("GET", secret, Some(team.as_str()), StatusCode::OK),
+            ("GET", download, Some(team.as_str()), StatusCode::OK),
+            (
+                "GET",
+                "/git.example.dev/oss/lib/commit-acl-oss/a.txt",
+                None,
+                StatusCode::OK,
+            ),
+            (
+                "PUT",
+                "/git.example.dev/oss/lib/commit-acl-oss/b.txt",
+                Some(team.as_str()),
+                StatusCode::FORBIDDEN,
+            ),
+        ] {
+            let body = if method == "PUT" {
+                Body::from("b")
+            } else {
+                Body::empty()
+            };
+            let response = send_authorized_request(&mut app, method, uri, token, body).await;
+            assert_eq!(response.status(), status, "{method} {uri} {token:?}");
+        }
+        let response = send_authorized_request(
             &mut app,
-            "GET",
-            "/git.example.dev/owner/repo-latest/@latest/dir/test_upload_download_latest.txt",
-            Body::empty(),
+            "POST",
+            "/git.example.dev/internal/secret/@channels/stable",
+            Some(&team),
+            Body::from(r#"{"commit": "commit-acl"}"#),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // the repository ACL takes precedence over the owner ACL
+        let repo_acl = "/@acls/git.example.dev/internal/secret";
+        let acl = format!(
+            r#"{{"entries": [{{"principal": "{outsider_id}", "permissions": ["list"]}}]}}"#
+        );
+        let response = send_authorized_request(
+            &mut app,
+            "PUT",
+            repo_acl,
+            Some("bootstrap"),
+            Body::from(acl),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
+        let response =
+            send_authorized_request(&mut app, "GET", secret, Some(&outsider), Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response =
+            send_authorized_request(&mut app, "GET", secret, Some(&team), Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
+        let response =
+            send_authorized_request(&mut app, "GET", "/@acls", Some("bootstrap"), Body::empty())
+                .await;
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
-        assert!(&body[..] == b"test_upload_download_latest");
-
-        std::fs::remove_dir_all("data/router/test_upload_download_latest").unwrap();
-    }
-
-    #[tokio::test]
-    async fn download_not_exist() {
-        let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_download_not_exist").unwrap();
-        let mut app = router(artifact_path, db);
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["acls"].as_array().unwrap().len(), 3);
 
-        let response = send_request(
+        let response = send_authorized_request(
             &mut app,
-            "GET",
-            "/git.example.dev/owner/repo/commit/dir/test_download_not_exist.txt",
-            Body::from("test_download_not_exist"),
+            "DELETE",
+            repo_acl,
+            Some("bootstrap"),
+            Body::empty(),
         )
         .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response =
+            send_authorized_request(&mut app, "GET", repo_acl, Some("bootstrap"), Body::empty())
+                .await;
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response =
+            send_authorized_request(&mut app, "GET", secret, Some(&team), Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
-
-        std::fs::remove_dir_all("data/router/test_download_not_exist").unwrap();
+        std::fs::remove_dir_all("data/router/test_repo_acls").unwrap();
     }
 
     #[tokio::test]
-    async fn list_repo() {
-        let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_list_repo").unwrap();
-        let mut app = router(artifact_path, db);
+    async fn oidc_upload() {
+        let path = "data/router/test_oidc_upload";
+        std::fs::create_dir_all(path).unwrap();
+        let key = oidc::tests::TestKey::generate("key-1");
+        std::fs::write(format!("{path}/jwks.json"), &key.jwks).unwrap();
+        let config = RouterConfig {
+            artifact_path: String::from("data/artifacts"),
+            auth: AuthConfig {
+                enabled: true,
+                anonymous_read: false,
+                admin_token: None,
+                oidc: Some(oidc::tests::config(crate::config::JwksSource::File(
+                    format!("{path}/jwks.json"),
+                ))),
+                presign_secret: None,
+            },
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            shutdown: CancellationToken::new(),
+        };
+        let db = database::Database::new_rocksdb(&format!("{path}/rocksdb")).unwrap();
+        let mut app = router(config, db);
+
+        let token = key.sign("owner/repo-oidc", "commit-oidc");
+        for (method, uri, status) in [
+            (
+                "PUT",
+                "/github.com/owner/repo-oidc/commit-oidc/a.txt",
+                StatusCode::OK,
+            ),
+            (
+                "GET",
+                "/github.com/owner/repo-oidc/commit-oidc/a.txt",
+                StatusCode::OK,
+            ),
+            // only its own commit, repository and server
+            (
+                "PUT",
+                "/github.com/owner/repo-oidc/commit-oidc-2/a.txt",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "PUT",
+                "/github.com/owner/repo-other/commit-oidc/a.txt",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "PUT",
+                "/gitlab.com/owner/repo-oidc/commit-oidc/a.txt",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "PUT",
+                "/github.com/owner/repo-oidc/@refs/main",
+                StatusCode::FORBIDDEN,
+            ),
+            ("GET", "/@tokens", StatusCode::FORBIDDEN),
+        ] {
+            let body = if method == "PUT" {
+                Body::from("a")
+            } else {
+                Body::empty()
+            };
+            let response = send_authorized_request(&mut app, method, uri, Some(&token), body).await;
+            assert_eq!(response.status(), status, "{method} {uri}");
+        }
 
-        let response = send_request(
+        let expired = key.sign_claims(serde_json::json!({
+            "iss": oidc::tests::ISSUER,
+            "aud": oidc::tests::AUDIENCE,
+            "exp": 1000,
+            "repository": "owner/repo-oidc",
+            "sha": "commit-oidc",
+        }));
+        let response = send_authorized_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_list_repo.txt",
-            Body::from("test_list_repo"),
+            "/github.com/owner/repo-oidc/commit-oidc/b.txt",
+            Some(&expired),
+            Body::from("b"),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        let response = send_request(&mut app, "GET", "/repositories", Body::empty()).await;
-        assert_eq!(response.status(), StatusCode::OK);
+        drop(app);
+        std::fs::remove_dir_all(path).unwrap();
+    }
 
+    async fn presign_url(app: &mut Router, token: &str, request: &str) -> String {
+        let response = send_authorized_request(
+            app,
+            "POST",
+            "/@presign",
+            Some(token),
+            Body::from(request.to_string()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK, "{request}");
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
-
         let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["repos"].as_array().unwrap().len(), 1);
-        assert_eq!(value["repos"][0]["server"], "git.example.dev");
-        assert_eq!(value["repos"][0]["owner"], "owner");
-        assert_eq!(value["repos"][0]["repo"], "repo");
-
-        std::fs::remove_dir_all("data/router/test_list_repo").unwrap();
+        value["url"].as_str().unwrap().to_string()
     }
 
     #[tokio::test]
-    async fn list_repo_multiple() {
-        let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_list_repo_multiple").unwrap();
-        let mut app = router(artifact_path, db);
+    async fn presigned_urls() {
+        let config = RouterConfig {
+            artifact_path: String::from("data/artifacts"),
+            auth: AuthConfig {
+                enabled: true,
+                anonymous_read: false,
+                admin_token: Some("bootstrap".to_string()),
+                oidc: None,
+                presign_secret: Some("presign-secret".to_string()),
+            },
+            limits: LimitsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            shutdown: CancellationToken::new(),
+        };
+        let db = database::Database::new_rocksdb("data/router/test_presigned_urls").unwrap();
+        let mut app = router(config, db);
+
+        let reader = create_token(&mut app, r#"{"name": "reader", "scopes": ["read"]}"#).await;
+        let writer = create_token(
+            &mut app,
+            r#"{"name": "ci", "scopes": ["write"], "server": "git.example.dev", "owner": "owner", "repo": "repo-presign"}"#,
+        )
+        .await;
+        let path = "/git.example.dev/owner/repo-presign/commit-presign/a.txt";
+        let upload = format!(r#"{{"method": "PUT", "path": "{path}", "expiresIn": 60}}"#);
+        let download = format!(r#"{{"method": "GET", "path": "{path}"}}"#);
+
+        // signing needs the access the signed request needs
+        for (token, request, status) in [
+            (None, &upload, StatusCode::UNAUTHORIZED),
+            (Some(reader.as_str()), &upload, StatusCode::FORBIDDEN),
+            (
+                Some(writer.as_str()),
+                &r#"{"method": "PUT", "path": "/git.example.dev/owner/repo-other/commit-presign/a.txt"}"#.to_string(),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Some(writer.as_str()),
+                &format!(r#"{{"method": "DELETE", "path": "{path}"}}"#),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                Some(writer.as_str()),
+                &r#"{"method": "PUT", "path": "/git.example.dev/owner/repo-presign/@refs/main"}"#.to_string(),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                Some(writer.as_str()),
+                &format!(r#"{{"method": "PUT", "path": "{path}", "expiresIn": 0}}"#),
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = send_authorized_request(
+                &mut app,
+                "POST",
+                "/@presign",
+                token,
+                Body::from(request.clone()),
+            )
+            .await;
+            assert_eq!(response.status(), status, "{token:?} {request}");
+        }
 
-        let response = send_request(
+        let upload_url = presign_url(&mut app, &writer, &upload).await;
+        let download_url = presign_url(&mut app, &reader, &download).await;
+        assert!(upload_url.starts_with(&format!("{path}?expires=")));
+
+        // a download url can't be used to upload, and before the upload the artifact doesn't exist
+        let response = send_request(&mut app, "PUT", &download_url, Body::from("a")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send_request(&mut app, "PUT", &upload_url, Body::from("a")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(&mut app, "GET", &download_url, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"a");
+
+        let other_path = download_url.replace("a.txt", "b.txt");
+        let last = if download_url.ends_with('0') {
+            '1'
+        } else {
+            '0'
+        };
+        let tampered = format!("{}{last}", &download_url[..download_url.len() - 1]);
+        let expired = format!(
+            "{path}?expires=1000&signature={}",
+            presign::sign("presign-secret", &Method::GET, path, 1000)
+        );
+        for uri in [other_path, tampered, expired] {
+            let response = send_request(&mut app, "GET", &uri, Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{uri}");
+        }
+
+        // encoded paths are checked against the repository they are routed to
+        let response = send_authorized_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_list_repo_multiple.txt",
-            Body::from("test_list_repo_multiple"),
+            "/@acls/git.example.dev/owner/repo-private",
+            Some("bootstrap"),
+            Body::from(r#"{"entries": []}"#),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
-        let response = send_request(
+        let response = send_authorized_request(
             &mut app,
-            "PUT",
-            "/git.example.dev/owner/repo-2/commit-2/dir/test_list_repo_multiple.txt",
-            Body::from("test_list_repo_multiple-2"),
+            "POST",
+            "/@presign",
+            Some(&reader),
+            Body::from(
+                r#"{"method": "GET", "path": "/git.example.dev/owner/repo-pr%69vate/commit-presign/a.txt"}"#,
+            ),
         )
         .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let encoded = r#"{"method": "GET", "path": "/git.example.dev/owner/repo-pre%73ign/commit-presign/a.txt"}"#;
+        let url = presign_url(&mut app, &reader, encoded).await;
+        let response = send_request(&mut app, "GET", &url, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        std::fs::remove_dir_all("data/router/test_presigned_urls").unwrap();
+    }
+
+    /// Send a request as if from `client`, with an optional bearer token.
+    async fn send_client_request(
+        app: &mut Router,
+        client: &str,
+        uri: &str,
+        token: Option<&str>,
+    ) -> hyper::Response<Body> {
+        let mut request = Request::builder().uri(uri).method("GET");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        let addr: SocketAddr = format!("{client}:40000").parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn rate_limits() {
+        let config = RouterConfig {
+            artifact_path: String::from("data/artifacts"),
+            auth: AuthConfig {
+                enabled: true,
+                anonymous_read: true,
+                admin_token: Some("bootstrap".to_string()),
+                oidc: None,
+                presign_secret: None,
+            },
+            limits: LimitsConfig {
+                requests: Some(crate::config::Rate {
+                    per_second: 0.01,
+                    burst: 2.0,
+                }),
+                bytes: None,
+                max_concurrent_uploads: None,
+                ..Default::default()
+            },
+            timeouts: TimeoutsConfig::default(),
+            shutdown: CancellationToken::new(),
+        };
+        let db = database::Database::new_rocksdb("data/router/test_rate_limits").unwrap();
+        let mut app = router(config, db);
+
+        // per client IP
+        for client in ["10.0.0.1", "10.0.0.1", "10.0.0.2"] {
+            let response = send_client_request(&mut app, client, "/ping", None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = send_client_request(&mut app, "10.0.0.1", "/ping", None).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "100");
+
+        // per identity, from any client
+        let token = create_token(&mut app, r#"{"name": "ci", "scopes": ["read"]}"#).await;
+        for client in ["10.0.0.3", "10.0.0.4"] {
+            let response =
+                send_client_request(&mut app, client, "/repositories", Some(&token)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response =
+            send_client_request(&mut app, "10.0.0.5", "/repositories", Some(&token)).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = send_client_request(&mut app, "10.0.0.5", "/repositories", None).await;
         assert_eq!(response.status(), StatusCode::OK);
 
+        std::fs::remove_dir_all("data/router/test_rate_limits").unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_uploads() {
+        let config = RouterConfig {
+            limits: LimitsConfig {
+                max_concurrent_uploads: Some(1),
+                ..Default::default()
+            },
+            ..RouterConfig::new(String::from("data/artifacts"))
+        };
+        let db = database::Database::new_rocksdb("data/router/test_concurrent_uploads").unwrap();
+        let mut app = router(config, db);
+
+        // an upload whose body never ends
+        let body = Body::from_stream(futures_util::stream::pending::<
+            Result<Vec<u8>, std::io::Error>,
+        >());
+        let request = Request::builder()
+            .uri("/git.example.dev/owner/repo-uploads/commit-uploads/a.txt")
+            .method("PUT")
+            .body(body)
+            .unwrap();
+        let pending = tokio::spawn(app.clone().oneshot(request));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let upload = "/git.example.dev/owner/repo-uploads/commit-uploads/b.txt";
+        let response = send_request(&mut app, "PUT", upload, Body::from("b")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        // downloads aren't capped
         let response = send_request(&mut app, "GET", "/repositories", Body::empty()).await;
         assert_eq!(response.status(), StatusCode::OK);
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
+        pending.abort();
+        let _ = pending.await;
+        let response = send_request(&mut app, "PUT", upload, Body::from("b")).await;
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["repos"].as_array().unwrap().len(), 2);
+        drop(app);
+        std::fs::remove_dir_all("data/router/test_concurrent_uploads").unwrap();
+    }
 
-        std::fs::remove_dir_all("data/router/test_list_repo_multiple").unwrap();
+    /// A body streamed in `chunks`, waiting `delay` before each of them.
+    fn slow_body(chunks: &'static [&'static str], delay: Duration) -> Body {
+        Body::from_stream(
+            futures_util::stream::iter(chunks).then(move |chunk| async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, std::io::Error>(*chunk)
+            }),
+        )
     }
 
     #[tokio::test]
-    async fn list_commits() {
-        let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_list_commit").unwrap();
-        let mut app = router(artifact_path, db);
+    async fn upload_limits() {
+        let config = RouterConfig {
+            limits: LimitsConfig {
+                max_upload_size: Some(4),
+                repo_max_upload_sizes: std::collections::HashMap::from([(
+                    "git.example.dev/owner/repo-limits-large".to_string(),
+                    8,
+                )]),
+                ..Default::default()
+            },
+            timeouts: TimeoutsConfig {
+                request: Duration::from_millis(100),
+                upload_idle: Duration::from_millis(300),
+                ..TimeoutsConfig::default()
+            },
+            ..RouterConfig::new(String::from("data/artifacts"))
+        };
+        let db = database::Database::new_rocksdb("data/router/test_upload_limits").unwrap();
+        let mut app = router(config, db);
+        let commit = "/git.example.dev/owner/repo-limits/commit-limits";
+        let file = |name: &str| format!("data/artifacts{commit}/{name}");
 
         let response = send_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_list_commit.txt",
-            Body::from("test_list_commit"),
+            &format!("{commit}/a.txt"),
+            Body::from("abcd"),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
 
+        // rejected up front from its Content-Length
+        let request = Request::builder()
+            .uri(format!("{commit}/b.txt"))
+            .method("PUT")
+            .header(header::CONTENT_LENGTH, "5")
+            .body(Body::from("abcde"))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!std::fs::exists(file("b.txt")).unwrap());
+
+        // or once too much was streamed, removing what was written
+        let body = slow_body(&["abc", "def"], Duration::ZERO);
+        let response = send_request(&mut app, "PUT", &format!("{commit}/c.txt"), body).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!std::fs::exists(file("c.txt")).unwrap());
+
+        // repositories may have a limit of their own
+        let large = "/git.example.dev/owner/repo-limits-large/commit-limits-large";
         let response = send_request(
             &mut app,
-            "GET",
-            "/git.example.dev/owner/repo",
-            Body::empty(),
+            "PUT",
+            &format!("{large}/a.txt"),
+            Body::from("abcdefgh"),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(
+            &mut app,
+            "PUT",
+            &format!("{large}/b.txt"),
+            Body::from("abcdefghi"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
 
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
-
-        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["server"], "git.example.dev");
-        assert_eq!(value["owner"], "owner");
-        assert_eq!(value["repo"], "repo");
-        assert_eq!(value["commits"].as_array().unwrap().len(), 1);
-        assert_eq!(value["commits"][0]["commit"], "commit");
-
-        std::fs::remove_dir_all("data/router/test_list_commit").unwrap();
+        // uploads aren't held to the request deadline while data keeps coming
+        let body = slow_body(&["a", "b", "c", "d"], Duration::from_millis(50));
+        let response = send_request(&mut app, "PUT", &format!("{commit}/d.txt"), body).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(std::fs::read_to_string(file("d.txt")).unwrap(), "abcd");
+
+        // but a stalled upload times out
+        let body = Body::from_stream(
+            futures_util::stream::iter([Ok::<_, std::io::Error>("a")])
+                .chain(futures_util::stream::pending()),
+        );
+        let response = send_request(&mut app, "PUT", &format!("{commit}/e.txt"), body).await;
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(!std::fs::exists(file("e.txt")).unwrap());
+
+        drop(app);
+        std::fs::remove_dir_all("data/router/test_upload_limits").unwrap();
     }
 
     #[tokio::test]
-    async fn list_commits_multiple() {
+    async fn audit_log() {
         let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_list_commit_multiple").unwrap();
-        let mut app = router(artifact_path, db);
+        let db = database::Database::new_rocksdb("data/router/test_audit_log").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let request = Request::builder()
+            .uri("/git.example.dev/owner/repo-audit/commit-audit-1/bin/app")
+            .method("PUT")
+            .header("X-Request-ID", "request-1")
+            .body(Body::from("app"))
+            .unwrap();
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-request-id"], "request-1");
 
         let response = send_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo/commit-1/dir/test-1.txt",
+            "/git.example.dev/owner/repo-audit/@commits/commit-audit-1/seal",
             Body::empty(),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
+        let seal_request_id = response.headers()["x-request-id"].clone();
         let response = send_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo/commit-2/dir/test-2.txt",
-            Body::empty(),
+            "/git.example.dev/owner/repo-audit/commit-audit-1/bin/other",
+            Body::from("other"),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
-
+        assert_eq!(response.status(), StatusCode::LOCKED);
+        // reads are not recorded
         let response = send_request(
             &mut app,
             "GET",
-            "/git.example.dev/owner/repo",
+            "/git.example.dev/owner/repo-audit/commit-audit-1/bin/app",
             Body::empty(),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("x-request-id").is_none());
 
+        let response = send_request(&mut app, "GET", "/audit", Body::empty()).await;
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
-
         let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["server"], "git.example.dev");
-        assert_eq!(value["owner"], "owner");
-        assert_eq!(value["repo"], "repo");
-        assert_eq!(value["commits"].as_array().unwrap().len(), 2);
-
-        std::fs::remove_dir_all("data/router/test_list_commit_multiple").unwrap();
-    }
-
-    #[tokio::test]
-    async fn list_artifacts() {
-        let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_list_artifacts").unwrap();
-        let mut app = router(artifact_path, db);
+        let entries = value["entries"].as_array().unwrap();
+        let actions: Vec<&str> = entries
+            .iter()
+            .map(|entry| entry["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(actions, ["upload", "seal", "upload"]);
+        assert_eq!(entries[0]["status"], 423);
+        assert_eq!(entries[1]["requestId"], seal_request_id.to_str().unwrap());
+        assert_eq!(entries[2]["requestId"], "request-1");
+        assert_eq!(entries[2]["method"], "PUT");
+        assert_eq!(
+            entries[2]["uri"],
+            "/git.example.dev/owner/repo-audit/commit-audit-1/bin/app"
+        );
+        assert_eq!(entries[2]["params"]["commit"], "commit-audit-1");
+        assert_eq!(entries[2]["params"]["path"], "bin/app");
 
         let response = send_request(
             &mut app,
-            "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_list_artifacts.txt",
-            Body::from("test_list_artifacts"),
+            "GET",
+            "/audit?repo=repo-audit&path=bin/app",
+            Body::empty(),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        let entries = value["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["requestId"], "request-1");
 
         let response = send_request(
             &mut app,
             "GET",
-            "/git.example.dev/owner/repo/commit",
+            "/audit?action=upload&limit=1",
             Body::empty(),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
-
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["entries"][0]["status"], 423);
+        let cursor = value["nextCursor"].as_str().unwrap();
+        let response = send_request(
+            &mut app,
+            "GET",
+            &format!("/audit?action=upload&limit=1&cursor={cursor}"),
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["entries"][0]["requestId"], "request-1");
+        assert!(value["nextCursor"].is_null());
 
+        let response = send_request(
+            &mut app,
+            "GET",
+            "/audit?until=2000-01-01T00:00:00Z",
+            Body::empty(),
+        )
+        .await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
         let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["server"], "git.example.dev");
-        assert_eq!(value["owner"], "owner");
-        assert_eq!(value["repo"], "repo");
-        assert_eq!(value["commit"], "commit");
-        assert_eq!(value["artifacts"].as_array().unwrap().len(), 1);
-        assert_eq!(value["artifacts"][0]["path"], "dir/test_list_artifacts.txt");
+        assert_eq!(value["entries"].as_array().unwrap().len(), 0);
 
-        std::fs::remove_dir_all("data/router/test_list_artifacts").unwrap();
+        std::fs::remove_dir_all("data/router/test_audit_log").unwrap();
+    }
+
+    /// Read `count` events from an SSE response body, as `(id, event type, data)`.
+    async fn read_events(
+        body: &mut Body,
+        count: usize,
+    ) -> Vec<(String, String, serde_json::Value)> {
+        let mut text = String::new();
+        while text.matches("\n\n").count() < count {
+            let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+                .await
+                .expect("timed out waiting for events")
+                .unwrap()
+                .unwrap();
+            text.push_str(std::str::from_utf8(&frame.into_data().unwrap()).unwrap());
+        }
+        text.split_terminator("\n\n")
+            .map(|event| {
+                let field = |name: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(str::trim)
+                        .unwrap()
+                        .to_string()
+                };
+                (
+                    field("id:"),
+                    field("event:"),
+                    serde_json::from_str(&field("data:")).unwrap(),
+                )
+            })
+            .collect()
     }
 
     #[tokio::test]
-    async fn list_artifacts_multiple() {
+    async fn event_stream() {
         let artifact_path = String::from("data/artifacts");
-        let db = database::Database::new_rocksdb("data/router/test_list_artifacts_multi").unwrap();
-        let mut app = router(artifact_path, db);
+        let db = database::Database::new_rocksdb("data/router/test_event_stream").unwrap();
+        let config = RouterConfig::new(artifact_path);
+        let shutdown = config.shutdown.clone();
+        let mut app = router(config, db);
 
-        let response = send_request(
-            &mut app,
-            "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_list_artifacts_multi.txt",
-            Body::empty(),
-        )
-        .await;
+        let response = send_request(&mut app, "GET", "/@events", Body::empty()).await;
         assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut stream = response.into_body();
+
         let response = send_request(
             &mut app,
             "PUT",
-            "/git.example.dev/owner/repo/commit/dir/test_list_artifacts_multi-2.txt",
-            Body::empty(),
+            "/git.example.dev/owner/repo-events/commit-events-1/a.txt",
+            Body::from("a"),
         )
         .await;
         assert_eq!(response.status(), StatusCode::OK);
 
+        let events = read_events(&mut stream, 3).await;
+        let types: Vec<&str> = events.iter().map(|(_, kind, _)| kind.as_str()).collect();
+        assert_eq!(
+            types,
+            ["repo.created", "commit.created", "artifact.created"]
+        );
+        let ids: Vec<&str> = events.iter().map(|(id, _, _)| id.as_str()).collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+        assert_eq!(events[2].2["commit"], "commit-events-1");
+        assert_eq!(events[2].2["path"], "a.txt");
+
+        // resume a repository stream after the first event
+        let request = Request::builder()
+            .uri("/git.example.dev/owner/repo-events/@events")
+            .header("Last-Event-ID", "1")
+            .body(Body::empty())
+            .unwrap();
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut repo_stream = response.into_body();
+        let events = read_events(&mut repo_stream, 2).await;
+        let ids: Vec<&str> = events.iter().map(|(id, _, _)| id.as_str()).collect();
+        assert_eq!(ids, ["2", "3"]);
+
+        // events of other repositories are left out
+        for uri in [
+            "/git.example.dev/owner/repo-events-other/commit-events-2/a.txt",
+            "/git.example.dev/owner/repo-events/commit-events-1/b.txt",
+        ] {
+            let response = send_request(&mut app, "PUT", uri, Body::from("b")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let events = read_events(&mut repo_stream, 1).await;
+        assert_eq!(events[0].0, "7");
+        assert_eq!(events[0].2["path"], "b.txt");
+        let events = read_events(&mut stream, 4).await;
+        assert_eq!(events[3].0, "7");
+
+        let request = Request::builder()
+            .uri("/@events")
+            .header("Last-Event-ID", "latest")
+            .body(Body::empty())
+            .unwrap();
+        let response = ServiceExt::<Request<Body>>::ready(&mut app)
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // a commit named events is still listed like any other
+        let uri = "/git.example.dev/owner/repo-events/events";
+        let response =
+            send_request(&mut app, "PUT", &format!("{uri}/a.txt"), Body::from("a")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send_request(&mut app, "GET", uri, Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+        assert_eq!(value["commit"], "events");
+
+        // streams end on shutdown, so that their connections can close
+        shutdown.cancel();
+        for body in [&mut stream, &mut repo_stream] {
+            let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+                .await
+                .expect("stream kept open after shutdown");
+            assert!(frame.is_none());
+        }
+
+        std::fs::remove_dir_all("data/router/test_event_stream").unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_relative_commit() {
+        let artifact_path = String::from("data/artifacts");
+        let db =
+            database::Database::new_rocksdb("data/router/test_download_relative_commit").unwrap();
+        let mut app = router(RouterConfig::new(artifact_path), db);
+
+        let mut before = String::new();
+        for commit in ["commit-rel-1", "commit-rel-2", "commit-rel-3"] {
+            let response = send_request(
+                &mut app,
+                "PUT",
+                &format!("/git.example.dev/owner/repo-rel/{commit}/test_relative.txt"),
+                Body::from(commit),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            set_commit_status(
+                &mut app,
+                "/git.example.dev/owner/repo-rel",
+                commit,
+                "success",
+            )
+            .await;
+
+            let response = send_json_request(
+                &mut app,
+                "PUT",
+                "/git.example.dev/owner/repo-rel/@refs/refs/heads/main",
+                &format!(r#"{{"commit": "{commit}"}}"#),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+
+            if commit == "commit-rel-2" {
+                before = time::OffsetDateTime::now_utc()
+                    .format(&time::format_description::well_known::Rfc3339)
+                    .unwrap();
+            }
+        }
+
+        for (expression, expected) in [
+            ("@latest", "commit-rel-3"),
+            ("@latest~1", "commit-rel-2"),
+            ("@latest~2", "commit-rel-1"),
+            ("@ref:main~1", "commit-rel-2"),
+            (&format!("@before:{before}"), "commit-rel-2"),
+        ] {
+            let response = send_request(
+                &mut app,
+                "GET",
+                &format!("/git.example.dev/owner/repo-rel/{expression}/test_relative.txt"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK, "{expression}");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], expected.as_bytes(), "{expression}");
+        }
+
+        for (expression, status) in [
+            ("@latest~3", StatusCode::NOT_FOUND),
+            ("@latest~x", StatusCode::BAD_REQUEST),
+            ("@latest~1001", StatusCode::BAD_REQUEST),
+            ("@latest~18446744073709551615", StatusCode::BAD_REQUEST),
+            (
+                "@channel:stable~18446744073709551615",
+                StatusCode::BAD_REQUEST,
+            ),
+            ("@before:yesterday", StatusCode::BAD_REQUEST),
+        ] {
+            let response = send_request(
+                &mut app,
+                "GET",
+                &format!("/git.example.dev/owner/repo-rel/{expression}/test_relative.txt"),
+                Body::empty(),
+            )
+            .await;
+            assert_eq!(response.status(), status, "{expression}");
+        }
+
         let response = send_request(
             &mut app,
             "GET",
-            "/git.example.dev/owner/repo/commit",
+            "/git.example.dev/owner/repo-rel/@refs/refs/heads/main",
             Body::empty(),
         )
         .await;
-        assert_eq!(response.status(), StatusCode::OK);
-
         let body = response.into_body().collect().await.unwrap().to_bytes();
-        assert!(!body.is_empty());
-
         let value: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
-        assert_eq!(value["server"], "git.example.dev");
-        assert_eq!(value["owner"], "owner");
-        assert_eq!(value["repo"], "repo");
-        assert_eq!(value["commit"], "commit");
-        assert_eq!(value["artifacts"].as_array().unwrap().len(), 2);
+        assert_eq!(value["history"].as_array().unwrap().len(), 3);
+        assert_eq!(value["history"][0]["commit"], "commit-rel-3");
 
-        std::fs::remove_dir_all("data/router/test_list_artifacts_multi").unwrap();
+        std::fs::remove_dir_all("data/router/test_download_relative_commit").unwrap();
     }
 }
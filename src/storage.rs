@@ -1,28 +1,87 @@
 use std::{
+    collections::{BTreeMap, HashMap},
     fs,
     io::Write,
-    path::Path,
-    time::{SystemTime, UNIX_EPOCH},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use axum::body::Body;
+use axum::{
+    body::Body,
+    http::{HeaderMap, Method, header},
+};
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
 
+use crate::auth;
+use crate::config::{LimitsConfig, TimeoutsConfig};
 use crate::database;
 use crate::error::HandleRequestError;
+use crate::events::EventHub;
+use crate::presign;
+
+/// Path parameters scoping a repository listing to a server or an owner.
+#[derive(Deserialize, Default)]
+pub struct ListReposParams {
+    server: Option<String>,
+    owner: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ListReposQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    prefix: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    since: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    until: Option<OffsetDateTime>,
+}
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ListReposResponse {
     pub repos: Vec<database::RepoData>,
+    pub next_cursor: Option<String>,
 }
 
-pub async fn list_repos(db: &database::Database) -> Result<ListReposResponse, HandleRequestError> {
-    let repos = db.list_repos()?;
-    Ok(ListReposResponse { repos })
+/// List the repositories `principal` is permitted to list.
+pub async fn list_repos(
+    db: &database::Database,
+    principal: &auth::Principal,
+    params: ListReposParams,
+    query: ListReposQuery,
+) -> Result<ListReposResponse, HandleRequestError> {
+    let visible = |repo: &database::RepoData| {
+        principal.permitted(
+            db,
+            &repo.server,
+            &repo.owner,
+            &repo.repo,
+            database::AclPermission::List,
+        )
+    };
+    let page = db.list_repos(database::ListReposParams {
+        server: params.server.as_ref(),
+        owner: params.owner.as_ref(),
+        prefix: query.prefix.as_ref(),
+        since: query.since,
+        until: query.until,
+        visible: Some(&visible),
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
+    })?;
+    Ok(ListReposResponse {
+        repos: page.items,
+        next_cursor: page.next_cursor,
+    })
 }
 
 #[derive(Deserialize)]
@@ -32,6 +91,21 @@ pub struct ListCommitsParams {
     repo: String,
 }
 
+#[derive(Deserialize)]
+pub struct ListCommitsQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    since: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    until: Option<OffsetDateTime>,
+    author: Option<String>,
+    branch: Option<String>,
+    pipeline: Option<String>,
+    label: Option<String>,
+    status: Option<database::CommitStatus>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ListCommitsResponse {
@@ -39,23 +113,283 @@ pub struct ListCommitsResponse {
     pub owner: String,
     pub repo: String,
     pub commits: Vec<database::CommitData>,
+    pub next_cursor: Option<String>,
 }
 
 pub async fn list_commits(
     db: &database::Database,
     params: ListCommitsParams,
+    query: ListCommitsQuery,
 ) -> Result<ListCommitsResponse, HandleRequestError> {
-    let commits = db.list_repo_commits(database::ListRepoCommitsParams {
+    let page = db.list_repo_commits(database::ListRepoCommitsParams {
         server: &params.server,
         owner: &params.owner,
         repo: &params.repo,
+        since: query.since,
+        until: query.until,
+        author: query.author.as_ref(),
+        branch: query.branch.as_ref(),
+        pipeline: query.pipeline.as_ref(),
+        labels: &parse_labels(query.label.as_deref().unwrap_or_default())?,
+        status: query.status,
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
     })?;
 
     Ok(ListCommitsResponse {
         server: params.server,
         owner: params.owner,
         repo: params.repo,
-        commits,
+        commits: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct CommitParams {
+    server: String,
+    owner: String,
+    repo: String,
+    commit: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetCommitResponse {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    #[serde(flatten)]
+    pub commit: database::CommitData,
+}
+
+pub async fn get_commit(
+    db: &database::Database,
+    params: CommitParams,
+) -> Result<GetCommitResponse, HandleRequestError> {
+    let commit = get_or_verify_commit(
+        db,
+        GetOrVerifyCommitParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            commit: &params.commit,
+        },
+    )?;
+
+    let data = db
+        .get_commit(database::GetCommitParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            commit: &commit,
+        })?
+        .ok_or_else(|| HandleRequestError::NotFound(format!("commit {commit} not found")))?;
+
+    Ok(GetCommitResponse {
+        server: params.server,
+        owner: params.owner,
+        repo: params.repo,
+        commit: data,
+    })
+}
+
+/// Commits starting with `@` would be read back as commit expressions, so they can't be created.
+fn check_commit_name(commit: &str) -> Result<(), HandleRequestError> {
+    if commit.starts_with('@') {
+        return Err(HandleRequestError::BadRequest(format!(
+            "invalid commit {commit}, commits can't start with @"
+        )));
+    }
+    Ok(())
+}
+
+/// Create the commit if it doesn't exist yet and merge the given metadata into it.
+pub async fn put_commit(
+    db: &database::Database,
+    events: &EventHub,
+    params: CommitParams,
+    metadata: database::CommitMetadata,
+) -> Result<(), HandleRequestError> {
+    check_commit_name(&params.commit)?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    let txn = db.transaction();
+    let mut new_events = Vec::new();
+
+    new_events.extend(txn.create_repo_if_not_exists(
+        time,
+        database::CreateRepositoryParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+        },
+    )?);
+
+    new_events.extend(txn.create_commit_if_not_exists(
+        time,
+        database::CreateCommitParams {
+            commit: &params.commit,
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            metadata: &database::CommitMetadata::default(),
+        },
+    )?);
+
+    txn.update_commit_metadata(database::UpdateCommitMetadataParams {
+        commit: &params.commit,
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        metadata: &metadata,
+    })?;
+
+    txn.commit()?;
+    events.publish(db, new_events);
+    Ok(())
+}
+
+/// Parse comma separated labels such as `os=linux,arch=arm64`.
+fn parse_labels(labels: &str) -> Result<database::Labels, HandleRequestError> {
+    labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(|label| match label.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(HandleRequestError::BadRequest(format!(
+                "invalid label {label}, expected name=value"
+            ))),
+        })
+        .collect()
+}
+
+/// Read a header as a string, joining repeated headers with commas.
+fn header_value(headers: &HeaderMap, name: &str) -> Result<Option<String>, HandleRequestError> {
+    let mut values = Vec::new();
+    for value in headers.get_all(name) {
+        match value.to_str() {
+            Ok(value) => values.push(value),
+            Err(_) => {
+                return Err(HandleRequestError::BadRequest(format!(
+                    "header {name} is not valid ASCII"
+                )));
+            }
+        }
+    }
+    if values.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(values.join(",")))
+}
+
+#[derive(Deserialize)]
+pub struct SetCommitStatusRequest {
+    status: database::CommitStatus,
+}
+
+/// Move a commit to a new build status, e.g. to `success` once CI has uploaded all artifacts.
+pub async fn set_commit_status(
+    db: &database::Database,
+    events: &EventHub,
+    params: CommitParams,
+    request: SetCommitStatusRequest,
+) -> Result<(), HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    let exists = db.exists_commit(database::ExistsCommitParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        commit: &params.commit,
+    })?;
+    if !exists {
+        return Err(HandleRequestError::NotFound(format!(
+            "commit {} not found",
+            params.commit
+        )));
+    }
+
+    let txn = db.transaction();
+    let event = txn.set_commit_status(
+        time,
+        database::SetCommitStatusParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            commit: &params.commit,
+            status: request.status,
+        },
+    )?;
+    txn.commit()?;
+    events.publish(db, event.into_iter().collect());
+    Ok(())
+}
+
+/// Seal a commit, so that no more artifacts can ever be added to it.
+pub async fn seal_commit(
+    db: &database::Database,
+    params: CommitParams,
+) -> Result<(), HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    let exists = db.exists_commit(database::ExistsCommitParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        commit: &params.commit,
+    })?;
+    if !exists {
+        return Err(HandleRequestError::NotFound(format!(
+            "commit {} not found",
+            params.commit
+        )));
+    }
+
+    let txn = db.transaction();
+    txn.seal_commit(
+        time,
+        database::SealCommitParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            commit: &params.commit,
+        },
+    )?;
+    txn.commit()?;
+    Ok(())
+}
+
+/// Read commit metadata sent along with an upload as `X-Commit-*` and `X-CI-*` headers.
+fn commit_metadata_from_headers(
+    headers: &HeaderMap,
+) -> Result<database::CommitMetadata, HandleRequestError> {
+    let header = |name: &str| header_value(headers, name);
+
+    let parents = header("x-commit-parents")?
+        .map(|parents| {
+            parents
+                .split([',', ' '])
+                .filter(|parent| !parent.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(database::CommitMetadata {
+        author: header("x-commit-author")?,
+        message: header("x-commit-message")?,
+        branch: header("x-commit-branch")?,
+        parents,
+        ci_run_url: header("x-ci-run-url")?,
+        ci_run_id: header("x-ci-run-id")?,
+        pipeline: header("x-ci-pipeline")?,
+        labels: parse_labels(&header("x-commit-labels")?.unwrap_or_default())?,
     })
 }
 
@@ -67,6 +401,15 @@ pub struct ListArtifactsParams {
     commit: String,
 }
 
+#[derive(Deserialize)]
+pub struct ListArtifactsQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    prefix: Option<String>,
+    glob: Option<String>,
+    label: Option<String>,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ListArtifactsResponse {
@@ -75,11 +418,13 @@ pub struct ListArtifactsResponse {
     pub repo: String,
     pub commit: String,
     pub artifacts: Vec<database::ArtifactData>,
+    pub next_cursor: Option<String>,
 }
 
 pub async fn list_artifacts(
     db: &database::Database,
     params: ListArtifactsParams,
+    query: ListArtifactsQuery,
 ) -> Result<ListArtifactsResponse, HandleRequestError> {
     let commit = get_or_verify_commit(
         db,
@@ -91,11 +436,18 @@ pub async fn list_artifacts(
         },
     )?;
 
-    let artifacts = db.list_artifacts(database::ListArtifactsParams {
+    let page = db.list_artifacts(database::ListArtifactsParams {
         server: &params.server,
         owner: &params.owner,
         repo: &params.repo,
         commit: &commit,
+        prefix: query.prefix.as_ref(),
+        glob: query.glob.as_ref(),
+        labels: &parse_labels(query.label.as_deref().unwrap_or_default())?,
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
     })?;
 
     Ok(ListArtifactsResponse {
@@ -103,10 +455,58 @@ pub async fn list_artifacts(
         owner: params.owner,
         repo: params.repo,
         commit,
-        artifacts,
+        artifacts: page.items,
+        next_cursor: page.next_cursor,
     })
 }
 
+/// How large an artifact may be, and how long its upload may stall.
+#[derive(Clone, Default)]
+pub struct UploadLimits {
+    pub max_size: Option<u64>,
+    /// Overrides of `max_size` keyed by `{server}/{owner}/{repo}`.
+    pub repo_max_sizes: HashMap<String, u64>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl UploadLimits {
+    pub fn new(limits: &LimitsConfig, timeouts: &TimeoutsConfig) -> Self {
+        UploadLimits {
+            max_size: limits.max_upload_size,
+            repo_max_sizes: limits.repo_max_upload_sizes.clone(),
+            idle_timeout: Some(timeouts.upload_idle),
+        }
+    }
+
+    fn max_size(&self, server: &str, owner: &str, repo: &str) -> Option<u64> {
+        self.repo_max_sizes
+            .get(&format!("{server}/{owner}/{repo}"))
+            .copied()
+            .or(self.max_size)
+    }
+}
+
+/// Reject an upload up front if its `Content-Length` is over the limit.
+fn check_content_length(
+    headers: &HeaderMap,
+    max_size: Option<u64>,
+) -> Result<(), HandleRequestError> {
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    match (length, max_size) {
+        (Some(length), Some(max_size)) if length > max_size => Err(too_large(max_size)),
+        _ => Ok(()),
+    }
+}
+
+fn too_large(max_size: u64) -> HandleRequestError {
+    HandleRequestError::PayloadTooLarge(format!(
+        "artifact is larger than the limit of {max_size} bytes"
+    ))
+}
+
 #[derive(Deserialize)]
 pub struct UploadParams {
     commit: String,
@@ -119,105 +519,1250 @@ pub struct UploadParams {
 pub async fn store_file(
     base_dir: &String,
     db: &database::Database,
+    events: &EventHub,
     params: UploadParams,
+    headers: &HeaderMap,
     body: Body,
+    limits: &UploadLimits,
 ) -> Result<(), HandleRequestError> {
+    check_commit_name(&params.commit)?;
+    let max_size = limits.max_size(&params.server, &params.owner, &params.repo);
+    check_content_length(headers, max_size)?;
     let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let metadata = commit_metadata_from_headers(headers)?;
+    let labels = parse_labels(&header_value(headers, "x-artifact-labels")?.unwrap_or_default())?;
     let dir = format!(
         "{}/{}/{}/{}/{}",
         base_dir, params.server, params.owner, params.repo, params.commit
     );
 
     let txn = db.transaction();
+    let mut new_events = Vec::new();
 
-    txn.create_repo_if_not_exists(
+    new_events.extend(txn.create_repo_if_not_exists(
         time,
         database::CreateRepositoryParams {
             server: &params.server,
             owner: &params.owner,
             repo: &params.repo,
         },
-    )?;
+    )?);
 
-    txn.create_commit_if_not_exists(
+    new_events.extend(txn.create_commit_if_not_exists(
         time,
         database::CreateCommitParams {
             commit: &params.commit,
             server: &params.server,
             owner: &params.owner,
             repo: &params.repo,
+            metadata: &metadata,
         },
-    )?;
+    )?);
 
-    txn.create_artifact(
+    new_events.push(txn.create_artifact(
         time,
         database::CreateArtifactParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
             commit: &params.commit,
             path: &params.path,
+            labels: &labels,
         },
-    )?;
+    )?);
 
+    // written aside and moved into place once committed, so that an upload losing a race
+    // for the same path doesn't replace the file of the one that won
     let path = Path::new(&dir).join(&params.path);
+    let upload_path = upload_path(&path);
+    write_body(&upload_path, body, max_size, limits.idle_timeout).await?;
+    if let Err(e) = txn.commit() {
+        let _ = fs::remove_file(&upload_path);
+        return Err(e.into());
+    }
+    fs::rename(&upload_path, &path)?;
+    events.publish(db, new_events);
+    Ok(())
+}
+
+/// A unique path next to `path` to write an upload to, before it's committed.
+fn upload_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let id = uuid::Uuid::new_v4().simple();
+    path.with_file_name(format!(".{name}.{id}.upload"))
+}
+
+/// Write `body` to `path`, removing the partial file if it fails.
+async fn write_body(
+    path: &Path,
+    body: Body,
+    max_size: Option<u64>,
+    idle_timeout: Option<Duration>,
+) -> Result<(), HandleRequestError> {
     fs::create_dir_all(path.parent().unwrap())?;
     let mut file = fs::File::create(path)?;
 
+    let result = copy_body(&mut file, body, max_size, idle_timeout).await;
+    if result.is_err() {
+        drop(file);
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+async fn copy_body(
+    file: &mut fs::File,
+    body: Body,
+    max_size: Option<u64>,
+    idle_timeout: Option<Duration>,
+) -> Result<(), HandleRequestError> {
     let mut stream = body.into_data_stream();
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(c) => file.write_all(&c)?,
-            Err(e) => return Err(HandleRequestError::AxumError(e)),
+    let mut size = 0;
+    loop {
+        let chunk = match idle_timeout {
+            Some(idle_timeout) => tokio::time::timeout(idle_timeout, stream.next())
+                .await
+                .map_err(|_| {
+                    HandleRequestError::RequestTimeout(format!(
+                        "no data received for {} seconds",
+                        idle_timeout.as_secs_f64()
+                    ))
+                })?,
+            None => stream.next().await,
+        };
+        let Some(chunk) = chunk else {
+            return Ok(());
+        };
+        let chunk = chunk.map_err(HandleRequestError::AxumError)?;
+        size += chunk.len() as u64;
+        if let Some(max_size) = max_size
+            && size > max_size
+        {
+            return Err(too_large(max_size));
         }
+        file.write_all(&chunk)?;
     }
+}
 
-    txn.commit()?;
-    Ok(())
+/// Whether the file of an artifact is in the directory of its repository and commit.
+pub fn artifact_file_exists(base_dir: &String, params: database::ExistsArtifactParams) -> bool {
+    let dir = format!(
+        "{}/{}/{}/{}/{}",
+        base_dir, params.server, params.owner, params.repo, params.commit
+    );
+    Path::new(&dir).join(params.path).is_file()
 }
 
-#[derive(Deserialize)]
-pub struct DownloadParams {
-    server: String,
-    owner: String,
-    repo: String,
-    commit: String,
-    path: String,
+/// Directory holding the files of a session until it is published.
+fn session_dir(base_dir: &String, id: &String) -> String {
+    format!("{base_dir}/.sessions/{id}")
 }
 
-pub async fn prepare_download_file(
-    data_dir: &String,
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateSessionResponse {
+    pub session: String,
+}
+
+/// Open an upload session for a commit. Commit metadata can be sent as headers,
+/// like with a plain upload.
+pub async fn create_session(
     db: &database::Database,
-    params: DownloadParams,
-) -> Result<(String, Body), HandleRequestError> {
-    let commit = get_or_verify_commit(
-        db,
-        GetOrVerifyCommitParams {
+    params: CommitParams,
+    headers: &HeaderMap,
+) -> Result<CreateSessionResponse, HandleRequestError> {
+    check_commit_name(&params.commit)?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let metadata = commit_metadata_from_headers(headers)?;
+    let id = uuid::Uuid::new_v4().simple().to_string();
+
+    let txn = db.transaction();
+    txn.create_session(
+        time,
+        database::CreateSessionParams {
+            id: &id,
             server: &params.server,
             owner: &params.owner,
             repo: &params.repo,
             commit: &params.commit,
+            metadata: &metadata,
         },
     )?;
+    txn.commit()?;
 
-    let exists = db.exists_artifact(database::ExistsArtifactParams {
-        server: &params.server,
-        owner: &params.owner,
-        repo: &params.repo,
-        commit: &commit,
-        path: &params.path,
+    Ok(CreateSessionResponse { session: id })
+}
+
+#[derive(Deserialize)]
+pub struct SessionParams {
+    session: String,
+}
+
+#[derive(Deserialize)]
+pub struct SessionUploadParams {
+    session: String,
+    path: String,
+}
+
+fn session_not_found(id: &String) -> HandleRequestError {
+    HandleRequestError::NotFound(format!("session {id} not found"))
+}
+
+/// Upload an artifact into a session. It stays invisible until the session is published.
+pub async fn store_session_file(
+    base_dir: &String,
+    db: &database::Database,
+    params: SessionUploadParams,
+    headers: &HeaderMap,
+    body: Body,
+    limits: &UploadLimits,
+) -> Result<(), HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let labels = parse_labels(&header_value(headers, "x-artifact-labels")?.unwrap_or_default())?;
+    let session = db
+        .get_session(&params.session)?
+        .ok_or_else(|| session_not_found(&params.session))?;
+    let max_size = limits.max_size(&session.server, &session.owner, &session.repo);
+    check_content_length(headers, max_size)?;
+    let commit = db.get_commit(database::GetCommitParams {
+        server: &session.server,
+        owner: &session.owner,
+        repo: &session.repo,
+        commit: &session.commit,
     })?;
-    if !exists {
-        return Err(HandleRequestError::NotFound(format!(
-            "file {} not found",
-            params.path
+    if commit.is_some_and(|commit| commit.time_sealed.is_some()) {
+        return Err(HandleRequestError::Locked(format!(
+            "commit {} is sealed",
+            session.commit
         )));
     }
 
-    let path = format!(
-        "{}/{}/{}/{}/{}/{}",
-        data_dir, params.server, params.owner, params.repo, commit, params.path
-    );
-    let file = match File::open(path).await {
-        Ok(file) => file,
-        Err(_) => {
+    let txn = db.transaction();
+    txn.stage_artifact(
+        time,
+        database::StageArtifactParams {
+            session: &params.session,
+            path: &params.path,
+            labels: &labels,
+        },
+    )?;
+
+    let dir = session_dir(base_dir, &params.session);
+    let path = Path::new(&dir).join(&params.path);
+    write_body(&path, body, max_size, limits.idle_timeout).await?;
+
+    // e.g. the session was published, aborted or expired meanwhile
+    if let Err(e) = txn.commit() {
+        let _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Make all artifacts of a session visible at once, creating the commit if needed.
+pub async fn publish_session(
+    base_dir: &String,
+    db: &database::Database,
+    events: &EventHub,
+    params: SessionParams,
+) -> Result<(), HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let session = db
+        .get_session(&params.session)?
+        .ok_or_else(|| session_not_found(&params.session))?;
+
+    // listed in the transaction, so that it conflicts with an upload into the session
+    let txn = db.transaction();
+    let artifacts = txn.list_session_artifacts(&params.session)?;
+    let paths: Vec<String> = artifacts.iter().map(|a| a.path.clone()).collect();
+    let mut new_events = Vec::new();
+
+    new_events.extend(txn.create_repo_if_not_exists(
+        time,
+        database::CreateRepositoryParams {
+            server: &session.server,
+            owner: &session.owner,
+            repo: &session.repo,
+        },
+    )?);
+
+    new_events.extend(txn.create_commit_if_not_exists(
+        time,
+        database::CreateCommitParams {
+            commit: &session.commit,
+            server: &session.server,
+            owner: &session.owner,
+            repo: &session.repo,
+            metadata: &session.metadata,
+        },
+    )?);
+
+    for artifact in &artifacts {
+        new_events.push(txn.create_artifact(
+            time,
+            database::CreateArtifactParams {
+                server: &session.server,
+                owner: &session.owner,
+                repo: &session.repo,
+                commit: &session.commit,
+                path: &artifact.path,
+                labels: &artifact.labels,
+            },
+        )?);
+    }
+
+    txn.delete_session(&params.session, &paths)?;
+
+    let staging_dir = session_dir(base_dir, &params.session);
+    let dir = format!(
+        "{}/{}/{}/{}/{}",
+        base_dir, session.server, session.owner, session.repo, session.commit
+    );
+    for path in &paths {
+        fs::create_dir_all(Path::new(&dir).join(path).parent().unwrap())?;
+    }
+
+    // files are only moved once committed, so a conflict leaves the session as it was,
+    // and no file is moved over one that isn't part of this commit
+    txn.commit()?;
+    for path in &paths {
+        fs::rename(
+            Path::new(&staging_dir).join(path),
+            Path::new(&dir).join(path),
+        )?;
+    }
+    events.publish(db, new_events);
+    fs::remove_dir_all(staging_dir).or_else(ignore_not_found)?;
+    Ok(())
+}
+
+/// Discard a session and everything uploaded into it.
+pub async fn abort_session(
+    base_dir: &String,
+    db: &database::Database,
+    params: SessionParams,
+) -> Result<(), HandleRequestError> {
+    if db.get_session(&params.session)?.is_none() {
+        return Err(session_not_found(&params.session));
+    }
+    discard_session(base_dir, db, &params.session)
+}
+
+fn discard_session(
+    base_dir: &String,
+    db: &database::Database,
+    id: &String,
+) -> Result<(), HandleRequestError> {
+    let txn = db.transaction();
+    let paths: Vec<String> = txn
+        .list_session_artifacts(id)?
+        .into_iter()
+        .map(|a| a.path)
+        .collect();
+    txn.delete_session(id, &paths)?;
+    txn.commit()?;
+
+    fs::remove_dir_all(session_dir(base_dir, id)).or_else(ignore_not_found)?;
+    Ok(())
+}
+
+/// How often sessions are checked for expiry.
+const SESSION_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+const SESSION_EXPIRY_BATCH_SIZE: usize = 100;
+
+/// Discard sessions open for longer than `ttl` until the process exits.
+pub async fn run_session_expiry(db: Arc<database::Database>, base_dir: String, ttl: Duration) {
+    loop {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        if let Err(e) = expire_sessions(&base_dir, &db, now.saturating_sub(ttl.as_nanos())) {
+            warn!("failed to expire upload sessions: {e}");
+        }
+        tokio::time::sleep(SESSION_EXPIRY_INTERVAL).await;
+    }
+}
+
+/// Discard a batch of sessions created before `before`, with everything uploaded into them.
+/// Returns how many were discarded.
+pub fn expire_sessions(
+    base_dir: &String,
+    db: &database::Database,
+    before: u128,
+) -> Result<usize, HandleRequestError> {
+    let mut expired = 0;
+    for id in db.list_expired_sessions(before, SESSION_EXPIRY_BATCH_SIZE)? {
+        // fails if the session was published or aborted meanwhile
+        match discard_session(base_dir, db, &id) {
+            Ok(()) => {
+                info!("upload session {id} expired");
+                expired += 1;
+            }
+            Err(e) => warn!("failed to expire upload session {id}: {e}"),
+        }
+    }
+    Ok(expired)
+}
+
+fn ignore_not_found(e: std::io::Error) -> Result<(), std::io::Error> {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => Ok(()),
+        _ => Err(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RefParams {
+    server: String,
+    owner: String,
+    repo: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetRefRequest {
+    commit: String,
+}
+
+pub async fn set_ref(
+    db: &database::Database,
+    params: RefParams,
+    request: SetRefRequest,
+) -> Result<(), HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    let exists = db.exists_commit(database::ExistsCommitParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        commit: &request.commit,
+    })?;
+    if !exists {
+        return Err(HandleRequestError::NotFound(format!(
+            "commit {} not found",
+            request.commit
+        )));
+    }
+
+    let txn = db.transaction();
+    txn.set_ref(
+        time,
+        database::SetRefParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            name: &params.name,
+            commit: &request.commit,
+        },
+    )?;
+    txn.commit()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRefHistoryResponse {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    pub name: String,
+    pub history: Vec<database::RefHistoryData>,
+    pub next_cursor: Option<String>,
+}
+
+pub async fn list_ref_history(
+    db: &database::Database,
+    params: RefParams,
+    query: PaginationQuery,
+) -> Result<ListRefHistoryResponse, HandleRequestError> {
+    let page = db.list_ref_history(database::ListRefHistoryParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        name: &params.name,
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
+    })?;
+
+    Ok(ListRefHistoryResponse {
+        server: params.server,
+        owner: params.owner,
+        repo: params.repo,
+        name: params.name,
+        history: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+/// Query string for listings that only support pagination.
+#[derive(Deserialize)]
+pub struct PaginationQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+/// How many items a page of a listing has if `limit` isn't given.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// The most items a page of a listing can have, larger limits are lowered to it.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// The page size of a listing asked for `limit` items.
+fn page_size(limit: Option<usize>) -> Option<usize> {
+    Some(limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE))
+}
+
+#[derive(Deserialize)]
+pub struct ListRefsParams {
+    server: String,
+    owner: String,
+    repo: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRefsResponse {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    pub refs: Vec<database::RefData>,
+    pub next_cursor: Option<String>,
+}
+
+pub async fn list_refs(
+    db: &database::Database,
+    params: ListRefsParams,
+    query: PaginationQuery,
+) -> Result<ListRefsResponse, HandleRequestError> {
+    let page = db.list_refs(database::ListRefsParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
+    })?;
+
+    Ok(ListRefsResponse {
+        server: params.server,
+        owner: params.owner,
+        repo: params.repo,
+        refs: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ChannelParams {
+    server: String,
+    owner: String,
+    repo: String,
+    channel: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromoteRequest {
+    /// A commit hash or commit expression, e.g. `@channel:beta`.
+    commit: String,
+    /// Who the client says is promoting, kept apart from the authenticated identity.
+    claimed_actor: Option<String>,
+    reason: Option<String>,
+    /// Also seal the commit, e.g. when promoting it to a release.
+    #[serde(default)]
+    seal: bool,
+}
+
+/// Point a release channel at a successful commit, recording who promoted it and why.
+pub async fn promote(
+    db: &database::Database,
+    principal: &auth::Principal,
+    params: ChannelParams,
+    request: PromoteRequest,
+) -> Result<(), HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    let valid_name = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if params.channel.is_empty() || !params.channel.chars().all(valid_name) {
+        return Err(HandleRequestError::BadRequest(format!(
+            "invalid channel name {}",
+            params.channel
+        )));
+    }
+
+    let commit = get_or_verify_commit(
+        db,
+        GetOrVerifyCommitParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            commit: &request.commit,
+        },
+    )?;
+    let data = db
+        .get_commit(database::GetCommitParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            commit: &commit,
+        })?
+        .ok_or_else(|| HandleRequestError::NotFound(format!("commit {commit} not found")))?;
+    if data.status != database::CommitStatus::Success {
+        return Err(HandleRequestError::Conflict(format!(
+            "commit {commit} is {}, only successful commits can be promoted",
+            data.status
+        )));
+    }
+
+    let identity = principal.identity().map(|identity| identity.0);
+    let txn = db.transaction();
+    txn.promote(
+        time,
+        database::PromoteParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            channel: &params.channel,
+            commit: &commit,
+            actor: identity.as_ref(),
+            claimed_actor: request.claimed_actor.as_ref(),
+            reason: request.reason.as_ref(),
+        },
+    )?;
+    if request.seal {
+        txn.seal_commit(
+            time,
+            database::SealCommitParams {
+                server: &params.server,
+                owner: &params.owner,
+                repo: &params.repo,
+                commit: &commit,
+            },
+        )?;
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListChannelsResponse {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    pub channels: Vec<database::ChannelData>,
+    pub next_cursor: Option<String>,
+}
+
+pub async fn list_channels(
+    db: &database::Database,
+    params: ListRefsParams,
+    query: PaginationQuery,
+) -> Result<ListChannelsResponse, HandleRequestError> {
+    let page = db.list_channels(database::ListChannelsParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
+    })?;
+
+    Ok(ListChannelsResponse {
+        server: params.server,
+        owner: params.owner,
+        repo: params.repo,
+        channels: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListPromotionsResponse {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+    pub channel: String,
+    pub promotions: Vec<database::PromotionData>,
+    pub next_cursor: Option<String>,
+}
+
+pub async fn list_promotions(
+    db: &database::Database,
+    params: ChannelParams,
+    query: PaginationQuery,
+) -> Result<ListPromotionsResponse, HandleRequestError> {
+    let page = db.list_promotions(database::ListPromotionsParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        channel: &params.channel,
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
+    })?;
+
+    Ok(ListPromotionsResponse {
+        server: params.server,
+        owner: params.owner,
+        repo: params.repo,
+        channel: params.channel,
+        promotions: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct RepoParams {
+    pub server: String,
+    pub owner: String,
+    pub repo: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateWebhookRequest {
+    url: String,
+    secret: Option<String>,
+    #[serde(default)]
+    events: Vec<database::EventKind>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWebhookResponse {
+    pub id: String,
+    pub secret: String,
+}
+
+/// Register a webhook for all repositories, or only for the repository in `params`.
+/// A secret is generated unless one is given.
+pub async fn create_webhook(
+    db: &database::Database,
+    params: Option<RepoParams>,
+    request: CreateWebhookRequest,
+) -> Result<CreateWebhookResponse, HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    let url = reqwest::Url::parse(&request.url)
+        .map_err(|e| HandleRequestError::BadRequest(format!("invalid url: {e}")))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(HandleRequestError::BadRequest(format!(
+            "invalid url: unsupported scheme {}",
+            url.scheme()
+        )));
+    }
+    let secret = match request.secret {
+        Some(secret) if secret.is_empty() => {
+            return Err(HandleRequestError::BadRequest(
+                "secret must not be empty".to_string(),
+            ));
+        }
+        Some(secret) => secret,
+        None => uuid::Uuid::new_v4().simple().to_string(),
+    };
+    let id = uuid::Uuid::new_v4().simple().to_string();
+
+    let txn = db.transaction();
+    txn.create_webhook(
+        time,
+        database::CreateWebhookParams {
+            id: &id,
+            url: &request.url,
+            secret: &secret,
+            events: &request.events,
+            scope: params.as_ref().map(|params| database::RepoScope {
+                server: &params.server,
+                owner: &params.owner,
+                repo: &params.repo,
+            }),
+        },
+    )?;
+    txn.commit()?;
+
+    Ok(CreateWebhookResponse { id, secret })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListWebhooksResponse {
+    pub webhooks: Vec<database::WebhookData>,
+    pub next_cursor: Option<String>,
+}
+
+/// List all webhooks, or only those registered for the repository in `params`.
+pub async fn list_webhooks(
+    db: &database::Database,
+    params: Option<RepoParams>,
+    query: PaginationQuery,
+) -> Result<ListWebhooksResponse, HandleRequestError> {
+    let page = db.list_webhooks(database::ListWebhooksParams {
+        scope: params.as_ref().map(|params| database::RepoScope {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+        }),
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
+    })?;
+    Ok(ListWebhooksResponse {
+        webhooks: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct WebhookParams {
+    id: String,
+}
+
+pub async fn delete_webhook(
+    db: &database::Database,
+    params: WebhookParams,
+) -> Result<(), HandleRequestError> {
+    if db.get_webhook(&params.id)?.is_none() {
+        return Err(HandleRequestError::NotFound(format!(
+            "webhook {} not found",
+            params.id
+        )));
+    }
+
+    let txn = db.transaction();
+    txn.delete_webhook(&params.id)?;
+    txn.commit()?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    name: String,
+    scopes: Vec<database::TokenScope>,
+    server: Option<String>,
+    owner: Option<String>,
+    repo: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTokenResponse {
+    pub id: String,
+    /// The token itself, which can't be retrieved again.
+    pub token: String,
+}
+
+/// Create a token with the given scopes, optionally restricted to a server, owner or repository.
+pub async fn create_token(
+    db: &database::Database,
+    request: CreateTokenRequest,
+) -> Result<CreateTokenResponse, HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    if request.name.is_empty() {
+        return Err(HandleRequestError::BadRequest(
+            "name must not be empty".to_string(),
+        ));
+    }
+    if request.scopes.is_empty() {
+        return Err(HandleRequestError::BadRequest(
+            "scopes must not be empty".to_string(),
+        ));
+    }
+    if (request.owner.is_some() && request.server.is_none())
+        || (request.repo.is_some() && request.owner.is_none())
+    {
+        return Err(HandleRequestError::BadRequest(
+            "owner requires server, and repo requires owner".to_string(),
+        ));
+    }
+
+    let (id, token) = auth::generate_token();
+    let txn = db.transaction();
+    txn.create_token(
+        time,
+        database::CreateTokenParams {
+            id: &id,
+            name: &request.name,
+            hash: &auth::hash_token(&token),
+            scopes: &request.scopes,
+            server: request.server.as_ref(),
+            owner: request.owner.as_ref(),
+            repo: request.repo.as_ref(),
+        },
+    )?;
+    txn.commit()?;
+
+    Ok(CreateTokenResponse { id, token })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListTokensResponse {
+    pub tokens: Vec<database::TokenData>,
+    pub next_cursor: Option<String>,
+}
+
+pub async fn list_tokens(
+    db: &database::Database,
+    query: PaginationQuery,
+) -> Result<ListTokensResponse, HandleRequestError> {
+    let page = db.list_tokens(database::Pagination {
+        limit: page_size(query.limit),
+        cursor: query.cursor.as_ref(),
+    })?;
+    Ok(ListTokensResponse {
+        tokens: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct TokenParams {
+    id: String,
+}
+
+/// Revoke a token. Requests using it are rejected from then on.
+pub async fn delete_token(
+    db: &database::Database,
+    params: TokenParams,
+) -> Result<(), HandleRequestError> {
+    if db.get_token(&params.id)?.is_none() {
+        return Err(HandleRequestError::NotFound(format!(
+            "token {} not found",
+            params.id
+        )));
+    }
+
+    let txn = db.transaction();
+    txn.delete_token(&params.id)?;
+    txn.commit()?;
+    Ok(())
+}
+
+/// Path parameters of an ACL, attached to a server, an owner or a repository.
+#[derive(Deserialize)]
+pub struct AclParams {
+    server: String,
+    owner: Option<String>,
+    repo: Option<String>,
+}
+
+impl AclParams {
+    fn target(&self) -> database::AclTarget<'_> {
+        database::AclTarget {
+            server: &self.server,
+            owner: self.owner.as_ref(),
+            repo: self.repo.as_ref(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetAclRequest {
+    entries: Vec<database::AclEntry>,
+}
+
+pub async fn get_acl(
+    db: &database::Database,
+    params: AclParams,
+) -> Result<database::AclData, HandleRequestError> {
+    db.get_acl(params.target())?
+        .ok_or_else(|| HandleRequestError::NotFound("acl not found".to_string()))
+}
+
+/// Attach an ACL, replacing the one already attached to the same server, owner or repository.
+pub async fn set_acl(
+    db: &database::Database,
+    params: AclParams,
+    request: SetAclRequest,
+) -> Result<(), HandleRequestError> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+
+    if request
+        .entries
+        .iter()
+        .any(|entry| entry.principal.is_empty())
+    {
+        return Err(HandleRequestError::BadRequest(
+            "principal must not be empty".to_string(),
+        ));
+    }
+
+    let txn = db.transaction();
+    txn.set_acl(time, params.target(), &request.entries)?;
+    txn.commit()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListAclsResponse {
+    pub acls: Vec<database::AclData>,
+    pub next_cursor: Option<String>,
+}
+
+pub async fn list_acls(
+    db: &database::Database,
+    query: PaginationQuery,
+) -> Result<ListAclsResponse, HandleRequestError> {
+    let page = db.list_acls(database::Pagination {
+        limit: page_size(query.limit),
+        cursor: query.cursor.as_ref(),
+    })?;
+    Ok(ListAclsResponse {
+        acls: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+/// Remove an ACL. The ACL of the owner or server above applies from then on, if any.
+pub async fn delete_acl(
+    db: &database::Database,
+    params: AclParams,
+) -> Result<(), HandleRequestError> {
+    if db.get_acl(params.target())?.is_none() {
+        return Err(HandleRequestError::NotFound("acl not found".to_string()));
+    }
+
+    let txn = db.transaction();
+    txn.delete_acl(params.target())?;
+    txn.commit()?;
+    Ok(())
+}
+
+/// How long a pre-signed URL is valid for if not given, an hour.
+const DEFAULT_PRESIGN_EXPIRES_IN: u64 = 60 * 60;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignRequest {
+    /// `GET` to download, or `PUT` to upload.
+    method: String,
+    /// The path of the artifact, `/{server}/{owner}/{repo}/{commit}/{path}`.
+    path: String,
+    /// In seconds, default to an hour.
+    expires_in: Option<u64>,
+}
+
+impl PresignRequest {
+    pub fn method(&self) -> Result<Method, HandleRequestError> {
+        match self.method.as_str() {
+            "GET" => Ok(Method::GET),
+            "PUT" => Ok(Method::PUT),
+            method => Err(HandleRequestError::BadRequest(format!(
+                "method must be GET or PUT, not {method}"
+            ))),
+        }
+    }
+
+    /// The repository and commit of the artifact.
+    pub fn target(&self) -> Result<auth::Target, HandleRequestError> {
+        let invalid = || {
+            HandleRequestError::BadRequest(
+                "path must be /{server}/{owner}/{repo}/{commit}/{path}".to_string(),
+            )
+        };
+        if self.path.contains(['?', '#']) {
+            return Err(invalid());
+        }
+        presign::target(&self.path).ok_or_else(invalid)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignResponse {
+    /// The path and query of the pre-signed URL.
+    pub url: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires: OffsetDateTime,
+}
+
+/// Sign a URL for an artifact, which can be used without other credentials until it expires.
+pub async fn presign(
+    secret: Option<&String>,
+    request: PresignRequest,
+) -> Result<PresignResponse, HandleRequestError> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let secret = secret.ok_or_else(|| {
+        HandleRequestError::BadRequest("pre-signed urls are not enabled".to_string())
+    })?;
+    let method = request.method()?;
+    request.target()?;
+    let expires_in = request.expires_in.unwrap_or(DEFAULT_PRESIGN_EXPIRES_IN);
+    if expires_in == 0 || expires_in > presign::MAX_EXPIRES_IN {
+        return Err(HandleRequestError::BadRequest(format!(
+            "expiresIn must be between 1 and {} seconds",
+            presign::MAX_EXPIRES_IN
+        )));
+    }
+
+    let expires = now + expires_in;
+    let signature = presign::sign(secret, &method, &request.path, expires);
+    Ok(PresignResponse {
+        url: format!("{}?expires={expires}&signature={signature}", request.path),
+        expires: OffsetDateTime::from_unix_timestamp(expires as i64)
+            .map_err(|e| HandleRequestError::Generic(e.to_string()))?,
+    })
+}
+
+/// A mutating request, as recorded in the audit log.
+pub struct AuditEntry {
+    pub request_id: String,
+    pub action: String,
+    pub method: String,
+    pub uri: String,
+    pub status: u16,
+    pub client: Option<String>,
+    pub identity: Option<String>,
+    pub params: BTreeMap<String, String>,
+}
+
+pub fn record_audit(
+    db: &database::Database,
+    time: u128,
+    entry: &AuditEntry,
+) -> Result<(), HandleRequestError> {
+    let txn = db.transaction();
+    txn.append_audit(
+        time,
+        database::AppendAuditParams {
+            request_id: &entry.request_id,
+            action: &entry.action,
+            method: &entry.method,
+            uri: &entry.uri,
+            status: entry.status,
+            client: entry.client.as_ref(),
+            identity: entry.identity.as_ref(),
+            params: &entry.params,
+        },
+    )?;
+    txn.commit()?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ListAuditQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    since: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    until: Option<OffsetDateTime>,
+    action: Option<String>,
+    identity: Option<String>,
+    server: Option<String>,
+    owner: Option<String>,
+    repo: Option<String>,
+    commit: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListAuditResponse {
+    pub entries: Vec<database::AuditData>,
+    pub next_cursor: Option<String>,
+}
+
+/// List audit log entries, newest first.
+pub async fn list_audit(
+    db: &database::Database,
+    query: ListAuditQuery,
+) -> Result<ListAuditResponse, HandleRequestError> {
+    let params = [
+        ("server", query.server),
+        ("owner", query.owner),
+        ("repo", query.repo),
+        ("commit", query.commit),
+        ("path", query.path),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name.to_string(), value?)))
+    .collect();
+
+    let page = db.list_audit(database::ListAuditParams {
+        since: query.since,
+        until: query.until,
+        action: query.action.as_ref(),
+        identity: query.identity.as_ref(),
+        params,
+        pagination: database::Pagination {
+            limit: page_size(query.limit),
+            cursor: query.cursor.as_ref(),
+        },
+    })?;
+    Ok(ListAuditResponse {
+        entries: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+/// Parse the `Last-Event-ID` header of a reconnecting event stream.
+pub fn last_event_id(headers: &HeaderMap) -> Result<Option<u64>, HandleRequestError> {
+    match header_value(headers, "last-event-id")? {
+        Some(id) => id
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| HandleRequestError::BadRequest(format!("invalid Last-Event-ID: {id}"))),
+        None => Ok(None),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DownloadParams {
+    server: String,
+    owner: String,
+    repo: String,
+    commit: String,
+    path: String,
+}
+
+pub async fn prepare_download_file(
+    data_dir: &String,
+    db: &database::Database,
+    params: DownloadParams,
+) -> Result<(String, Body), HandleRequestError> {
+    let commit = get_or_verify_commit(
+        db,
+        GetOrVerifyCommitParams {
+            server: &params.server,
+            owner: &params.owner,
+            repo: &params.repo,
+            commit: &params.commit,
+        },
+    )?;
+
+    let exists = db.exists_artifact(database::ExistsArtifactParams {
+        server: &params.server,
+        owner: &params.owner,
+        repo: &params.repo,
+        commit: &commit,
+        path: &params.path,
+    })?;
+    if !exists {
+        return Err(HandleRequestError::NotFound(format!(
+            "file {} not found",
+            params.path
+        )));
+    }
+
+    let path = format!(
+        "{}/{}/{}/{}/{}/{}",
+        data_dir, params.server, params.owner, params.repo, commit, params.path
+    );
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(_) => {
             return Err(HandleRequestError::NotFound(format!(
                 "file {} not found",
                 params.path
@@ -237,32 +1782,186 @@ pub struct GetOrVerifyCommitParams<'a> {
     pub commit: &'a String,
 }
 
-/// Get the latest commit if `commit` is "@latest", otherwise verify that `commit` exists.
+/// How many steps back `~N` may go, as each of them is walked.
+const MAX_SKIP: usize = 1000;
+
+/// A symbolic commit, e.g. `@latest~1`, `@latest:any`, `@channel:stable`, `@before:2024-01-01T00:00:00Z`, `@label:release=true`
+/// or `@ref:main~2`.
+enum CommitExpression<'a> {
+    /// The newest successful commit, optionally added before a time or carrying labels,
+    /// skipping `skip` newer ones. With `any_status`, pending and failed commits count too.
+    Latest {
+        before: Option<OffsetDateTime>,
+        labels: database::Labels,
+        any_status: bool,
+        skip: usize,
+    },
+    /// The commit a ref pointed at `skip` changes ago, trying each ref name in order.
+    Ref { names: Vec<String>, skip: usize },
+    /// The commit promoted into a channel `skip` promotions ago.
+    Channel { name: &'a str, skip: usize },
+    /// A plain commit hash.
+    Commit(&'a String),
+}
+
+fn parse_commit_expression(commit: &String) -> Result<CommitExpression<'_>, HandleRequestError> {
+    let invalid = || HandleRequestError::BadRequest(format!("invalid commit expression {commit}"));
+    if !commit.starts_with('@') {
+        return Ok(CommitExpression::Commit(commit));
+    }
+
+    let (base, skip) = match commit.rsplit_once('~') {
+        Some((base, skip)) => match skip.parse::<usize>() {
+            Ok(skip) if skip <= MAX_SKIP => (base, skip),
+            _ => return Err(invalid()),
+        },
+        None => (commit.as_str(), 0),
+    };
+    if base == "@latest" || base == "@latest:any" {
+        return Ok(CommitExpression::Latest {
+            before: None,
+            labels: database::Labels::new(),
+            any_status: base == "@latest:any",
+            skip,
+        });
+    }
+    if let Some(before) = base.strip_prefix("@before:") {
+        let before = OffsetDateTime::parse(before, &Rfc3339).map_err(|_| invalid())?;
+        return Ok(CommitExpression::Latest {
+            before: Some(before),
+            labels: database::Labels::new(),
+            any_status: false,
+            skip,
+        });
+    }
+    if let Some(labels) = base.strip_prefix("@label:") {
+        let labels = parse_labels(labels)?;
+        if labels.is_empty() {
+            return Err(invalid());
+        }
+        return Ok(CommitExpression::Latest {
+            before: None,
+            labels,
+            any_status: false,
+            skip,
+        });
+    }
+    if let Some(name) = base.strip_prefix("@channel:") {
+        return Ok(CommitExpression::Channel { name, skip });
+    }
+    if let Some(names) = ref_candidates(base) {
+        return Ok(CommitExpression::Ref { names, skip });
+    }
+    Ok(CommitExpression::Commit(commit))
+}
+
+/// Resolve a commit expression (see [`CommitExpression`]) to a commit hash,
+/// or verify that a plain `commit` exists.
 fn get_or_verify_commit(
     db: &database::Database,
     params: GetOrVerifyCommitParams<'_>,
 ) -> Result<String, HandleRequestError> {
-    let is_latest = params.commit == "@latest";
-    if is_latest {
-        let commit = db.get_latest_commit(database::GetLatestCommitParams {
-            server: params.server,
-            owner: params.owner,
-            repo: params.repo,
-        })?;
-        return Ok(commit);
+    let not_found = || HandleRequestError::NotFound(format!("commit {} not found", params.commit));
+
+    match parse_commit_expression(params.commit)? {
+        CommitExpression::Latest {
+            before,
+            labels,
+            any_status,
+            skip,
+        } => {
+            let commit = db.get_latest_commit(database::GetLatestCommitParams {
+                server: params.server,
+                owner: params.owner,
+                repo: params.repo,
+                before,
+                skip,
+                labels: &labels,
+                any_status,
+            })?;
+            commit.ok_or_else(not_found)
+        }
+        CommitExpression::Ref { names, skip } => {
+            for name in &names {
+                let commit = db.get_ref(database::GetRefParams {
+                    server: params.server,
+                    owner: params.owner,
+                    repo: params.repo,
+                    name,
+                })?;
+                if commit.is_none() {
+                    continue;
+                }
+                if skip == 0 {
+                    return commit.ok_or_else(not_found);
+                }
+
+                let history = db.list_ref_history(database::ListRefHistoryParams {
+                    server: params.server,
+                    owner: params.owner,
+                    repo: params.repo,
+                    name,
+                    pagination: database::Pagination {
+                        limit: Some(skip + 1),
+                        cursor: None,
+                    },
+                })?;
+                let commit = history.items.into_iter().nth(skip).map(|item| item.commit);
+                return commit.ok_or_else(not_found);
+            }
+            Err(not_found())
+        }
+        CommitExpression::Channel { name, skip } => {
+            let name = name.to_string();
+            if skip == 0 {
+                let commit = db.get_channel(database::GetChannelParams {
+                    server: params.server,
+                    owner: params.owner,
+                    repo: params.repo,
+                    channel: &name,
+                })?;
+                return commit.ok_or_else(not_found);
+            }
+
+            let promotions = db.list_promotions(database::ListPromotionsParams {
+                server: params.server,
+                owner: params.owner,
+                repo: params.repo,
+                channel: &name,
+                pagination: database::Pagination {
+                    limit: Some(skip + 1),
+                    cursor: None,
+                },
+            })?;
+            let commit = promotions.items.into_iter().nth(skip).map(|p| p.commit);
+            commit.ok_or_else(not_found)
+        }
+        CommitExpression::Commit(commit) => {
+            let exists = db.exists_commit(database::ExistsCommitParams {
+                server: params.server,
+                owner: params.owner,
+                repo: params.repo,
+                commit,
+            })?;
+            if !exists {
+                return Err(not_found());
+            }
+            Ok(commit.clone())
+        }
     }
+}
 
-    let exists = db.exists_commit(database::ExistsCommitParams {
-        server: params.server,
-        owner: params.owner,
-        repo: params.repo,
-        commit: params.commit,
-    })?;
-    if !exists {
-        return Err(HandleRequestError::NotFound(format!(
-            "commit {} not found",
-            params.commit
-        )));
+/// Returns the ref names to look up, in order, for "@ref:<name>" and "@tag:<name>".
+fn ref_candidates(commit: &str) -> Option<Vec<String>> {
+    if let Some(name) = commit.strip_prefix("@ref:") {
+        Some(vec![
+            name.to_string(),
+            format!("refs/heads/{name}"),
+            format!("refs/tags/{name}"),
+        ])
+    } else {
+        commit
+            .strip_prefix("@tag:")
+            .map(|name| vec![format!("refs/tags/{name}"), name.to_string()])
     }
-    Ok(params.commit.clone())
 }
@@ -0,0 +1,328 @@
+use std::{
+    io,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    signal::unix::{SignalKind, signal},
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        self, RootCertStore, ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+    server::TlsStream,
+};
+use tracing::{info, warn};
+
+use crate::config::TlsConfig;
+
+/// How often the certificate files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+/// Connections that don't finish the handshake in time are closed.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts TLS connections with the current certificates. Reloading them only affects
+/// new connections, the ones already established keep going.
+pub struct Reloader {
+    config: TlsConfig,
+    server_config: RwLock<Arc<ServerConfig>>,
+}
+
+impl Reloader {
+    pub fn new(config: TlsConfig) -> Result<Self, String> {
+        let server_config = server_config(&config)?;
+        Ok(Reloader {
+            config,
+            server_config: RwLock::new(Arc::new(server_config)),
+        })
+    }
+
+    /// Read the certificates again. If they are invalid, the current ones are kept.
+    pub fn reload(&self) -> Result<(), String> {
+        let server_config = server_config(&self.config)?;
+        *self.server_config.write().unwrap() = Arc::new(server_config);
+        Ok(())
+    }
+
+    pub async fn accept<I>(&self, socket: I) -> io::Result<TlsStream<I>>
+    where
+        I: AsyncRead + AsyncWrite + Unpin,
+    {
+        let acceptor = TlsAcceptor::from(Arc::clone(&self.server_config.read().unwrap()));
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "tls handshake timed out"))?
+    }
+
+    /// Reload the certificates on SIGHUP, or when any of their files changes.
+    pub async fn watch(self: Arc<Self>) {
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to install SIGHUP handler");
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        let mut modified = self.modified();
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => info!("SIGHUP received, reloading certificates"),
+                _ = interval.tick() => {
+                    if self.modified() == modified {
+                        continue;
+                    }
+                    info!("certificate files changed, reloading certificates");
+                }
+            }
+            modified = self.modified();
+            match self.reload() {
+                Ok(()) => info!("certificates reloaded"),
+                Err(e) => warn!("failed to reload certificates, keeping the current ones: {e}"),
+            }
+        }
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [
+            Some(&self.config.cert_path),
+            Some(&self.config.key_path),
+            self.config.client_ca_path.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+    }
+}
+
+fn read_certs(path: &String) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to read certificates from {path}: {e}"))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates in {path}"));
+    }
+    Ok(certs)
+}
+
+fn server_config(config: &TlsConfig) -> Result<ServerConfig, String> {
+    let certs = read_certs(&config.cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)
+        .map_err(|e| format!("failed to read private key from {}: {e}", config.key_path))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("invalid client ca in {path}: {e}"))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if config.client_auth_optional {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid certificate or private key: {e}"))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+        KeyUsagePurpose,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_rustls::{
+        TlsConnector,
+        rustls::{ClientConfig, pki_types::ServerName},
+    };
+
+    struct Ca {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl Ca {
+        fn generate() -> Self {
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.self_signed(&key).unwrap();
+            Ca { cert, key }
+        }
+
+        /// Issue a certificate for `localhost`, returning it and its key in PEM.
+        fn issue(&self, purpose: ExtendedKeyUsagePurpose) -> (String, String) {
+            let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+    }
+
+    fn write_files(path: &str, ca: &Ca, client_ca: Option<&Ca>) -> TlsConfig {
+        std::fs::create_dir_all(path).unwrap();
+        let (cert, key) = ca.issue(ExtendedKeyUsagePurpose::ServerAuth);
+        std::fs::write(format!("{path}/cert.pem"), cert).unwrap();
+        std::fs::write(format!("{path}/key.pem"), key).unwrap();
+        if let Some(client_ca) = client_ca {
+            std::fs::write(format!("{path}/ca.pem"), client_ca.cert.pem()).unwrap();
+        }
+        TlsConfig {
+            cert_path: format!("{path}/cert.pem"),
+            key_path: format!("{path}/key.pem"),
+            client_ca_path: client_ca.map(|_| format!("{path}/ca.pem")),
+            client_auth_optional: false,
+        }
+    }
+
+    /// Accept connections on a local port, answering `ping` with `pong`.
+    async fn start_server(reloader: Arc<Reloader>) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let reloader = Arc::clone(&reloader);
+                tokio::spawn(async move {
+                    let Ok(mut stream) = reloader.accept(socket).await else {
+                        return;
+                    };
+                    let mut buf = [0; 4];
+                    while stream.read_exact(&mut buf).await.is_ok() {
+                        if stream.write_all(b"pong").await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    type ClientStream = tokio_rustls::client::TlsStream<TcpStream>;
+
+    async fn connect(
+        addr: std::net::SocketAddr,
+        ca: &Ca,
+        client_cert: Option<(String, String)>,
+    ) -> io::Result<ClientStream> {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.cert.der().clone()).unwrap();
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let mut config = match client_cert {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+                    PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let socket = TcpStream::connect(addr).await?;
+        let server_name = ServerName::try_from("localhost").unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(server_name, socket)
+            .await
+    }
+
+    async fn ping(stream: &mut ClientStream) -> io::Result<()> {
+        stream.write_all(b"ping").await?;
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"pong");
+        Ok(())
+    }
+
+    fn peer_certificate(stream: &ClientStream) -> Vec<u8> {
+        stream.get_ref().1.peer_certificates().unwrap()[0].to_vec()
+    }
+
+    #[tokio::test]
+    async fn reload_certificates() {
+        let path = "data/test_tls_reload";
+        let ca = Ca::generate();
+        let config = write_files(path, &ca, None);
+        let reloader = Arc::new(Reloader::new(config).unwrap());
+        let addr = start_server(Arc::clone(&reloader)).await;
+
+        let mut before = connect(addr, &ca, None).await.unwrap();
+        ping(&mut before).await.unwrap();
+        let first = peer_certificate(&before);
+        assert_eq!(before.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        // invalid files are rejected, keeping the current certificates
+        std::fs::write(format!("{path}/key.pem"), "not a key").unwrap();
+        assert!(reloader.reload().is_err());
+        let mut stream = connect(addr, &ca, None).await.unwrap();
+        ping(&mut stream).await.unwrap();
+        assert_eq!(peer_certificate(&stream), first);
+
+        write_files(path, &ca, None);
+        reloader.reload().unwrap();
+        let mut after = connect(addr, &ca, None).await.unwrap();
+        ping(&mut after).await.unwrap();
+        assert_ne!(peer_certificate(&after), first);
+        // connections established before keep going
+        ping(&mut before).await.unwrap();
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_certificates() {
+        let path = "data/test_tls_client_certificates";
+        let ca = Ca::generate();
+        let client_ca = Ca::generate();
+        let config = write_files(path, &ca, Some(&client_ca));
+        let addr = start_server(Arc::new(Reloader::new(config.clone()).unwrap())).await;
+
+        let client_cert = client_ca.issue(ExtendedKeyUsagePurpose::ClientAuth);
+        let mut stream = connect(addr, &ca, Some(client_cert)).await.unwrap();
+        ping(&mut stream).await.unwrap();
+
+        // with TLS 1.3, the client may only learn about the rejection after the handshake
+        let untrusted_cert = Ca::generate().issue(ExtendedKeyUsagePurpose::ClientAuth);
+        for client_cert in [None, Some(untrusted_cert)] {
+            let result = match connect(addr, &ca, client_cert).await {
+                Ok(mut stream) => ping(&mut stream).await,
+                Err(e) => Err(e),
+            };
+            assert!(result.is_err());
+        }
+
+        let optional = TlsConfig {
+            client_auth_optional: true,
+            ..config
+        };
+        let addr = start_server(Arc::new(Reloader::new(optional).unwrap())).await;
+        let mut stream = connect(addr, &ca, None).await.unwrap();
+        ping(&mut stream).await.unwrap();
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
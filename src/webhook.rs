@@ -0,0 +1,327 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::database;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const BATCH_SIZE: usize = 100;
+/// Deliveries are dropped after this many failed attempts.
+const MAX_ATTEMPTS: u32 = 10;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Deliver queued events to webhooks until the process exits.
+/// The queue lives in the database, so deliveries pending at shutdown are sent after a restart.
+pub async fn run(db: Arc<database::Database>) {
+    let client = client();
+    loop {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        if let Err(e) = deliver_due(&db, &client, now).await {
+            warn!("failed to deliver webhooks: {e:?}");
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap()
+}
+
+/// Send every delivery due at `now`. Failed ones are retried with exponential backoff.
+pub async fn deliver_due(
+    db: &database::Database,
+    client: &reqwest::Client,
+    now: u128,
+) -> Result<(), database::Error> {
+    for delivery in db.list_due_deliveries(now, BATCH_SIZE)? {
+        let txn = db.transaction();
+        let webhook = match db.get_webhook(&delivery.webhook)? {
+            Some(webhook) => webhook,
+            None => {
+                debug!("dropping delivery {} of deleted webhook", delivery.id);
+                txn.remove_delivery(&delivery)?;
+                txn.commit()?;
+                continue;
+            }
+        };
+
+        match send(client, &webhook, &delivery).await {
+            Ok(()) => {
+                debug!("delivered {} to webhook {}", delivery.id, webhook.id);
+                txn.remove_delivery(&delivery)?;
+            }
+            Err(e) if delivery.attempts + 1 >= MAX_ATTEMPTS => {
+                warn!(
+                    "giving up delivery {} to webhook {} after {MAX_ATTEMPTS} attempts: {e}",
+                    delivery.id, webhook.id
+                );
+                txn.remove_delivery(&delivery)?;
+            }
+            Err(e) => {
+                debug!(
+                    "failed to deliver {} to webhook {}: {e}",
+                    delivery.id, webhook.id
+                );
+                let time_due = now + backoff(delivery.attempts).as_nanos();
+                txn.retry_delivery(&delivery, time_due)?;
+            }
+        }
+        txn.commit()?;
+    }
+    Ok(())
+}
+
+async fn send(
+    client: &reqwest::Client,
+    webhook: &database::WebhookData,
+    delivery: &database::DeliveryData,
+) -> Result<(), String> {
+    let response = client
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Artifact-Store-Event", delivery.event.to_string())
+        .header("X-Artifact-Store-Delivery", &delivery.id)
+        .header(
+            "X-Artifact-Store-Signature",
+            signature(&webhook.secret, delivery.payload.as_bytes()),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("unexpected status {}", response.status()));
+    }
+    Ok(())
+}
+
+/// The `sha256=` prefixed hex HMAC-SHA256 of the payload, keyed by the webhook secret.
+pub fn signature(secret: &str, payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// How long to wait before retrying a delivery that failed `attempts + 1` times.
+fn backoff(attempts: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts))
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, extract::State, http::HeaderMap, http::StatusCode, routing::post};
+    use tokio::sync::Mutex;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// Start a receiver on a local port that records requests and fails the first `failures` of them.
+    async fn start_receiver(failures: usize) -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    async move |State(received): State<Received>,
+                                headers: HeaderMap,
+                                body: String| {
+                        let mut received = received.lock().await;
+                        received.push((headers, body));
+                        if received.len() <= failures {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            StatusCode::OK
+                        }
+                    },
+                ),
+            )
+            .with_state(Arc::clone(&received));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/hook"), received)
+    }
+
+    fn create_webhook(
+        db: &database::Database,
+        id: &str,
+        url: &str,
+        events: &[database::EventKind],
+        scope: Option<database::RepoScope>,
+    ) {
+        let txn = db.transaction();
+        txn.create_webhook(
+            1,
+            database::CreateWebhookParams {
+                id: &id.to_string(),
+                url: &url.to_string(),
+                secret: &"secret".to_string(),
+                events,
+                scope,
+            },
+        )
+        .unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[tokio::test]
+    async fn deliver_events() {
+        let path = "data/test_webhook_deliver_events";
+        let _ = std::fs::remove_dir_all(path);
+        let db = database::Database::new_rocksdb(path).unwrap();
+        let (url, received) = start_receiver(1).await;
+
+        let server = "github.com".to_string();
+        let owner = "owner".to_string();
+        let repo = "repo".to_string();
+        let other_repo = "other-repo".to_string();
+        create_webhook(&db, "global", &url, &[], None);
+        create_webhook(
+            &db,
+            "artifacts",
+            &url,
+            &[database::EventKind::ArtifactCreated],
+            Some(database::RepoScope {
+                server: &server,
+                owner: &owner,
+                repo: &repo,
+            }),
+        );
+        create_webhook(
+            &db,
+            "other",
+            &url,
+            &[],
+            Some(database::RepoScope {
+                server: &server,
+                owner: &owner,
+                repo: &other_repo,
+            }),
+        );
+
+        let txn = db.transaction();
+        txn.create_commit_if_not_exists(
+            100,
+            database::CreateCommitParams {
+                commit: &"commit-1".to_string(),
+                server: &server,
+                owner: &owner,
+                repo: &repo,
+                metadata: &database::CommitMetadata::default(),
+            },
+        )
+        .unwrap();
+        txn.create_artifact(
+            100,
+            database::CreateArtifactParams {
+                server: &server,
+                owner: &owner,
+                repo: &repo,
+                commit: &"commit-1".to_string(),
+                path: &"a.txt".to_string(),
+                labels: &database::Labels::new(),
+            },
+        )
+        .unwrap();
+        txn.commit().unwrap();
+
+        // commit.created for "global", artifact.created for "global" and "artifacts"
+        let deliveries = db.list_due_deliveries(100, BATCH_SIZE).unwrap();
+        assert_eq!(deliveries.len(), 3);
+        assert_eq!(db.list_due_deliveries(99, BATCH_SIZE).unwrap().len(), 0);
+
+        // the first request fails and is retried later
+        let client = client();
+        deliver_due(&db, &client, 200).await.unwrap();
+        assert_eq!(received.lock().await.len(), 3);
+        assert_eq!(db.list_due_deliveries(200, BATCH_SIZE).unwrap().len(), 0);
+        let retries = db.list_due_deliveries(u128::MAX, BATCH_SIZE).unwrap();
+        assert_eq!(retries.len(), 1);
+        assert_eq!(retries[0].attempts, 1);
+        assert_eq!(retries[0].time_due, 200 + INITIAL_BACKOFF.as_nanos());
+
+        deliver_due(&db, &client, u128::MAX / 2).await.unwrap();
+        assert_eq!(
+            db.list_due_deliveries(u128::MAX, BATCH_SIZE).unwrap().len(),
+            0
+        );
+
+        let received = received.lock().await;
+        assert_eq!(received.len(), 4);
+        for (headers, body) in received.iter() {
+            assert_eq!(
+                headers["X-Artifact-Store-Signature"],
+                signature("secret", body.as_bytes())
+            );
+            let event: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(event["repo"], "repo");
+            assert_eq!(
+                event["type"],
+                headers["X-Artifact-Store-Event"].to_str().unwrap()
+            );
+        }
+        // the retry is the same payload as the failed attempt
+        assert_eq!(received[0].1, received[3].1);
+        assert_eq!(
+            received[0].0["X-Artifact-Store-Delivery"],
+            received[3].0["X-Artifact-Store-Delivery"]
+        );
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn drop_deliveries_of_deleted_webhook() {
+        let path = "data/test_webhook_deleted";
+        let _ = std::fs::remove_dir_all(path);
+        let db = database::Database::new_rocksdb(path).unwrap();
+        let (url, received) = start_receiver(0).await;
+        create_webhook(&db, "global", &url, &[], None);
+
+        let txn = db.transaction();
+        txn.create_repo_if_not_exists(
+            100,
+            database::CreateRepositoryParams {
+                server: &"github.com".to_string(),
+                owner: &"owner".to_string(),
+                repo: &"repo".to_string(),
+            },
+        )
+        .unwrap();
+        txn.delete_webhook(&"global".to_string()).unwrap();
+        txn.commit().unwrap();
+
+        deliver_due(&db, &client(), 100).await.unwrap();
+        assert_eq!(
+            db.list_due_deliveries(u128::MAX, BATCH_SIZE).unwrap().len(),
+            0
+        );
+        assert_eq!(received.lock().await.len(), 0);
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), Duration::from_secs(5));
+        assert_eq!(backoff(1), Duration::from_secs(10));
+        assert_eq!(backoff(3), Duration::from_secs(40));
+        assert_eq!(backoff(20), MAX_BACKOFF);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+}